@@ -0,0 +1,44 @@
+//! Making and rejecting a candidate layout, the way the annealing loop does,
+//! by copying the accepted layout or by undoing the swaps in place.
+//!
+//! Run with `cargo +nightly bench`.
+
+#![feature(test)]
+
+extern crate keygen;
+extern crate rand;
+extern crate rand_chacha;
+extern crate test;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use test::Bencher;
+
+use keygen::layout::INIT_LAYOUT;
+use keygen::layout::LAYOUT_MASK;
+
+const SWAPS: usize = 3;
+
+#[bench]
+fn reject_by_cloning(b: &mut Bencher)
+{
+	let accepted = INIT_LAYOUT.clone();
+	let mut rng = ChaCha12Rng::seed_from_u64(0);
+	b.iter(|| {
+		let mut candidate = accepted.clone();
+		candidate.shuffle_masked(SWAPS, &LAYOUT_MASK, &mut rng);
+		test::black_box(&candidate);
+	});
+}
+
+#[bench]
+fn reject_by_undoing(b: &mut Bencher)
+{
+	let mut accepted = INIT_LAYOUT.clone();
+	let mut rng = ChaCha12Rng::seed_from_u64(0);
+	b.iter(|| {
+		let swaps = accepted.shuffle_masked(SWAPS, &LAYOUT_MASK, &mut rng);
+		test::black_box(&accepted);
+		accepted.undo_swaps(&swaps);
+	});
+}
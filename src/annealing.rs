@@ -1,11 +1,15 @@
-/// Stochaistic optimisation based on simulated annealing.
-/// Math is shamelessly taken from: http://mkweb.bcgsc.ca/carpalx/?simulated_annealing
-/// This code is written to be generic and can be reused for other applications.
+//! Stochaistic optimisation based on simulated annealing.
+//! Math is shamelessly taken from: http://mkweb.bcgsc.ca/carpalx/?simulated_annealing
+//! This code is written to be generic and can be reused for other applications.
 
 extern crate rand;
 
+use std::collections::VecDeque;
+use std::error::Error;
 use std::f64;
+use std::fmt;
 use std::ops::Range;
+use std::str::FromStr;
 use self::rand::thread_rng;
 use self::rand::Rng;
 
@@ -17,6 +21,102 @@ const P0: f64   = 1.0;
 const N:  usize = 15000;
 const KN: f64   = K / (N as f64);
 
+// How hard `AcceptanceTracker` pulls the temperature towards its target: the
+// temperature changes by a factor of exp(ADAPT_GAIN (target - rate)) per move.
+const ADAPT_GAIN:   f64   = 0.1;
+const ADAPT_WINDOW: usize = 100;
+
+// How the temperature falls from `start_temp` to `end_temp` over a run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoolingSchedule
+{
+	// By the same amount every iteration.
+	Linear,
+	// By the same factor every iteration, as Carpalx does.
+	Exponential,
+	// By the same factor once every 1% of the run, holding in between.
+	Geometric,
+}
+
+// A string that does not name a `CoolingSchedule`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnknownSchedule(pub String);
+
+// An acceptance rate to hold, falling exponentially from `start_rate` to
+// `end_rate` over a run, measured over the last `window` moves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveTarget
+{
+	pub start_rate: f64,
+	pub end_rate:   f64,
+	pub window:     usize,
+}
+
+// A string that is not an `AdaptiveTarget`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TargetError(pub String);
+
+// Decides on the moves of one run and keeps count of which were accepted.
+// With an `AdaptiveTarget` the temperature starts at `start_temp` and is
+// nudged after every move, once the window is full, up when too few moves
+// were accepted and down when too many were; otherwise it follows the
+// schedule.
+#[derive(Clone, Debug)]
+pub struct AcceptanceTracker
+{
+	params:      AnnealingParams,
+	recent:      VecDeque<bool>,
+	accepted:    usize,
+	temperature: f64,
+}
+
+// The last `capacity` accepted swaps, as pairs of items, each of which may
+// not be made again until `tenure` iterations after it was accepted, so that
+// a run does not keep undoing and redoing the same few moves.
+#[derive(Clone, Debug)]
+pub struct TabuList
+{
+	recent:   VecDeque<((usize, usize), usize)>,
+	capacity: usize,
+	tenure:   usize,
+}
+
+// Everything that shapes an annealing run. The default is the Carpalx
+// schedule above: T0 falling exponentially to T0 exp(-K) over N iterations.
+// With `iters_per_temp` above 1 the temperature is held for stages of that
+// many iterations, at the schedule's value where the stage starts.
+// With `adaptive` set, the temperature follows the acceptance rate instead
+// and only `start_temp` is used. `cycle_rate` is the share of moves that
+// rotate three items instead of swapping two, which is none by default.
+// `tabu` is the capacity of the run's `TabuList`, which is off at 0.
+// `patience`, unless it is 0, stops a run early once that many moves in a row
+// have not found a layout better than any before, or, with `adaptive` set,
+// once the temperature has fallen below `end_temp`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnnealingParams
+{
+	pub start_temp:     f64,
+	pub end_temp:       f64,
+	pub iterations:     usize,
+	pub iters_per_temp: usize,
+	pub schedule:       CoolingSchedule,
+	pub adaptive:       Option<AdaptiveTarget>,
+	pub cycle_rate:     f64,
+	pub tabu:           usize,
+	pub tabu_tenure:    usize,
+	pub patience:       usize,
+}
+
+// Why a run stopped before its last iteration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EarlyStop
+{
+	// `patience` moves went by without a new best layout.
+	Patience(usize),
+	// The adaptive temperature fell below `end_temp`.
+	TemperatureFloor,
+}
+
 // T(i) = T0 exp(-ik/N)
 fn temperature(i: usize)
 -> f64
@@ -35,12 +135,35 @@ fn cutoff_p(de: f64, i: usize)
 // For positive dE, accept if r < p_dE where r ~ Uniform(0, 1)
 pub fn accept_transition(de: f64, i: usize)
 -> bool
+{
+	accept_transition_seeded(de, i, &mut thread_rng())
+}
+
+// Same as `accept_transition`, but draws r from `rng`.
+pub fn accept_transition_seeded(de: f64, i: usize, rng: &mut impl Rng)
+-> bool
 {
 	if de < 0.0 {
 		true
 	} else {
 		let p_de = cutoff_p(de, i);
-		let r = thread_rng().next_f64();
+		let r = rng.gen::<f64>();
+		r < p_de
+	}
+}
+
+// For positive dE, accept with probability p0 exp(-dE/T). At zero
+// temperature only improvements are accepted.
+pub fn accept_at(de: f64, t: f64, rng: &mut impl Rng)
+-> bool
+{
+	if de < 0.0 {
+		true
+	} else if t.is_nan() || t <= 0.0 {
+		false
+	} else {
+		let p_de = P0 * f64::exp(-de / t);
+		let r = rng.gen::<f64>();
 		r < p_de
 	}
 }
@@ -50,3 +173,419 @@ pub fn get_simulation_range()
 {
 	1..(N+1)
 }
+
+impl AnnealingParams
+{
+	// `stages` stages of `iters_per_temp` iterations, each cooler than the
+	// last by `factor`.
+	pub fn geometric(start_temp: f64, factor: f64, iters_per_temp: usize, stages: usize)
+	-> AnnealingParams
+	{
+		AnnealingParams {
+			start_temp,
+			end_temp: start_temp * factor.powi(stages as i32),
+			iterations: iters_per_temp * stages,
+			iters_per_temp,
+			..AnnealingParams::default()
+		}
+	}
+
+	pub fn linear(start_temp: f64, end_temp: f64, iterations: usize)
+	-> AnnealingParams
+	{
+		AnnealingParams {
+			start_temp,
+			end_temp,
+			iterations,
+			schedule: CoolingSchedule::Linear,
+			..AnnealingParams::default()
+		}
+	}
+
+	// The default schedule, squeezed or stretched to `iterations`.
+	pub fn with_budget(iterations: usize)
+	-> AnnealingParams
+	{
+		AnnealingParams { iterations, ..AnnealingParams::default() }
+	}
+
+	// T(i) for iteration i of `iterations`, counting from 1.
+	pub fn temperature(&self, i: usize)
+	-> f64
+	{
+		let stage = self.iters_per_temp.max(1);
+		let i = if stage > 1 { i.saturating_sub(1) / stage * stage } else { i };
+		let t = i as f64 / self.iterations.max(1) as f64;
+		match self.schedule {
+			// Cooling by a factor from zero stays at zero.
+			_ if self.start_temp <= 0.0 && self.schedule != CoolingSchedule::Linear => 0.0,
+			CoolingSchedule::Linear =>
+				self.start_temp + (self.end_temp - self.start_temp) * t,
+			CoolingSchedule::Exponential =>
+				self.start_temp * (self.end_temp / self.start_temp).powf(t),
+			CoolingSchedule::Geometric =>
+				self.start_temp * (self.end_temp / self.start_temp).powf((t * 100.0).floor() / 100.0),
+		}
+	}
+
+	// Same as `accept_transition_seeded`, at this schedule's T(i). At zero
+	// temperature only improvements are accepted.
+	pub fn accept_transition(&self, de: f64, i: usize, rng: &mut impl Rng)
+	-> bool
+	{
+		accept_at(de, self.temperature(i), rng)
+	}
+
+	pub fn simulation_range(&self)
+	-> Range<usize>
+	{
+		1..(self.iterations+1)
+	}
+
+	// Whether a run should stop now, `since_best` moves after it last found
+	// a new best layout and with its temperature at `temperature`.
+	pub fn early_stop(&self, since_best: usize, temperature: f64)
+	-> Option<EarlyStop>
+	{
+		if self.patience == 0 {
+			None
+		} else if since_best >= self.patience {
+			Some(EarlyStop::Patience(self.patience))
+		} else if self.adaptive.is_some() && temperature < self.end_temp {
+			Some(EarlyStop::TemperatureFloor)
+		} else {
+			None
+		}
+	}
+
+	// How many iterations a stage of the run lasts, for reporting progress:
+	// `iters_per_temp`, or 1% of the run if the temperature changes every
+	// iteration.
+	pub fn stage_len(&self)
+	-> usize
+	{
+		if self.iters_per_temp > 1 {
+			self.iters_per_temp
+		} else {
+			(self.iterations / 100).max(1)
+		}
+	}
+}
+
+impl AcceptanceTracker
+{
+	pub fn new(params: &AnnealingParams)
+	-> AcceptanceTracker
+	{
+		AcceptanceTracker {
+			params: *params,
+			recent: VecDeque::new(),
+			accepted: 0,
+			temperature: params.temperature(1),
+		}
+	}
+
+	// A tracker that carries on from one saved with `recent` and
+	// `temperature`, for resuming a run.
+	pub fn resume(params: &AnnealingParams, recent: &[bool], temperature: f64)
+	-> AcceptanceTracker
+	{
+		AcceptanceTracker {
+			params: *params,
+			recent: recent.iter().cloned().collect(),
+			accepted: recent.iter().filter(|&&a| a).count(),
+			temperature,
+		}
+	}
+
+	// Whether to accept a move that changes the energy by `de` at iteration
+	// `i`, which is recorded.
+	pub fn accept(&mut self, de: f64, i: usize, rng: &mut impl Rng)
+	-> bool
+	{
+		if self.params.adaptive.is_none() {
+			self.temperature = self.params.temperature(i);
+		}
+		let accepted = accept_at(de, self.temperature, rng);
+		self.record(accepted, i);
+		accepted
+	}
+
+	// Counts a move made at iteration `i`, and with an `AdaptiveTarget`
+	// adjusts the temperature for the next one.
+	pub fn record(&mut self, accepted: bool, i: usize)
+	{
+		let window = match self.params.adaptive {
+			Some(target) => target.window.max(1),
+			None => ADAPT_WINDOW,
+		};
+		self.recent.push_back(accepted);
+		if accepted {
+			self.accepted += 1;
+		}
+		if self.recent.len() > window && self.recent.pop_front() == Some(true) {
+			self.accepted -= 1;
+		}
+
+		if self.params.adaptive.is_some() && self.recent.len() == window {
+			let error = self.target_rate(i) - self.rate();
+			self.temperature *= f64::exp(ADAPT_GAIN * error);
+		}
+	}
+
+	// The temperature the next move will be judged at, or the last one was
+	// if the temperature follows the schedule.
+	pub fn temperature(&self)
+	-> f64
+	{
+		self.temperature
+	}
+
+	// Whether each move in the window was accepted, oldest first.
+	pub fn recent(&self)
+	-> impl Iterator<Item = bool> + '_
+	{
+		self.recent.iter().cloned()
+	}
+
+	// The share of the moves in the window that were accepted, or 0 before
+	// the first move.
+	pub fn rate(&self)
+	-> f64
+	{
+		self.accepted as f64 / self.recent.len().max(1) as f64
+	}
+
+	// The acceptance rate to aim for at iteration `i`, or NaN without an
+	// `AdaptiveTarget`.
+	pub fn target_rate(&self, i: usize)
+	-> f64
+	{
+		match self.params.adaptive {
+			Some(target) => {
+				let t = i as f64 / self.params.iterations.max(1) as f64;
+				target.start_rate * (target.end_rate / target.start_rate).powf(t)
+			},
+			None => f64::NAN,
+		}
+	}
+}
+
+impl TabuList
+{
+	pub fn new(capacity: usize, tenure: usize)
+	-> TabuList
+	{
+		TabuList { recent: VecDeque::with_capacity(capacity), capacity, tenure }
+	}
+
+	// An empty list sized by `params`.
+	pub fn for_params(params: &AnnealingParams)
+	-> TabuList
+	{
+		TabuList::new(params.tabu, params.tabu_tenure)
+	}
+
+	// A list sized by `params` that holds `entries`, as `entries` returned
+	// them, for resuming a run.
+	pub fn resume(params: &AnnealingParams, entries: &[((usize, usize), usize)])
+	-> TabuList
+	{
+		let mut tabu = TabuList::for_params(params);
+		let skip = entries.len().saturating_sub(tabu.capacity);
+		tabu.recent.extend(entries[skip..].iter().cloned());
+		tabu
+	}
+
+	// The swaps held and the iterations they were accepted at, oldest first.
+	pub fn entries(&self)
+	-> impl Iterator<Item = ((usize, usize), usize)> + '_
+	{
+		self.recent.iter().cloned()
+	}
+
+	// Remembers `swaps`, accepted at iteration `i`, forgetting the oldest
+	// swaps once the list is full.
+	pub fn record(&mut self, swaps: &[(usize, usize)], i: usize)
+	{
+		if self.capacity == 0 {
+			return;
+		}
+		for &(a, b) in swaps {
+			if self.recent.len() == self.capacity {
+				self.recent.pop_front();
+			}
+			self.recent.push_back(((a.min(b), a.max(b)), i));
+		}
+	}
+
+	// Whether any of `swaps` is a swap the list holds that was accepted no
+	// more than `tenure` iterations before iteration `i`.
+	pub fn forbids(&self, swaps: &[(usize, usize)], i: usize)
+	-> bool
+	{
+		swaps.iter().any(|&(a, b)| {
+			let pair = (a.min(b), a.max(b));
+			self.recent.iter().any(|&(p, at)| p == pair && i.saturating_sub(at) <= self.tenure)
+		})
+	}
+}
+
+impl Default for AnnealingParams
+{
+	fn default()
+	-> AnnealingParams
+	{
+		AnnealingParams {
+			start_temp: T0,
+			end_temp: T0 * f64::exp(-K),
+			iterations: N,
+			iters_per_temp: 1,
+			schedule: CoolingSchedule::Exponential,
+			adaptive: None,
+			cycle_rate: 0.0,
+			tabu: 0,
+			tabu_tenure: 100,
+			patience: 0,
+		}
+	}
+}
+
+impl FromStr for CoolingSchedule
+{
+	type Err = UnknownSchedule;
+
+	fn from_str(s: &str)
+	-> Result<CoolingSchedule, UnknownSchedule>
+	{
+		match &s.to_lowercase()[..] {
+			"linear" => Ok(CoolingSchedule::Linear),
+			"exponential" => Ok(CoolingSchedule::Exponential),
+			"geometric" => Ok(CoolingSchedule::Geometric),
+			_ => Err(UnknownSchedule(s.to_string())),
+		}
+	}
+}
+
+// START:END, such as "0.8:0.02", with the default window.
+impl FromStr for AdaptiveTarget
+{
+	type Err = TargetError;
+
+	fn from_str(s: &str)
+	-> Result<AdaptiveTarget, TargetError>
+	{
+		let err = || TargetError(s.to_string());
+		let mut rates = s.splitn(2, ':').map(|r| r.trim().parse::<f64>());
+		let (start_rate, end_rate) = match (rates.next(), rates.next()) {
+			(Some(Ok(a)), Some(Ok(b))) => (a, b),
+			_ => return Err(err()),
+		};
+		if [start_rate, end_rate].iter().any(|&r| r.is_nan() || r <= 0.0 || r > 1.0) {
+			return Err(err());
+		}
+		Ok(AdaptiveTarget { start_rate, end_rate, window: ADAPT_WINDOW })
+	}
+}
+
+impl fmt::Display for TargetError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		write!(f, "`{}` is not an acceptance target; expected START:END rates between 0 and 1", self.0)
+	}
+}
+
+impl Error for TargetError {}
+
+impl fmt::Display for UnknownSchedule
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		write!(f, "unknown cooling schedule `{}`", self.0)
+	}
+}
+
+impl Error for UnknownSchedule {}
+
+impl fmt::Display for EarlyStop
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		match *self {
+			EarlyStop::Patience(n) => write!(f, "no new best layout in {} moves", n),
+			EarlyStop::TemperatureFloor => write!(f, "the temperature fell below the end temperature"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::iter;
+
+	use super::*;
+
+	fn tracker(window: usize)
+	-> AcceptanceTracker
+	{
+		let target = AdaptiveTarget { start_rate: 0.5, end_rate: 0.5, window };
+		AcceptanceTracker::new(&AnnealingParams { adaptive: Some(target), ..AnnealingParams::default() })
+	}
+
+	// The temperature after each move of `stream`.
+	fn temperatures(mut tracker: AcceptanceTracker, stream: impl Iterator<Item = bool>)
+	-> Vec<f64>
+	{
+		stream.enumerate().map(|(i, accepted)| {
+			tracker.record(accepted, i + 1);
+			tracker.temperature()
+		}).collect()
+	}
+
+	#[test]
+	fn accepting_too_much_cools_and_too_little_heats()
+	{
+		let start = tracker(10).temperature();
+
+		let cooled = temperatures(tracker(10), iter::repeat_n(true, 100));
+		assert!(cooled[..9].iter().all(|&t| t == start));
+		assert!(cooled[9..].windows(2).all(|w| w[1] < w[0]));
+
+		let heated = temperatures(tracker(10), iter::repeat_n(false, 100));
+		assert!(heated[..9].iter().all(|&t| t == start));
+		assert!(heated[9..].windows(2).all(|w| w[1] > w[0]));
+	}
+
+	#[test]
+	fn accepting_at_the_target_rate_holds_the_temperature()
+	{
+		let start = tracker(10).temperature();
+		let held = temperatures(tracker(10), (0..1000).map(|i| i % 2 == 0));
+		assert!(held.iter().all(|&t| (t / start - 1.0).abs() < 0.06), "{:?}", held);
+	}
+
+	#[test]
+	fn a_shift_in_the_stream_turns_the_temperature_around()
+	{
+		let stream = iter::repeat_n(true, 200).chain(iter::repeat_n(false, 200));
+		let t = temperatures(tracker(20), stream);
+		assert!(t[199] < t[0]);
+		// The window still remembers accepts for a while after the shift.
+		assert!(t[399] > t[219]);
+	}
+
+	#[test]
+	fn without_a_target_the_schedule_sets_the_temperature()
+	{
+		let params = AnnealingParams::default();
+		let mut tracker = AcceptanceTracker::new(&params);
+		for i in 1..=500 {
+			tracker.record(true, i);
+		}
+		assert_eq!(tracker.temperature(), params.temperature(1));
+	}
+}
@@ -0,0 +1,216 @@
+//! Starting layouts put together in code, as a base layout with some keys
+//! moved and some kept in place.
+//!
+//! Steps are applied in the order `build` lists them, not the order they were
+//! called in: first positions are pinned, then characters are placed, then
+//! characters are pinned wherever they ended up.
+
+use std::error::Error;
+use std::fmt;
+
+use layout::Layout;
+use layout::LayoutError;
+use layout::LayoutShuffleMask;
+use layout::Transliteration;
+use layout::INIT_LAYOUT;
+use layout::LAYOUT_MASK;
+
+pub struct LayoutBuilder
+{
+	base:             Layout,
+	placements:       Vec<(char, usize)>,
+	pinned:           Vec<char>,
+	pinned_positions: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BuildError
+{
+	CharNotFound(char),
+	PositionOutOfRange(usize),
+	Locked(usize),
+	ConflictingPlacement { key: char, pos: usize },
+	InvalidLayout(Vec<LayoutError>),
+}
+
+impl LayoutBuilder
+{
+	// Starts from `INIT_LAYOUT`.
+	pub fn new()
+	-> LayoutBuilder
+	{
+		LayoutBuilder {
+			base: INIT_LAYOUT.clone(),
+			placements: Vec::new(),
+			pinned: Vec::new(),
+			pinned_positions: Vec::new(),
+		}
+	}
+
+	pub fn base(mut self, layout: &Layout)
+	-> LayoutBuilder
+	{
+		self.base = layout.clone();
+		self
+	}
+
+	// Moves `c` to `pos`, and whatever was at `pos` to where `c` was, on both
+	// layers.
+	pub fn place(mut self, c: char, pos: usize)
+	-> LayoutBuilder
+	{
+		self.placements.push((c, pos));
+		self
+	}
+
+	pub fn pin(mut self, c: char)
+	-> LayoutBuilder
+	{
+		self.pinned.push(c);
+		self
+	}
+
+	pub fn pin_position(mut self, pos: usize)
+	-> LayoutBuilder
+	{
+		self.pinned_positions.push(pos);
+		self
+	}
+
+	// The layout, along with a mask that keeps the pinned keys in place.
+	// Empty keys are pinned too, as they are in the command line tool.
+	pub fn build(&self)
+	-> Result<(Layout, LayoutShuffleMask), BuildError>
+	{
+		let mut layout = self.base.clone();
+		let mut mask = LAYOUT_MASK.clone();
+		for &pos in &self.pinned_positions {
+			if pos >= mask.0.len() {
+				return Err(BuildError::PositionOutOfRange(pos));
+			}
+			mask.pin(pos);
+		}
+
+		let mut placed = Vec::new();
+		for &(c, pos) in &self.placements {
+			if pos >= mask.0.len() {
+				return Err(BuildError::PositionOutOfRange(pos));
+			}
+			let from = find(&layout, c)?;
+			if from == pos {
+				if !placed.contains(&pos) {
+					placed.push(pos);
+				}
+				continue;
+			}
+			if placed.contains(&from) || placed.contains(&pos) {
+				return Err(BuildError::ConflictingPlacement { key: c, pos });
+			}
+			for &p in &[from, pos] {
+				if !mask.is_swappable(p) {
+					return Err(BuildError::Locked(p));
+				}
+			}
+			layout.swap_positions(from, pos);
+			placed.push(pos);
+		}
+
+		for &c in &self.pinned {
+			mask.pin(find(&layout, c)?);
+		}
+		for (pos, &c) in layout.lower().keys().iter().enumerate() {
+			if c == '\0' {
+				mask.pin(pos);
+			}
+		}
+
+		layout.validate_masked(&mask).map_err(BuildError::InvalidLayout)?;
+		Ok((layout, mask))
+	}
+}
+
+impl Default for LayoutBuilder
+{
+	fn default()
+	-> LayoutBuilder
+	{
+		LayoutBuilder::new()
+	}
+}
+
+fn find(layout: &Layout, c: char)
+-> Result<usize, BuildError>
+{
+	match *layout.get_position_map_with(&Transliteration::none()).get_key_position(c) {
+		Some(ref kp) => Ok(kp.pos),
+		None => Err(BuildError::CharNotFound(c)),
+	}
+}
+
+impl fmt::Display for BuildError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		match *self {
+			BuildError::CharNotFound(c) => write!(f, "`{}` is not on the layout", c),
+			BuildError::PositionOutOfRange(pos) => write!(f, "position {} is not on the keyboard", pos),
+			BuildError::Locked(pos) => write!(f, "position {} cannot be changed", pos),
+			BuildError::ConflictingPlacement { key, pos } =>
+				write!(f, "placing `{}` at position {} undoes an earlier placement", key, pos),
+			BuildError::InvalidLayout(ref errors) => {
+				write!(f, "invalid layout")?;
+				for e in errors {
+					write!(f, "; {}", e)?;
+				}
+				Ok(())
+			},
+		}
+	}
+}
+
+impl Error for BuildError {}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use layout::QWERTY_LAYOUT;
+
+	#[test]
+	fn placing_a_key_evicts_the_one_there()
+	{
+		let (layout, mask) = LayoutBuilder::new().base(&QWERTY_LAYOUT).place('e', 32).pin('z').build().unwrap();
+		assert_eq!(layout.position_of('e'), Some(32));
+		assert_eq!(layout.char_at(2, false), Ok('\0'));
+		assert!(!mask.is_swappable(2));
+		assert!(!mask.is_swappable(QWERTY_LAYOUT.position_of('z').unwrap()));
+		assert!(mask.is_swappable(32));
+	}
+
+	#[test]
+	fn conflicting_placements_are_refused()
+	{
+		let builder = LayoutBuilder::new().base(&QWERTY_LAYOUT).place('e', 32);
+		assert_eq!(builder.place('t', 32).build().unwrap_err(), BuildError::ConflictingPlacement { key: 't', pos: 32 });
+
+		// Moving a placed key away again is a conflict too.
+		let builder = LayoutBuilder::new().base(&QWERTY_LAYOUT).place('e', 32).place('e', 5);
+		assert_eq!(builder.build().unwrap_err(), BuildError::ConflictingPlacement { key: 'e', pos: 5 });
+
+		// Placing a key where it already is is not.
+		assert!(LayoutBuilder::new().base(&QWERTY_LAYOUT).place('q', 0).place('q', 0).build().is_ok());
+	}
+
+	#[test]
+	fn pinning_a_character_the_base_lacks_is_refused()
+	{
+		let builder = LayoutBuilder::new().base(&QWERTY_LAYOUT).pin('=');
+		assert_eq!(builder.build().unwrap_err(), BuildError::CharNotFound('='));
+		// No transliteration: `é` is missing even though `e` is there.
+		let builder = LayoutBuilder::new().base(&QWERTY_LAYOUT).pin('é');
+		assert_eq!(builder.build().unwrap_err(), BuildError::CharNotFound('é'));
+		assert_eq!(LayoutBuilder::new().place('#', 3).build().unwrap_err(), BuildError::CharNotFound('#'));
+		assert_eq!(LayoutBuilder::new().pin_position(34).build().unwrap_err(), BuildError::PositionOutOfRange(34));
+	}
+}
@@ -0,0 +1,337 @@
+//! Saving an annealing run part of the way through, so that a long run that
+//! gets interrupted can carry on where it stopped.
+//!
+//! A checkpoint holds everything `simulator::simulate_checkpointed` carries
+//! from one iteration to the next, including the position of the random
+//! number generator, so a resumed run makes the same moves the run would have
+//! made without stopping. It is written as JSON:
+//!
+//! ```json
+//! { "version": 2, "corpus": 1234, "iteration": 5000,
+//!   "layout": ["...", "..."], "penalty": 0.52, "best": [{ "layout": ["...", "..."], "penalty": 0.51 }],
+//!   "best_penalty": 0.51, "since_best": 40,
+//!   "temperature": 0.01, "acceptance": [true, false, ...], "tabu": [[3, 14, 4990], ...],
+//!   "stage": [50, 12, 3], "rng": { "seed": "...", "stream": 0, "word_pos": "1234" } }
+//! ```
+//!
+//! A layout is a list of its layers, lower first, each a string of its keys
+//! in position order, empty keys included as `\u0000`. Unlike the grid
+//! format, this holds any layout the optimizer can make, such as one with the
+//! space bar moved onto a letter key. The numbers that may not fit in a
+//! double are strings.
+
+extern crate rand;
+extern crate rand_chacha;
+extern crate serde_json;
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use self::rand::SeedableRng;
+use self::rand_chacha::ChaCha12Rng;
+use self::serde_json::json;
+use self::serde_json::Value;
+
+use layout::Layer;
+use layout::Layout;
+
+// The format `Checkpoint::to_json` writes. Checkpoints in any other version
+// are refused.
+pub const CHECKPOINT_VERSION: u64 = 2;
+
+#[derive(Clone, Debug)]
+pub struct Checkpoint
+{
+	// `corpus_hash` of the corpus the run was scoring against.
+	pub corpus_hash:  u64,
+	// The number of iterations done.
+	pub iteration:    usize,
+	// The accepted layout and its penalty.
+	pub layout:       Layout,
+	pub penalty:      f64,
+	// The best layouts so far, best first.
+	pub best:         Vec<(Layout, f64)>,
+	// The lowest penalty so far, the starting layout's included, and how
+	// many moves ago it was found.
+	pub best_penalty: f64,
+	pub since_best:   usize,
+	// The acceptance tracker's temperature and window of recent moves, oldest
+	// first.
+	pub temperature:  f64,
+	pub acceptance:   Vec<bool>,
+	// The tabu list's swaps and the iterations they were accepted at, oldest
+	// first.
+	pub tabu:         Vec<((usize, usize), usize)>,
+	// Moves tried, accepted and accepted though worse in the current stage.
+	pub stage:        (usize, usize, usize),
+	pub rng:          ChaCha12Rng,
+}
+
+// When and where a run saves its progress: after every `every` new best
+// layouts, unless `every` is 0, after `interval` has passed since the last
+// save, if set, and once the run is over.
+#[derive(Clone, Debug)]
+pub struct CheckpointPolicy
+{
+	pub path:        PathBuf,
+	pub every:       usize,
+	pub interval:    Option<Duration>,
+	pub corpus_hash: u64,
+}
+
+#[derive(Debug)]
+pub enum CheckpointError
+{
+	Io(io::Error),
+	Json(serde_json::Error),
+	UnsupportedVersion(u64),
+	Missing(&'static str),
+	InvalidLayout(String),
+	CorpusMismatch,
+}
+
+impl Checkpoint
+{
+	pub fn to_json(&self)
+	-> String
+	{
+		let best: Vec<Value> = self.best.iter()
+			.map(|&(ref layout, penalty)| json!({ "layout": layout_value(layout), "penalty": penalty }))
+			.collect();
+		let tabu: Vec<Value> = self.tabu.iter()
+			.map(|&((a, b), at)| json!([a, b, at]))
+			.collect();
+		let seed: String = self.rng.get_seed().iter().map(|b| format!("{:02x}", b)).collect();
+		let root = json!({
+			"version": CHECKPOINT_VERSION,
+			"corpus": self.corpus_hash,
+			"iteration": self.iteration,
+			"layout": layout_value(&self.layout),
+			"penalty": self.penalty,
+			"best": best,
+			"best_penalty": self.best_penalty,
+			"since_best": self.since_best,
+			"temperature": self.temperature,
+			"acceptance": self.acceptance,
+			"tabu": tabu,
+			"stage": [self.stage.0, self.stage.1, self.stage.2],
+			"rng": {
+				"seed": seed,
+				"stream": self.rng.get_stream(),
+				"word_pos": self.rng.get_word_pos().to_string(),
+			},
+		});
+		root.to_string()
+	}
+
+	pub fn from_json(s: &str)
+	-> Result<Checkpoint, CheckpointError>
+	{
+		let root: Value = serde_json::from_str(s).map_err(CheckpointError::Json)?;
+		let version = u64_field(&root, "version")?;
+		if version != CHECKPOINT_VERSION {
+			return Err(CheckpointError::UnsupportedVersion(version));
+		}
+
+		let mut best = Vec::new();
+		for entry in array_field(&root, "best")? {
+			best.push((layout_field(entry, "layout")?, f64_field(entry, "penalty")?));
+		}
+		let mut acceptance = Vec::new();
+		for accepted in array_field(&root, "acceptance")? {
+			acceptance.push(accepted.as_bool().ok_or(CheckpointError::Missing("acceptance"))?);
+		}
+		let mut tabu = Vec::new();
+		for entry in array_field(&root, "tabu")? {
+			let n = |i: usize| entry.get(i).and_then(Value::as_u64).ok_or(CheckpointError::Missing("tabu"));
+			tabu.push(((n(0)? as usize, n(1)? as usize), n(2)? as usize));
+		}
+		let stage = array_field(&root, "stage")?;
+		let n = |i: usize| stage.get(i).and_then(Value::as_u64).ok_or(CheckpointError::Missing("stage"));
+
+		Ok(Checkpoint {
+			corpus_hash: u64_field(&root, "corpus")?,
+			iteration: u64_field(&root, "iteration")? as usize,
+			layout: layout_field(&root, "layout")?,
+			penalty: f64_field(&root, "penalty")?,
+			best,
+			best_penalty: f64_field(&root, "best_penalty")?,
+			since_best: u64_field(&root, "since_best")? as usize,
+			temperature: f64_field(&root, "temperature")?,
+			acceptance,
+			tabu,
+			stage: (n(0)? as usize, n(1)? as usize, n(2)? as usize),
+			rng: rng_field(&root)?,
+		})
+	}
+
+	pub fn load(path: &Path)
+	-> Result<Checkpoint, CheckpointError>
+	{
+		let s = fs::read_to_string(path).map_err(CheckpointError::Io)?;
+		Checkpoint::from_json(&s)
+	}
+
+	// Writes to a file next to `path` first and moves it into place, so that
+	// being stopped while writing leaves the previous checkpoint as it was.
+	pub fn save(&self, path: &Path)
+	-> Result<(), CheckpointError>
+	{
+		let mut partial = path.as_os_str().to_owned();
+		partial.push(".partial");
+		fs::write(&partial, self.to_json()).map_err(CheckpointError::Io)?;
+		fs::rename(&partial, path).map_err(CheckpointError::Io)
+	}
+}
+
+impl CheckpointPolicy
+{
+	// The policy for the `run`th of a series of runs, counting from 0, so
+	// that each run keeps its own checkpoint: the first saves to `path`, and
+	// run `n` after it to `path` with `.n` put in before the extension.
+	pub fn for_run(&self, run: usize)
+	-> CheckpointPolicy
+	{
+		if run == 0 {
+			return self.clone();
+		}
+
+		let mut name = self.path.file_stem().unwrap_or_default().to_owned();
+		name.push(format!(".{}", run));
+		if let Some(ext) = self.path.extension() {
+			name.push(".");
+			name.push(ext);
+		}
+		CheckpointPolicy { path: self.path.with_file_name(name), ..self.clone() }
+	}
+}
+
+// A hash of the corpus text for telling corpora apart, which unlike the
+// standard library's hashers is the same on every build: 64-bit FNV-1a.
+pub fn corpus_hash(text: &str)
+-> u64
+{
+	text.bytes().fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn field<'a>(value: &'a Value, name: &'static str)
+-> Result<&'a Value, CheckpointError>
+{
+	value.get(name).ok_or(CheckpointError::Missing(name))
+}
+
+fn u64_field(value: &Value, name: &'static str)
+-> Result<u64, CheckpointError>
+{
+	field(value, name)?.as_u64().ok_or(CheckpointError::Missing(name))
+}
+
+fn f64_field(value: &Value, name: &'static str)
+-> Result<f64, CheckpointError>
+{
+	field(value, name)?.as_f64().ok_or(CheckpointError::Missing(name))
+}
+
+fn array_field<'a>(value: &'a Value, name: &'static str)
+-> Result<&'a Vec<Value>, CheckpointError>
+{
+	field(value, name)?.as_array().ok_or(CheckpointError::Missing(name))
+}
+
+fn layout_value(layout: &Layout)
+-> Value
+{
+	layout.layers().map(|layer| layer.keys().iter().collect::<String>()).collect()
+}
+
+fn layout_field(value: &Value, name: &'static str)
+-> Result<Layout, CheckpointError>
+{
+	let mut layers = Vec::new();
+	for (i, layer) in array_field(value, name)?.iter().enumerate() {
+		let keys: Vec<char> = layer.as_str().ok_or(CheckpointError::Missing(name))?.chars().collect();
+		let count = keys.len();
+		let keys = <[char; 34]>::try_from(keys).map_err(|_| {
+			CheckpointError::InvalidLayout(format!("layer {} has {} keys, expected 34", i + 1, count))
+		})?;
+		layers.push(Layer::from_keys(keys));
+	}
+	if layers.len() < 2 {
+		return Err(CheckpointError::InvalidLayout(format!("{} layers, expected at least 2", layers.len())));
+	}
+	let mut layers = layers.into_iter();
+	let (lower, upper) = (layers.next().unwrap(), layers.next().unwrap());
+	Ok(layers.fold(Layout::two_layer(lower, upper), Layout::with_layer))
+}
+
+fn rng_field(root: &Value)
+-> Result<ChaCha12Rng, CheckpointError>
+{
+	let rng = field(root, "rng")?;
+	let hex = field(rng, "seed")?.as_str().ok_or(CheckpointError::Missing("seed"))?;
+	let mut seed = [0u8; 32];
+	if hex.len() != 64 || !hex.is_ascii() {
+		return Err(CheckpointError::Missing("seed"));
+	}
+	for (i, b) in seed.iter_mut().enumerate() {
+		*b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| CheckpointError::Missing("seed"))?;
+	}
+	let word_pos = field(rng, "word_pos")?.as_str()
+		.and_then(|s| s.parse::<u128>().ok())
+		.ok_or(CheckpointError::Missing("word_pos"))?;
+
+	let mut restored = ChaCha12Rng::from_seed(seed);
+	restored.set_stream(u64_field(rng, "stream")?);
+	restored.set_word_pos(word_pos);
+	Ok(restored)
+}
+
+impl fmt::Display for CheckpointError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		match *self {
+			CheckpointError::Io(ref e) => write!(f, "{}", e),
+			CheckpointError::Json(ref e) => write!(f, "could not parse checkpoint: {}", e),
+			CheckpointError::UnsupportedVersion(v) =>
+				write!(f, "checkpoint version {} is not supported (expected {})", v, CHECKPOINT_VERSION),
+			CheckpointError::Missing(name) => write!(f, "the checkpoint has no valid `{}`", name),
+			CheckpointError::InvalidLayout(ref e) => write!(f, "invalid layout in checkpoint: {}", e),
+			CheckpointError::CorpusMismatch =>
+				write!(f, "the checkpoint was made with a different corpus"),
+		}
+	}
+}
+
+impl Error for CheckpointError {}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn each_run_after_the_first_gets_its_own_checkpoint()
+	{
+		let policy = CheckpointPolicy {
+			path:        PathBuf::from("runs/colemak.json"),
+			every:       100,
+			interval:    None,
+			corpus_hash: 7,
+		};
+		assert_eq!(policy.for_run(0).path, PathBuf::from("runs/colemak.json"));
+		assert_eq!(policy.for_run(1).path, PathBuf::from("runs/colemak.1.json"));
+		assert_eq!(policy.for_run(12).path, PathBuf::from("runs/colemak.12.json"));
+		assert_eq!(policy.for_run(2).corpus_hash, 7);
+
+		let bare = CheckpointPolicy { path: PathBuf::from("checkpoint"), ..policy };
+		assert_eq!(bare.for_run(3).path, PathBuf::from("checkpoint.3"));
+	}
+}
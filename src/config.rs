@@ -0,0 +1,252 @@
+//! Run configuration read from a TOML file: the starting layout and the keys
+//! that must stay where they are.
+//!
+//! ```toml
+//! [layout]
+//! lower = "jcyfkzl,uq=rsthdmnaio'/vgpbxw.;-e "
+//! upper = "JCYFKZL<UQ+RSTHDMNAIO\"?VGPBXW>:_E "
+//!
+//! [constraints]
+//! pinned = "zxcv"
+//! pinned_positions = [10]
+//! ```
+//!
+//! Layers are given in position order (see the KeyMap format in layout.rs).
+//! `upper` may be left out, in which case it is derived by shifting `lower`.
+
+extern crate toml;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use self::toml::Value;
+
+use layout::Layout;
+use layout::LayoutError;
+use layout::LayoutShuffleMask;
+use layout::Transliteration;
+use layout::LAYOUT_MASK;
+
+pub struct Config
+{
+	pub layout: Layout,
+	pub mask:   LayoutShuffleMask,
+}
+
+#[derive(Debug)]
+pub enum ConfigError
+{
+	Io(io::Error),
+	Toml(toml::de::Error),
+	Missing(&'static str),
+	WrongType(&'static str),
+	WrongKeyCount { key: &'static str, found: usize },
+	InvalidLayout(Vec<LayoutError>),
+	PinnedCharNotFound(char),
+	PinnedPositionOutOfRange(i64),
+}
+
+impl Config
+{
+	pub fn from_file(path: &Path)
+	-> Result<Config, ConfigError>
+	{
+		let mut s = String::new();
+		File::open(path)
+			.and_then(|mut f| f.read_to_string(&mut s))
+			.map_err(ConfigError::Io)?;
+		Config::from_str(&s)
+	}
+}
+
+impl FromStr for Config
+{
+	type Err = ConfigError;
+
+	fn from_str(s: &str)
+	-> Result<Config, ConfigError>
+	{
+		let root = s.parse::<toml::Table>().map_err(ConfigError::Toml)?;
+
+		let layout_table = match root.get("layout") {
+			Some(Value::Table(t)) => t,
+			Some(_) => return Err(ConfigError::WrongType("layout")),
+			None => return Err(ConfigError::Missing("layout")),
+		};
+		let lower = match layout_table.get("lower") {
+			Some(Value::String(s)) => s,
+			Some(_) => return Err(ConfigError::WrongType("layout.lower")),
+			None => return Err(ConfigError::Missing("layout.lower")),
+		};
+		let upper = match layout_table.get("upper") {
+			Some(Value::String(s)) => Some(s),
+			Some(_) => return Err(ConfigError::WrongType("layout.upper")),
+			None => None,
+		};
+
+		let lower = layer_keys(lower, "layout.lower")?;
+		let layout = match upper {
+			Some(upper) => Layout::from_keys(lower, layer_keys(upper, "layout.upper")?),
+			None => Layout::from_lower_keys(lower),
+		};
+		let mut mask = LAYOUT_MASK.clone();
+		let constraints = match root.get("constraints") {
+			Some(Value::Table(t)) => Some(t),
+			Some(_) => return Err(ConfigError::WrongType("constraints")),
+			None => None,
+		};
+		if let Some(constraints) = constraints {
+			match constraints.get("pinned") {
+				Some(Value::String(pinned)) => {
+					let map = layout.get_position_map_with(&Transliteration::none());
+					for c in pinned.chars() {
+						match *map.get_key_position(c) {
+							Some(ref kp) => mask.pin(kp.pos),
+							None => return Err(ConfigError::PinnedCharNotFound(c)),
+						}
+					}
+				},
+				Some(_) => return Err(ConfigError::WrongType("constraints.pinned")),
+				None => (),
+			}
+
+			match constraints.get("pinned_positions") {
+				Some(Value::Array(positions)) => {
+					for pos in positions {
+						match *pos {
+							Value::Integer(i) if i >= 0 && (i as usize) < 34 => mask.pin(i as usize),
+							Value::Integer(i) => return Err(ConfigError::PinnedPositionOutOfRange(i)),
+							_ => return Err(ConfigError::WrongType("constraints.pinned_positions")),
+						}
+					}
+				},
+				Some(_) => return Err(ConfigError::WrongType("constraints.pinned_positions")),
+				None => (),
+			}
+		}
+
+		layout.validate_masked(&mask).map_err(ConfigError::InvalidLayout)?;
+
+		Ok(Config { layout, mask })
+	}
+}
+
+impl fmt::Display for ConfigError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		match *self {
+			ConfigError::Io(ref e) => write!(f, "could not read config: {}", e),
+			ConfigError::Toml(ref e) => write!(f, "could not parse config: {}", e),
+			ConfigError::Missing(key) => write!(f, "`{}` is missing", key),
+			ConfigError::WrongType(key) => write!(f, "`{}` has the wrong type", key),
+			ConfigError::WrongKeyCount { key, found } =>
+				write!(f, "`{}` should have 34 keys, but has {}", key, found),
+			ConfigError::InvalidLayout(ref errors) => {
+				write!(f, "invalid layout")?;
+				for e in errors {
+					write!(f, "; {}", e)?;
+				}
+				Ok(())
+			},
+			ConfigError::PinnedCharNotFound(c) =>
+				write!(f, "cannot pin `{}`, which is not on the layout", c),
+			ConfigError::PinnedPositionOutOfRange(i) =>
+				write!(f, "cannot pin position {}, which is not on the keyboard", i),
+		}
+	}
+}
+
+impl Error for ConfigError {}
+
+fn layer_keys(s: &str, key: &'static str)
+-> Result<[char; 34], ConfigError>
+{
+	let chars: Vec<char> = s.chars().collect();
+	if chars.len() != 34 {
+		return Err(ConfigError::WrongKeyCount { key, found: chars.len() });
+	}
+
+	let mut keys = ['\0'; 34];
+	keys.copy_from_slice(&chars);
+	Ok(keys)
+}
+
+#[cfg(test)]
+mod tests
+{
+	extern crate rand_chacha;
+
+	use super::*;
+	use layout::QWERTY_LAYOUT;
+	use self::rand_chacha::rand_core::SeedableRng;
+	use self::rand_chacha::ChaCha12Rng;
+
+	// QWERTY, with the bottom-left ZXCV block held in place.
+	static ZXCV_CONFIG: &str = r#"
+		[layout]
+		lower = "qwertyuiop-asdfghjkl;'zxcvbnm,./\u0000 "
+
+		[constraints]
+		pinned = "zxcv"
+		pinned_positions = [10, 32]
+	"#;
+
+	fn config_error(s: &str)
+	-> String
+	{
+		match Config::from_str(s) {
+			Ok(_) => panic!("config should not load:{}", s),
+			Err(e) => e.to_string(),
+		}
+	}
+
+	#[test]
+	fn zxcv_block_is_pinned()
+	{
+		let config = Config::from_str(ZXCV_CONFIG).unwrap();
+		assert_eq!(config.layout, QWERTY_LAYOUT);
+
+		let pinned: Vec<usize> = (0..34).filter(|&i| !config.mask.0[i]).collect();
+		assert_eq!(pinned, vec![10, 22, 23, 24, 25, 32]);
+
+		let mut rng = ChaCha12Rng::seed_from_u64(0);
+		let mut layout = config.layout.clone();
+		for _ in 0..100 {
+			layout.shuffle_masked(3, &config.mask, &mut rng);
+			let block: String = (22..26).map(|i| layout.char_at(i, false).unwrap()).collect();
+			assert_eq!(block, "zxcv");
+		}
+		assert_ne!(layout, QWERTY_LAYOUT);
+	}
+
+	#[test]
+	fn invalid_configs_are_described()
+	{
+		assert_eq!(config_error("[constraints]\npinned = \"zxcv\""), "`layout` is missing");
+		assert_eq!(config_error("layout = 1"), "`layout` has the wrong type");
+		assert_eq!(
+			config_error(&ZXCV_CONFIG.replace("\\u0000 \"", "\\u0000\"")),
+			"`layout.lower` should have 34 keys, but has 33");
+		assert_eq!(
+			config_error(&ZXCV_CONFIG.replace("\"zxcv\"", "\"zx€\"")),
+			"cannot pin `€`, which is not on the layout");
+		assert_eq!(
+			config_error(&ZXCV_CONFIG.replace("\"zxcv\"", "\"zxé\"")),
+			"cannot pin `é`, which is not on the layout");
+		assert_eq!(
+			config_error(&ZXCV_CONFIG.replace("[10, 32]", "[10, 34]")),
+			"cannot pin position 34, which is not on the keyboard");
+		assert_eq!(
+			config_error(&ZXCV_CONFIG.replace("[10, 32]", "[10, \"32\"]")),
+			"`constraints.pinned_positions` has the wrong type");
+		assert!(config_error(&ZXCV_CONFIG.replace("[10, 32]", "[10]")).starts_with("invalid layout; "));
+		assert!(config_error("[layout").starts_with("could not parse config: "));
+	}
+}
@@ -0,0 +1,172 @@
+//! Character and bigram counts of a text, so that metrics over a large corpus
+//! do not have to walk the whole text for every layout.
+//!
+//! The metrics on `Layout` take anything that implements `Ngrams`, which is
+//! both a `Corpus` and a plain `str`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Corpus
+{
+	chars:   BTreeMap<char, u64>,
+	bigrams: BTreeMap<(char, char), u64>,
+	// Uppercase characters that `fold_case` counted as lowercase.
+	shifts:  u64,
+}
+
+// A source of weighted characters and adjacent character pairs.
+pub trait Ngrams
+{
+	fn each_char(&self, f: &mut dyn FnMut(char, u64));
+	fn each_bigram(&self, f: &mut dyn FnMut(char, char, u64));
+}
+
+impl Corpus
+{
+	pub fn from_file(path: &Path)
+	-> io::Result<Corpus>
+	{
+		fs::read_to_string(path).map(|s| Corpus::from_text(&s))
+	}
+
+	pub fn from_text(text: &str)
+	-> Corpus
+	{
+		let mut corpus = Corpus::default();
+		corpus.add(text, 1);
+		corpus
+	}
+
+	// Counts each string `weight` times, as when the input is a word list
+	// with frequencies. No bigram spans two strings.
+	pub fn from_weighted(entries: &[(String, u64)])
+	-> Corpus
+	{
+		let mut corpus = Corpus::default();
+		for &(ref text, weight) in entries {
+			corpus.add(text, weight);
+		}
+		corpus
+	}
+
+	pub fn char_count(&self, c: char)
+	-> u64
+	{
+		self.chars.get(&c).cloned().unwrap_or(0)
+	}
+
+	pub fn bigram_count(&self, a: char, b: char)
+	-> u64
+	{
+		self.bigrams.get(&(a, b)).cloned().unwrap_or(0)
+	}
+
+	// The number of characters in the corpus.
+	pub fn len(&self)
+	-> u64
+	{
+		self.chars.values().sum()
+	}
+
+	pub fn is_empty(&self)
+	-> bool
+	{
+		self.chars.is_empty()
+	}
+
+	// The same corpus with uppercase letters counted as their lowercase
+	// letters, so that `'A'` and `'a'` are one key to place. How many would
+	// have needed shift is kept in `shift_count`, for analysis to charge for
+	// separately. Characters that do not lowercase to a single character are
+	// left as they are.
+	pub fn fold_case(&self)
+	-> Corpus
+	{
+		let mut folded = Corpus { shifts: self.shifts, ..Corpus::default() };
+		for (&c, &n) in &self.chars {
+			if fold_char(c) != c {
+				folded.shifts += n;
+			}
+			*folded.chars.entry(fold_char(c)).or_insert(0) += n;
+		}
+		for (&(a, b), &n) in &self.bigrams {
+			*folded.bigrams.entry((fold_char(a), fold_char(b))).or_insert(0) += n;
+		}
+		folded
+	}
+
+	// How many characters `fold_case` lowercased, across every fold that
+	// made this corpus. 0 for a corpus that was never folded.
+	pub fn shift_count(&self)
+	-> u64
+	{
+		self.shifts
+	}
+
+	fn add(&mut self, text: &str, weight: u64)
+	{
+		if weight == 0 {
+			return;
+		}
+		text.each_char(&mut |c, n| *self.chars.entry(c).or_insert(0) += n * weight);
+		text.each_bigram(&mut |a, b, n| *self.bigrams.entry((a, b)).or_insert(0) += n * weight);
+	}
+}
+
+// `c` lowercased, if that is one character and `c` is uppercase.
+fn fold_char(c: char)
+-> char
+{
+	if !c.is_uppercase() {
+		return c;
+	}
+	let mut lower = c.to_lowercase();
+	match (lower.next(), lower.next()) {
+		(Some(l), None) => l,
+		_ => c,
+	}
+}
+
+impl Ngrams for Corpus
+{
+	fn each_char(&self, f: &mut dyn FnMut(char, u64))
+	{
+		for (&c, &n) in &self.chars {
+			f(c, n);
+		}
+	}
+
+	fn each_bigram(&self, f: &mut dyn FnMut(char, char, u64))
+	{
+		for (&(a, b), &n) in &self.bigrams {
+			f(a, b, n);
+		}
+	}
+}
+
+impl Ngrams for str
+{
+	fn each_char(&self, f: &mut dyn FnMut(char, u64))
+	{
+		for c in self.chars() {
+			f(c, 1);
+		}
+	}
+
+	fn each_bigram(&self, f: &mut dyn FnMut(char, char, u64))
+	{
+		let mut chars = self.chars();
+		let mut prev = match chars.next() {
+			Some(c) => c,
+			None => return,
+		};
+		for c in chars {
+			f(prev, c, 1);
+			prev = c;
+		}
+	}
+}
@@ -0,0 +1,102 @@
+//! One CSV row per key position, for comparing runs in a spreadsheet.
+//!
+//! The `presses` and `penalty` columns are only filled in when the layout was
+//! scored against a corpus. `Layout::stats_csv` writes a table of the
+//! metrics on `Layout` instead, for any text.
+
+use corpus::Ngrams;
+use layout::finger_for_pos;
+use layout::hand_for_pos;
+use layout::row_for_pos;
+use layout::Finger;
+use layout::Hand;
+use layout::KeyMap;
+use layout::Layout;
+use layout::Row;
+use penalty::PositionStats;
+
+// Everything the CSV says about one position.
+pub struct KeyRow
+{
+	pub pos:    usize,
+	pub hand:   Hand,
+	pub finger: Finger,
+	pub row:    Row,
+	pub lower:  char,
+	pub upper:  char,
+	pub stats:  Option<PositionStats>,
+}
+
+pub fn to_csv(layout: &Layout, stats: Option<&KeyMap<PositionStats>>)
+-> String
+{
+	let mut s = String::from("position,hand,finger,row,lower,upper,presses,penalty\n");
+	for key in key_rows(layout, stats) {
+		let (presses, penalty) = match key.stats {
+			Some(st) => (st.presses.to_string(), st.penalty.to_string()),
+			None => (String::new(), String::new()),
+		};
+		s.push_str(&format!("{},{},{},{},{},{},{},{}\n",
+			key.pos, key.hand, key.finger, key.row,
+			field(key.lower), field(key.upper), presses, penalty));
+	}
+	s
+}
+
+pub fn key_rows(layout: &Layout, stats: Option<&KeyMap<PositionStats>>)
+-> Vec<KeyRow>
+{
+	let lower = layout.lower().keys();
+	let upper = layout.upper().keys();
+	(0..lower.len()).map(|pos| KeyRow {
+		pos,
+		hand: hand_for_pos(pos).unwrap(),
+		finger: finger_for_pos(pos).unwrap(),
+		row: row_for_pos(pos).unwrap(),
+		lower: lower[pos],
+		upper: upper[pos],
+		stats: stats.map(|st| st.0[pos]),
+	}).collect()
+}
+
+impl Layout
+{
+	// One row per position with the key's lower character, how often the
+	// key is pressed in `text`, and how many same-finger bigrams, as
+	// `same_finger_bigrams` counts them, it is one of the keys of.
+	pub fn stats_csv<T: Ngrams + ?Sized>(&self, text: &T)
+	-> String
+	{
+		let map = self.get_position_map();
+		let mut presses: KeyMap<u64> = KeyMap::default();
+		let mut same_finger: KeyMap<u64> = KeyMap::default();
+		map.each_press(text, |kp, n| presses[kp.pos] += n);
+		map.each_press_pair(text, |p, c, n| {
+			if p.same_finger(&c) && p.finger != Finger::Thumb && p.pos != c.pos {
+				same_finger[p.pos] += n;
+				same_finger[c.pos] += n;
+			}
+		});
+
+		let mut s = String::from("position,char,finger,hand,row,frequency,same_finger_bigrams\n");
+		for key in key_rows(self, None) {
+			s.push_str(&format!("{},{},{},{},{},{},{}\n",
+				key.pos, field(key.lower), key.finger, key.hand, key.row,
+				presses[key.pos], same_finger[key.pos]));
+		}
+		s
+	}
+}
+
+// A key as a CSV field. Characters that would break the row, and the space
+// bar, which would be invisible, are quoted; empty keys are empty fields.
+fn field(c: char)
+-> String
+{
+	match c {
+		'\0' => String::new(),
+		'"' => "\"\"\"\"".to_string(),
+		',' | '\n' | '\r' | ' ' => format!("\"{}\"", c),
+		_ => c.to_string(),
+	}
+}
@@ -0,0 +1,123 @@
+//! Kanata `defsrc`/`deflayer` pairs, for trying a layout with a software
+//! remapper.
+//!
+//! `defsrc` names the physical keys by what they type on the reference layout,
+//! which is also the layout the operating system is assumed to use. Each entry
+//! of `deflayer` is then the reference key, shifted if need be, that types the
+//! new layout's character at the same position.
+
+use layout::KeyPress;
+use layout::Layout;
+use layout::LayoutPosMap;
+use layout::LayoutShuffleMask;
+use layout::GRID_ROWS;
+use layout::LAYOUT_MASK;
+
+// Width of one key column.
+const COLUMN_WIDTH: usize = 5;
+
+pub fn to_kanata(layout: &Layout, reference: &Layout)
+-> String
+{
+	to_kanata_masked(layout, reference, &LAYOUT_MASK)
+}
+
+// Same as `to_kanata`, but positions that `mask` does not let the optimizer
+// swap are left transparent, so that they keep doing what they did before.
+pub fn to_kanata_masked(layout: &Layout, reference: &Layout, mask: &LayoutShuffleMask)
+-> String
+{
+	let map = reference.get_position_map();
+	let sources: Vec<String> = (0..34).map(|i| source_key(reference, i)).collect();
+	let targets: Vec<String> = (0..34).map(|i| {
+		if mask.is_swappable(i) {
+			target_key(layout.lower().keys()[i], reference, &map).unwrap_or_else(|| "XX".to_string())
+		} else {
+			"_".to_string()
+		}
+	}).collect();
+
+	let mut s = String::new();
+	s.push_str("(defsrc\n");
+	s.push_str(&grid(&sources));
+	s.push_str(")\n\n(deflayer keygen\n");
+	s.push_str(&grid(&targets));
+	s.push_str(")\n");
+	s
+}
+
+// Characters on the lower layer that the reference layout cannot type, and so
+// were exported as `XX` by `to_kanata`. Empty positions are not reported.
+pub fn unmapped_keys(layout: &Layout, reference: &Layout)
+-> Vec<char>
+{
+	let map = reference.get_position_map();
+	LAYOUT_MASK.swappable_positions().into_iter()
+		.map(|i| layout.lower().keys()[i])
+		.filter(|&c| c != '\0' && target_key(c, reference, &map).is_none())
+		.collect()
+}
+
+// The physical key at position `pos`. The reference may leave a thumb key
+// empty, in which case it is the spare ISO key next to left shift.
+fn source_key(reference: &Layout, pos: usize)
+-> String
+{
+	match key_name(reference.lower().keys()[pos]) {
+		Some(name) => name,
+		None if pos == 32 => "102d".to_string(),
+		None => "XX".to_string(),
+	}
+}
+
+// The key, on a system using the reference layout, that types `c`. `map` is
+// the reference's position map.
+fn target_key(c: char, reference: &Layout, map: &LayoutPosMap)
+-> Option<String>
+{
+	if c == '\0' {
+		return Some("XX".to_string());
+	}
+
+	let kp = KeyPress::new(c, map)?;
+	let unshifted = reference.lower().keys()[kp.pos];
+	let name = source_key(reference, kp.pos);
+	if unshifted == c {
+		Some(name)
+	} else {
+		Some(format!("S-{}", name))
+	}
+}
+
+fn key_name(c: char)
+-> Option<String>
+{
+	let name = match c {
+		'a'..='z' | '0'..='9' | '-' | '=' | '[' | ']' | '\\' | ';' | '\'' | ',' | '.' | '/' =>
+			return Some(c.to_string()),
+		'`' => "grv",
+		' ' => "spc",
+		_   => return None,
+	};
+	Some(name.to_string())
+}
+
+// Lays the keys out in KeyMap rows, with the thumb keys under the inner
+// columns.
+fn grid(keys: &[String])
+-> String
+{
+	let mut s = String::new();
+	for &(start, left, right) in GRID_ROWS.iter() {
+		let mut line = " ".repeat(2 + (5 - left) * COLUMN_WIDTH);
+		for (i, key) in keys[start..start + left + right].iter().enumerate() {
+			if i == left {
+				line.push_str("  ");
+			}
+			line.push_str(&format!("{:<1$}", key, COLUMN_WIDTH));
+		}
+		s.push_str(line.trim_end());
+		s.push('\n');
+	}
+	s
+}
@@ -0,0 +1,81 @@
+//! Apple .keylayout documents for macOS.
+//!
+//! The lower layer is bound to no modifiers and the upper layer to shift.
+//! Caps lock shifts letters only. Keys that keygen does not place are left
+//! out, so macOS falls back to its defaults for them.
+
+use layout::Layout;
+
+// macOS virtual key codes for each KeyMap position, as in Carbon's Events.h
+// (kVK_ANSI_Q and so on). The left thumb key goes on kVK_ISO_Section, the
+// spare key of ISO boards.
+static KEYLAYOUT_KEY_CODES: [u8; 34] = [
+	12, 13, 14, 15, 17,   16, 32, 34, 31, 35, 33,
+	0,  1,  2,  3,  5,    4,  38, 40, 37, 41, 39,
+	6,  7,  8,  9,  11,   45, 46, 43, 47, 44,
+	10, 49];
+
+// `name` is the name macOS shows in the input source menu.
+pub fn to_keylayout(layout: &Layout, name: &str)
+-> String
+{
+	let lower = layout.lower().keys();
+	let upper = layout.upper().keys();
+	let mut caps = *lower;
+	for (c, u) in caps.iter_mut().zip(upper.iter()) {
+		if c.is_alphabetic() {
+			*c = *u;
+		}
+	}
+
+	let mut s = String::new();
+	s.push_str("<?xml version=\"1.1\" encoding=\"UTF-8\"?>\n");
+	s.push_str("<!DOCTYPE keyboard SYSTEM \"file://localhost/System/Library/DTDs/KeyboardLayout.dtd\">\n");
+	s.push_str(&format!("<keyboard group=\"126\" id=\"-19341\" name=\"{}\" maxout=\"1\">\n", escape(name)));
+	s.push_str("    <layouts>\n");
+	s.push_str("        <layout first=\"0\" last=\"17\" mapSet=\"ANSI\" modifiers=\"Modifiers\"/>\n");
+	s.push_str("    </layouts>\n");
+	s.push_str("    <modifierMap id=\"Modifiers\" defaultIndex=\"0\">\n");
+	s.push_str("        <keyMapSelect mapIndex=\"0\">\n");
+	s.push_str("            <modifier keys=\"\"/>\n");
+	s.push_str("        </keyMapSelect>\n");
+	s.push_str("        <keyMapSelect mapIndex=\"1\">\n");
+	s.push_str("            <modifier keys=\"anyShift caps?\"/>\n");
+	s.push_str("        </keyMapSelect>\n");
+	s.push_str("        <keyMapSelect mapIndex=\"2\">\n");
+	s.push_str("            <modifier keys=\"caps\"/>\n");
+	s.push_str("        </keyMapSelect>\n");
+	s.push_str("    </modifierMap>\n");
+	s.push_str("    <keyMapSet id=\"ANSI\">\n");
+	for (index, keys) in [lower, upper, &caps].iter().enumerate() {
+		s.push_str(&format!("        <keyMap index=\"{}\">\n", index));
+		for (code, &c) in KEYLAYOUT_KEY_CODES.iter().zip(keys.iter()) {
+			if c != '\0' {
+				s.push_str(&format!("            <key code=\"{}\" output=\"{}\"/>\n", code, escape_char(c)));
+			}
+		}
+		s.push_str("        </keyMap>\n");
+	}
+	s.push_str("    </keyMapSet>\n");
+	s.push_str("</keyboard>\n");
+	s
+}
+
+fn escape(s: &str)
+-> String
+{
+	s.chars().map(escape_char).collect()
+}
+
+fn escape_char(c: char)
+-> String
+{
+	match c {
+		'&'  => "&amp;".to_string(),
+		'<'  => "&lt;".to_string(),
+		'>'  => "&gt;".to_string(),
+		'"'  => "&quot;".to_string(),
+		'\'' => "&apos;".to_string(),
+		_    => c.to_string(),
+	}
+}
@@ -0,0 +1,130 @@
+//! Microsoft Keyboard Layout Creator (.klc) source files for Windows.
+//!
+//! Only the keys that keygen places are listed; MSKLC fills in the rest of
+//! the board from its defaults. The lower layer is the unshifted state and
+//! the upper layer the shifted one, and no dead keys are generated.
+
+use layout::Layout;
+
+// Scan codes for each KeyMap position. The left thumb key goes on the spare
+// ISO key next to left shift.
+static KLC_SCAN_CODES: [&str; 34] = [
+	"10", "11", "12", "13", "14",   "15", "16", "17", "18", "19", "1a",
+	"1e", "1f", "20", "21", "22",   "23", "24", "25", "26", "27", "28",
+	"2c", "2d", "2e", "2f", "30",   "31", "32", "33", "34", "35",
+	"56", "39"];
+
+// The US virtual key of each scan code, used for keys whose character has no
+// virtual key of its own.
+static KLC_DEFAULT_VKS: [&str; 34] = [
+	"Q", "W", "E", "R", "T",   "Y", "U", "I", "O", "P", "OEM_4",
+	"A", "S", "D", "F", "G",   "H", "J", "K", "L", "OEM_1", "OEM_7",
+	"Z", "X", "C", "V", "B",   "N", "M", "OEM_COMMA", "OEM_PERIOD", "OEM_2",
+	"OEM_102", "SPACE"];
+
+static KLC_KEY_NAMES: [(&str, &str); 28] = [
+	("01", "Esc"), ("0e", "Backspace"), ("0f", "Tab"), ("1c", "Enter"),
+	("1d", "Ctrl"), ("2a", "Shift"), ("36", "\"Right Shift\""), ("37", "\"Num *\""),
+	("38", "Alt"), ("39", "Space"), ("3a", "\"Caps Lock\""), ("3b", "F1"),
+	("3c", "F2"), ("3d", "F3"), ("3e", "F4"), ("3f", "F5"),
+	("40", "F6"), ("41", "F7"), ("42", "F8"), ("43", "F9"),
+	("44", "F10"), ("45", "Pause"), ("46", "\"Scroll Lock\""), ("4a", "\"Num -\""),
+	("4e", "\"Num +\""), ("53", "\"Num Del\""), ("57", "F11"), ("58", "F12")];
+
+static KLC_KEY_NAMES_EXT: [(&str, &str); 20] = [
+	("1c", "\"Num Enter\""), ("1d", "\"Right Ctrl\""), ("35", "\"Num /\""), ("37", "\"Prnt Scrn\""),
+	("38", "\"Right Alt\""), ("45", "\"Num Lock\""), ("46", "Break"), ("47", "Home"),
+	("48", "Up"), ("49", "\"Page Up\""), ("4b", "Left"), ("4d", "Right"),
+	("4f", "End"), ("50", "Down"), ("51", "\"Page Down\""), ("52", "Insert"),
+	("53", "Delete"), ("5b", "\"Left Windows\""), ("5c", "\"Right Windows\""), ("5d", "Application")];
+
+// `name` is the keyboard's description; MSKLC limits the DLL name derived
+// from it to eight characters.
+pub fn to_klc(layout: &Layout, name: &str)
+-> String
+{
+	let lower = layout.lower().keys();
+	let upper = layout.upper().keys();
+	let dll: String = name.chars().filter(|c| c.is_ascii_alphanumeric()).take(8).collect();
+
+	let mut s = String::new();
+	s.push_str(&format!("KBD\t{}\t\"{}\"\n\n", dll, name));
+	s.push_str(&format!("COPYRIGHT\t\"{}\"\n\n", name));
+	s.push_str(&format!("COMPANY\t\"{}\"\n\n", name));
+	s.push_str("LOCALENAME\t\"en-US\"\n\n");
+	s.push_str("LOCALEID\t\"00000409\"\n\n");
+	s.push_str("VERSION\t1.0\n\n");
+	s.push_str("SHIFTSTATE\n\n");
+	s.push_str("0\t//Column 4\n");
+	s.push_str("1\t//Column 5 : Shft\n");
+	s.push_str("2\t//Column 6 :       Ctrl\n\n");
+	s.push_str("LAYOUT\t\t;an extra '@' at the end is a dead key\n\n");
+	s.push_str("//SC\tVK_\t\tCap\t0\t1\t2\n");
+	s.push_str("//--\t----\t\t----\t----\t----\t----\n\n");
+	for i in 0..34 {
+		let vk = virtual_key(lower[i]).unwrap_or(KLC_DEFAULT_VKS[i]);
+		let cap = if lower[i].is_ascii_alphabetic() { 1 } else { 0 };
+		let ctrl = if lower[i] == ' ' { "0020" } else { "-1" };
+		s.push_str(&format!("{}\t{}\t\t{}\t{}\t{}\t{}\n",
+			KLC_SCAN_CODES[i], vk, cap, character(lower[i]), character(upper[i]), ctrl));
+	}
+	s.push('\n');
+
+	s.push_str("KEYNAME\n\n");
+	for &(sc, key) in KLC_KEY_NAMES.iter() {
+		s.push_str(&format!("{}\t{}\n", sc, key));
+	}
+	s.push_str("\nKEYNAME_EXT\n\n");
+	for &(sc, key) in KLC_KEY_NAMES_EXT.iter() {
+		s.push_str(&format!("{}\t{}\n", sc, key));
+	}
+
+	s.push_str("\nDESCRIPTIONS\n\n");
+	s.push_str(&format!("0409\t{}\n", name));
+	s.push_str("\nLANGUAGENAMES\n\n");
+	s.push_str("0409\tEnglish (United States)\n");
+	s.push_str("\nENDKBD\n");
+	s
+}
+
+// Letters and digits are written as themselves, anything else as its code
+// point, and an empty key as -1.
+fn character(c: char)
+-> String
+{
+	match c {
+		'\0' => "-1".to_string(),
+		'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_string(),
+		_ => format!("{:04x}", c as u32),
+	}
+}
+
+// The US virtual key for the key that types `c` unshifted, so that shortcuts
+// follow the characters.
+fn virtual_key(c: char)
+-> Option<&'static str>
+{
+	let vk = match c {
+		'a' => "A", 'b' => "B", 'c' => "C", 'd' => "D", 'e' => "E", 'f' => "F",
+		'g' => "G", 'h' => "H", 'i' => "I", 'j' => "J", 'k' => "K", 'l' => "L",
+		'm' => "M", 'n' => "N", 'o' => "O", 'p' => "P", 'q' => "Q", 'r' => "R",
+		's' => "S", 't' => "T", 'u' => "U", 'v' => "V", 'w' => "W", 'x' => "X",
+		'y' => "Y", 'z' => "Z",
+		'0' => "0", '1' => "1", '2' => "2", '3' => "3", '4' => "4",
+		'5' => "5", '6' => "6", '7' => "7", '8' => "8", '9' => "9",
+		' '  => "SPACE",
+		'-'  => "OEM_MINUS",
+		'='  => "OEM_PLUS",
+		'['  => "OEM_4",
+		']'  => "OEM_6",
+		'\\' => "OEM_5",
+		';'  => "OEM_1",
+		'\'' => "OEM_7",
+		'`'  => "OEM_3",
+		','  => "OEM_COMMA",
+		'.'  => "OEM_PERIOD",
+		'/'  => "OEM_2",
+		_    => return None,
+	};
+	Some(vk)
+}
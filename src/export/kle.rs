@@ -0,0 +1,87 @@
+//! keyboard-layout-editor.com JSON, for looking at a layout rather than
+//! typing on it.
+//!
+//! KLE reads a board as an array of rows, each row an array of keys. A key
+//! is its legend, a string with one line per legend position, and an object
+//! before a key moves it to the right by `x` key widths. Each key here shows
+//! its shifted character above its lower one, the two hands are a key apart
+//! and the thumb keys sit under the inner columns. The space bar is labelled
+//! `␣` so that it can be seen. Legends are HTML to KLE, so `&`, `<` and `>`
+//! are escaped.
+
+extern crate serde_json;
+
+use self::serde_json::json;
+use self::serde_json::Value;
+
+use layout::Layout;
+use layout::GRID_ROWS;
+
+// The gap between the hands, in key widths.
+const HAND_GAP: usize = 1;
+
+pub fn to_kle(layout: &Layout)
+-> String
+{
+	let lower = layout.lower().keys();
+	let upper = layout.upper().keys();
+	let legend = |pos: usize| Value::String(legend(lower[pos], upper[pos]));
+
+	let mut rows: Vec<Value> = Vec::new();
+	for &(start, left, right) in &GRID_ROWS[..3] {
+		let mut row: Vec<Value> = (start..start + left).map(legend).collect();
+		row.push(json!({ "x": HAND_GAP }));
+		row.extend((start + left..start + left + right).map(legend));
+		rows.push(Value::Array(row));
+	}
+
+	// One thumb key under each hand's inner column.
+	let (start, left, _) = GRID_ROWS[3];
+	let inner = GRID_ROWS[0].1 - 1;
+	rows.push(json!([
+		{ "x": inner }, legend(start),
+		{ "x": HAND_GAP }, legend(start + left),
+	]));
+
+	let mut s = String::from("[\n");
+	let rows: Vec<String> = rows.iter().map(|row| row.to_string()).collect();
+	s.push_str(&rows.join(",\n"));
+	s.push_str("\n]\n");
+	s
+}
+
+impl Layout
+{
+	// The layout as KLE JSON; see `to_kle`.
+	pub fn to_kle_json(&self)
+	-> String
+	{
+		to_kle(self)
+	}
+}
+
+// The shifted character on the top line and the lower one below, or nothing
+// for an empty key. Only the lower character is shown when shift does not
+// change it.
+fn legend(lower: char, upper: char)
+-> String
+{
+	match (lower, upper) {
+		('\0', '\0') => String::new(),
+		(l, u) if l == u || u == '\0' => escape(l),
+		('\0', u) => escape(u),
+		(l, u) => format!("{}\n{}", escape(u), escape(l)),
+	}
+}
+
+fn escape(c: char)
+-> String
+{
+	match c {
+		'&' => "&amp;".to_string(),
+		'<' => "&lt;".to_string(),
+		'>' => "&gt;".to_string(),
+		' ' => "&#9251;".to_string(),
+		_ => c.to_string(),
+	}
+}
@@ -0,0 +1,124 @@
+//! Writes layouts out in formats that keyboard firmware and operating systems
+//! understand, so that a result can be typed on without transcribing it.
+
+pub mod csv;
+pub mod kanata;
+pub mod keylayout;
+pub mod klc;
+pub mod kle;
+pub mod qmk;
+pub mod xkb;
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use layout::KeyMap;
+use layout::Layout;
+use layout::QWERTY_LAYOUT;
+use penalty::PositionStats;
+
+/* ----- *
+ * TYPES *
+ * ----- */
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format
+{
+	Qmk,
+	Xkb,
+	Klc,
+	Kanata,
+	Keylayout,
+	Csv,
+	Kle,
+}
+
+#[derive(Debug)]
+pub struct UnknownFormat(pub String);
+
+/* ----- *
+ * IMPLS *
+ * ----- */
+
+impl Format
+{
+	// Where the CLI writes the export, relative to the working directory.
+	pub fn file_name(&self)
+	-> &'static str
+	{
+		match *self {
+			Format::Qmk => "keymap.c",
+			Format::Xkb => "keygen.xkb",
+			Format::Klc => "keygen.klc",
+			Format::Kanata => "keygen.kbd",
+			Format::Keylayout => "keygen.keylayout",
+			Format::Csv => "keygen.csv",
+			Format::Kle => "keygen-kle.json",
+		}
+	}
+
+	// Renders `layout` with the default settings of each exporter.
+	pub fn export(&self, layout: &Layout)
+	-> String
+	{
+		self.export_with_stats(layout, None)
+	}
+
+	// Same as `export`, but formats that report statistics include `stats`.
+	pub fn export_with_stats(&self, layout: &Layout, stats: Option<&KeyMap<PositionStats>>)
+	-> String
+	{
+		match *self {
+			Format::Qmk => qmk::to_qmk_keymap(layout, "_BASE"),
+			Format::Xkb => xkb::to_xkb_symbols(layout, "keygen"),
+			Format::Klc => klc::to_klc(layout, "keygen"),
+			Format::Kanata => kanata::to_kanata(layout, &QWERTY_LAYOUT),
+			Format::Keylayout => keylayout::to_keylayout(layout, "keygen"),
+			Format::Csv => csv::to_csv(layout, stats),
+			Format::Kle => kle::to_kle(layout),
+		}
+	}
+
+	// Characters on `layout` that the format has no way to express.
+	pub fn unmapped_keys(&self, layout: &Layout)
+	-> Vec<char>
+	{
+		match *self {
+			Format::Qmk => qmk::unmapped_keys(layout),
+			Format::Kanata => kanata::unmapped_keys(layout, &QWERTY_LAYOUT),
+			Format::Xkb | Format::Klc | Format::Keylayout | Format::Csv | Format::Kle => Vec::new(),
+		}
+	}
+}
+
+impl FromStr for Format
+{
+	type Err = UnknownFormat;
+
+	fn from_str(s: &str)
+	-> Result<Format, UnknownFormat>
+	{
+		match s {
+			"qmk" => Ok(Format::Qmk),
+			"xkb" => Ok(Format::Xkb),
+			"klc" => Ok(Format::Klc),
+			"kanata" => Ok(Format::Kanata),
+			"keylayout" => Ok(Format::Keylayout),
+			"csv" => Ok(Format::Csv),
+			"kle" => Ok(Format::Kle),
+			_ => Err(UnknownFormat(s.to_string())),
+		}
+	}
+}
+
+impl fmt::Display for UnknownFormat
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		write!(f, "unknown export format `{}` (expected qmk, xkb, klc, kanata, keylayout, csv or kle)", self.0)
+	}
+}
+
+impl Error for UnknownFormat {}
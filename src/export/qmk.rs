@@ -0,0 +1,117 @@
+//! QMK keymap.c fragments.
+//!
+//! The `LAYOUT(...)` arguments are given in KeyMap order: the three alpha rows,
+//! left half first, then the two thumb keys. Only the lower layer is exported;
+//! the shifted characters come from the host's US keymap.
+
+use layout::Layout;
+use layout::GRID_ROWS;
+
+// Width of one keycode column, including the trailing comma and space.
+const COLUMN_WIDTH: usize = 9;
+
+impl Layout
+{
+	pub fn to_qmk_keymap(&self, layer_name: &str)
+	-> String
+	{
+		to_qmk_keymap(self, layer_name)
+	}
+}
+
+pub fn to_qmk_keymap(layout: &Layout, layer_name: &str)
+-> String
+{
+	to_qmk_keymap_with_thumb(layout, layer_name, None)
+}
+
+// Same as `to_qmk_keymap`, but uses `thumb_keycode` for the left thumb key
+// (position 32) instead of the character the layout puts there; for example
+// `LT(1, KC_E)` to make it a layer tap.
+pub fn to_qmk_keymap_with_thumb(layout: &Layout, layer_name: &str, thumb_keycode: Option<&str>)
+-> String
+{
+	let keys = layout.lower().keys();
+	let mut keycodes: Vec<String> = keys.iter().map(|&c| {
+		keycode(c).unwrap_or_else(|| "KC_NO".to_string())
+	}).collect();
+	if let Some(thumb) = thumb_keycode {
+		keycodes[32] = thumb.to_string();
+	}
+
+	let mut s = format!("[{}] = LAYOUT(\n", layer_name);
+	for &(start, left, right) in GRID_ROWS.iter() {
+		// Right-align the left half so that the thumb keys sit under the
+		// inner columns.
+		let mut line = " ".repeat(4 + (5 - left) * COLUMN_WIDTH);
+		for (i, kc) in keycodes[start..start + left + right].iter().enumerate() {
+			if i == left {
+				line.push_str("    ");
+			}
+			let last = start + i == keycodes.len() - 1;
+			let cell = if last { kc.clone() } else { format!("{},", kc) };
+			line.push_str(&format!("{:<1$}", cell, COLUMN_WIDTH));
+		}
+		s.push_str(line.trim_end());
+		s.push('\n');
+	}
+	s.push_str("),\n");
+	s
+}
+
+// Characters on the lower layer that have no QMK keycode, and so were
+// exported as `KC_NO`. Empty positions are not reported.
+pub fn unmapped_keys(layout: &Layout)
+-> Vec<char>
+{
+	layout.lower().keys().iter()
+		.cloned()
+		.filter(|&c| c != '\0' && keycode(c).is_none())
+		.collect()
+}
+
+// The basic QMK keycode that types `c` on a US host. Shifted symbols use the
+// QMK aliases, which send the shift themselves.
+pub fn keycode(c: char)
+-> Option<String>
+{
+	let name = match c {
+		'a'..='z' | '0'..='9' => return Some(format!("KC_{}", c.to_ascii_uppercase())),
+		'\0'  => "KC_NO",
+		' '   => "KC_SPC",
+		'-'   => "KC_MINS",
+		'='   => "KC_EQL",
+		'['   => "KC_LBRC",
+		']'   => "KC_RBRC",
+		'\\'  => "KC_BSLS",
+		';'   => "KC_SCLN",
+		'\''  => "KC_QUOT",
+		'`'   => "KC_GRV",
+		','   => "KC_COMM",
+		'.'   => "KC_DOT",
+		'/'   => "KC_SLSH",
+		'!'   => "KC_EXLM",
+		'@'   => "KC_AT",
+		'#'   => "KC_HASH",
+		'$'   => "KC_DLR",
+		'%'   => "KC_PERC",
+		'^'   => "KC_CIRC",
+		'&'   => "KC_AMPR",
+		'*'   => "KC_ASTR",
+		'('   => "KC_LPRN",
+		')'   => "KC_RPRN",
+		'_'   => "KC_UNDS",
+		'+'   => "KC_PLUS",
+		'{'   => "KC_LCBR",
+		'}'   => "KC_RCBR",
+		'|'   => "KC_PIPE",
+		':'   => "KC_COLN",
+		'"'   => "KC_DQUO",
+		'~'   => "KC_TILD",
+		'<'   => "KC_LABK",
+		'>'   => "KC_RABK",
+		'?'   => "KC_QUES",
+		_     => return None,
+	};
+	Some(name.to_string())
+}
@@ -0,0 +1,79 @@
+//! XKB symbols files for Linux.
+//!
+//! The layout is laid over `us(basic)`, so keys that keygen does not place
+//! keep their US meaning. The lower layer is the first level of each key and
+//! the upper layer the second.
+
+use layout::Layout;
+
+// XKB key names for each KeyMap position. The left thumb key has no place on
+// a standard board, so it goes on the spare ISO key next to left shift.
+static XKB_KEY_NAMES: [&str; 34] = [
+	"AD01", "AD02", "AD03", "AD04", "AD05",   "AD06", "AD07", "AD08", "AD09", "AD10", "AD11",
+	"AC01", "AC02", "AC03", "AC04", "AC05",   "AC06", "AC07", "AC08", "AC09", "AC10", "AC11",
+	"AB01", "AB02", "AB03", "AB04", "AB05",   "AB06", "AB07", "AB08", "AB09", "AB10",
+	"LSGT", "SPCE"];
+
+pub fn to_xkb_symbols(layout: &Layout, name: &str)
+-> String
+{
+	let lower = layout.lower().keys();
+	let upper = layout.upper().keys();
+
+	let mut s = String::new();
+	s.push_str("default partial alphanumeric_keys\n");
+	s.push_str(&format!("xkb_symbols \"{}\" {{\n", name));
+	s.push_str("    include \"us(basic)\"\n");
+	s.push_str(&format!("    name[Group1] = \"{}\";\n\n", name));
+	for (i, key) in XKB_KEY_NAMES.iter().enumerate() {
+		s.push_str(&format!("    key <{}> {{ [ {}, {} ] }};\n", key, keysym(lower[i]), keysym(upper[i])));
+	}
+	s.push_str("};\n");
+	s
+}
+
+// The keysym name for `c`. Characters without a short name use the Unicode
+// form, which XKB accepts for any code point.
+pub fn keysym(c: char)
+-> String
+{
+	let name = match c {
+		'a'..='z' | 'A'..='Z' | '0'..='9' => return c.to_string(),
+		'\0' => "NoSymbol",
+		' '  => "space",
+		'!'  => "exclam",
+		'"'  => "quotedbl",
+		'#'  => "numbersign",
+		'$'  => "dollar",
+		'%'  => "percent",
+		'&'  => "ampersand",
+		'\'' => "apostrophe",
+		'('  => "parenleft",
+		')'  => "parenright",
+		'*'  => "asterisk",
+		'+'  => "plus",
+		','  => "comma",
+		'-'  => "minus",
+		'.'  => "period",
+		'/'  => "slash",
+		':'  => "colon",
+		';'  => "semicolon",
+		'<'  => "less",
+		'='  => "equal",
+		'>'  => "greater",
+		'?'  => "question",
+		'@'  => "at",
+		'['  => "bracketleft",
+		'\\' => "backslash",
+		']'  => "bracketright",
+		'^'  => "asciicircum",
+		'_'  => "underscore",
+		'`'  => "grave",
+		'{'  => "braceleft",
+		'|'  => "bar",
+		'}'  => "braceright",
+		'~'  => "asciitilde",
+		_    => return format!("U{:04X}", c as u32),
+	};
+	name.to_string()
+}
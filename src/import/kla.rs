@@ -0,0 +1,130 @@
+//! Layouts exported from patorjk's keyboard-layout-analyzer.
+//!
+//! Only the "standard" (ANSI) keyboard type is read. Its `keys` array holds
+//! one array per row, number row first, and each key gives its `primary` and
+//! `shift` characters as character codes:
+//!
+//! ```json
+//! { "keyboardType": "standard",
+//!   "keys": [[ ... ], [{ "primary": 9, ... }, { "primary": 113, "shift": 81 }, ...], ...] }
+//! ```
+//!
+//! The alpha block fills positions 0 to 31 and the space bar position 33.
+//! Everything else on the board is skipped.
+
+use std::convert::TryFrom;
+
+use super::serde_json;
+use super::serde_json::Value;
+
+use super::ImportError;
+use layout::Layout;
+
+// Where each KeyMap row starts in the analyzer's rows, as (analyzer row,
+// analyzer column, first position, key count). The first column of these
+// rows is tab, caps lock and shift.
+static KLA_ROWS: [(usize, usize, usize, usize); 3] = [
+	(1, 1, 0,  11),
+	(2, 1, 11, 11),
+	(3, 1, 22, 10)];
+
+// The row the space bar is on.
+const KLA_SPACE_ROW: usize = 4;
+
+pub struct KlaImport
+{
+	pub layout:  Layout,
+	// Characters on keys that have no place among the 34 positions.
+	pub skipped: Vec<char>,
+}
+
+impl Layout
+{
+	pub fn from_kla_json(s: &str)
+	-> Result<Layout, ImportError>
+	{
+		parse(s).map(|import| import.layout)
+	}
+}
+
+pub fn parse(s: &str)
+-> Result<KlaImport, ImportError>
+{
+	let root: Value = serde_json::from_str(s).map_err(ImportError::Json)?;
+	match root.get("keyboardType").and_then(|t| t.as_str()) {
+		None | Some("standard") => (),
+		Some(kind) => return Err(ImportError::UnsupportedKeyboard(kind.to_string())),
+	}
+	let rows = match root.get("keys").and_then(|k| k.as_array()) {
+		Some(rows) => rows,
+		None => return Err(ImportError::MissingRows),
+	};
+	let key = |row: usize, col: usize| {
+		rows.get(row)
+			.and_then(|r| r.as_array())
+			.and_then(|r| r.get(col))
+			.ok_or(ImportError::MissingKey { row, col })
+	};
+
+	let mut lower = ['\0'; 34];
+	let mut shifted = [None; 34];
+	let mut used = Vec::new();
+	for &(row, col, first, count) in KLA_ROWS.iter() {
+		for i in 0..count {
+			let k = key(row, col + i)?;
+			lower[first + i] = char_field(k, "primary")?.unwrap_or('\0');
+			shifted[first + i] = char_field(k, "shift")?;
+			used.push((row, col + i));
+		}
+	}
+
+	if let Some(space_row) = rows.get(KLA_SPACE_ROW).and_then(|r| r.as_array()) {
+		for (col, k) in space_row.iter().enumerate() {
+			if char_field(k, "primary")? == Some(' ') {
+				lower[33] = ' ';
+				shifted[33] = char_field(k, "shift")?;
+				used.push((KLA_SPACE_ROW, col));
+				break;
+			}
+		}
+	}
+
+	// Keys without a shifted character get the US one.
+	let derived = Layout::from_lower_keys(lower);
+	let mut upper = *derived.upper().keys();
+	for (u, s) in upper.iter_mut().zip(shifted.iter()) {
+		if let Some(s) = *s {
+			*u = s;
+		}
+	}
+	let layout = Layout::from_keys(lower, upper);
+
+	let mut skipped = Vec::new();
+	for (row, r) in rows.iter().enumerate() {
+		for (col, k) in r.as_array().into_iter().flatten().enumerate() {
+			if used.contains(&(row, col)) {
+				continue;
+			}
+			if let Some(c) = char_field(k, "primary")? {
+				skipped.push(c);
+			}
+		}
+	}
+
+	Ok(KlaImport { layout, skipped })
+}
+
+// A printable character code, or `None` for the analyzer's placeholders:
+// missing fields, control characters and negative codes for keys like enter.
+fn char_field(key: &Value, field: &str)
+-> Result<Option<char>, ImportError>
+{
+	let code = match key.get(field).and_then(|c| c.as_i64()) {
+		Some(code) if code >= 32 => code,
+		_ => return Ok(None),
+	};
+	u32::try_from(code).ok()
+		.and_then(::std::char::from_u32)
+		.map(Some)
+		.ok_or(ImportError::InvalidCharCode(code))
+}
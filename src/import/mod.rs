@@ -0,0 +1,46 @@
+//! Reads layouts published in other tools' formats.
+
+extern crate serde_json;
+
+pub mod kla;
+
+use std::error::Error;
+use std::fmt;
+
+/* ----- *
+ * TYPES *
+ * ----- */
+
+#[derive(Debug)]
+pub enum ImportError
+{
+	Json(serde_json::Error),
+	UnsupportedKeyboard(String),
+	MissingRows,
+	MissingKey { row: usize, col: usize },
+	InvalidCharCode(i64),
+}
+
+/* ----- *
+ * IMPLS *
+ * ----- */
+
+impl fmt::Display for ImportError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		match *self {
+			ImportError::Json(ref e) => write!(f, "could not parse layout: {}", e),
+			ImportError::UnsupportedKeyboard(ref kind) =>
+				write!(f, "unsupported keyboard type `{}`", kind),
+			ImportError::MissingRows => write!(f, "the layout has no `keys` rows"),
+			ImportError::MissingKey { row, col } =>
+				write!(f, "no key at row {}, column {}", row, col),
+			ImportError::InvalidCharCode(code) =>
+				write!(f, "{} is not a character code", code),
+		}
+	}
+}
+
+impl Error for ImportError {}
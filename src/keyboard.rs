@@ -0,0 +1,287 @@
+//! Physical keyboards: which hand, finger, row and column each position is
+//! on, where it sits, and whether the optimizer may move its key.
+//!
+//! `Keyboard::default_split` is the board that the statics in layout.rs
+//! describe, with a thumb key for each hand, and `Keyboard::one_thumb` the
+//! same board with only the right thumb key in use. Other boards are put
+//! together with `KeyboardBuilder`, one key per position in KeyMap order, with
+//! as many thumb keys on either hand as they have.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::sync::OnceLock;
+
+use layout::center_for_pos;
+use layout::column_for_pos;
+use layout::coords_for_pos;
+use layout::finger_for_pos;
+use layout::hand_for_pos;
+use layout::lateral_for_pos;
+use layout::row_for_pos;
+use layout::Finger;
+use layout::Hand;
+use layout::KeyMap;
+use layout::LayoutShuffleMask;
+use layout::Row;
+use layout::FINGER_SPEED;
+use layout::LAYOUT_MASK;
+
+// One physical key. Columns count out from the middle of the board on each
+// hand, and `coords` are in key widths, x to the right and y down. `lateral`
+// keys are the ones a finger has to stretch sideways for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Key
+{
+	pub hand:    Hand,
+	pub finger:  Finger,
+	pub row:     Row,
+	pub column:  u8,
+	pub center:  bool,
+	pub lateral: bool,
+	pub coords:  (f32, f32),
+}
+
+// `speeds` is how fast each finger presses keys, relative to the index
+// finger, indexed by `Finger`.
+#[derive(Clone, Debug)]
+pub struct Keyboard<const N: usize = 34>
+{
+	keys:   KeyMap<Key, N>,
+	mask:   LayoutShuffleMask<N>,
+	speeds: [f32; 5],
+}
+
+pub struct KeyboardBuilder
+{
+	keys:   Vec<Key>,
+	pinned: Vec<usize>,
+	speeds: [f32; 5],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum KeyboardError
+{
+	WrongKeyCount { expected: usize, found: usize },
+	PositionOutOfRange(usize),
+}
+
+impl Key
+{
+	// A key that is neither in a centre column nor a stretch.
+	pub fn new(hand: Hand, finger: Finger, row: Row, column: u8, coords: (f32, f32))
+	-> Key
+	{
+		Key { hand, finger, row, column, center: false, lateral: false, coords }
+	}
+}
+
+impl Keyboard
+{
+	// The 34-key board that the rest of the crate assumes, with `LAYOUT_MASK`.
+	// Position 32 is the left thumb and 33 the right one.
+	pub fn default_split()
+	-> Keyboard
+	{
+		Keyboard::split_builder().build().unwrap()
+	}
+
+	// The default board with the left thumb key pinned, so that whatever a
+	// layout has there stays put. The built-in layouts leave it empty, which
+	// leaves space on the right thumb as the only thumb key.
+	pub fn one_thumb()
+	-> Keyboard
+	{
+		Keyboard::split_builder().pin(32).build().unwrap()
+	}
+
+	// `default_split`, built the first time it is asked for and shared after
+	// that, for the lookups that happen on every score.
+	pub fn shared_default()
+	-> &'static Keyboard
+	{
+		static DEFAULT: OnceLock<Keyboard> = OnceLock::new();
+		DEFAULT.get_or_init(Keyboard::default_split)
+	}
+
+	fn split_builder()
+	-> KeyboardBuilder
+	{
+		let mut builder = KeyboardBuilder::new();
+		for pos in 0..34 {
+			builder = builder.key(Key {
+				hand: hand_for_pos(pos).unwrap(),
+				finger: finger_for_pos(pos).unwrap(),
+				row: row_for_pos(pos).unwrap(),
+				column: column_for_pos(pos).unwrap(),
+				center: center_for_pos(pos).unwrap(),
+				lateral: lateral_for_pos(pos).unwrap(),
+				coords: coords_for_pos(pos).unwrap(),
+			});
+		}
+		for pos in (0..34).filter(|&pos| !LAYOUT_MASK.is_swappable(pos)) {
+			builder = builder.pin(pos);
+		}
+		builder
+	}
+}
+
+impl <const N: usize> Keyboard<N>
+{
+	pub fn key(&self, pos: usize)
+	-> Option<&Key>
+	{
+		self.keys.0.get(pos)
+	}
+
+	pub fn keys(&self)
+	-> &KeyMap<Key, N>
+	{
+		&self.keys
+	}
+
+	// The thumb keys' positions and the hand each belongs to, in position
+	// order.
+	pub fn thumbs(&self)
+	-> Vec<(usize, Hand)>
+	{
+		self.keys.0.iter().enumerate()
+			.filter(|&(_, key)| key.finger == Finger::Thumb)
+			.map(|(pos, key)| (pos, key.hand))
+			.collect()
+	}
+
+	// The positions the optimizer may swap.
+	pub fn mask(&self)
+	-> &LayoutShuffleMask<N>
+	{
+		&self.mask
+	}
+
+	pub fn finger_speeds(&self)
+	-> &[f32; 5]
+	{
+		&self.speeds
+	}
+
+	// The home row key of the finger that presses `pos`, leaving out the
+	// centre columns, or `pos` itself for a thumb key or a finger with no home
+	// key. `None` if `pos` is not on the board.
+	pub fn home_pos(&self, pos: usize)
+	-> Option<usize>
+	{
+		let key = self.key(pos)?;
+		if key.row == Row::Thumb {
+			return Some(pos);
+		}
+		let home = self.keys.enumerate_positions()
+			.find(|&(_, k)| k.row == Row::Home && !k.center && k.hand == key.hand && k.finger == key.finger)
+			.map_or(pos, |(i, _)| i);
+		Some(home)
+	}
+
+	// How far, in key widths, the finger that presses `pos` travels to it from
+	// its home key.
+	pub fn travel_to(&self, pos: usize)
+	-> f32
+	{
+		match (self.home_pos(pos).and_then(|h| self.key(h)), self.key(pos)) {
+			(Some(home), Some(key)) => {
+				let ((x0, y0), (x1, y1)) = (home.coords, key.coords);
+				((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt()
+			},
+			_ => 0.0,
+		}
+	}
+}
+
+impl KeyboardBuilder
+{
+	pub fn new()
+	-> KeyboardBuilder
+	{
+		KeyboardBuilder {
+			keys: Vec::new(),
+			pinned: Vec::new(),
+			speeds: FINGER_SPEED,
+		}
+	}
+
+	// Adds the key at the next position.
+	pub fn key(mut self, key: Key)
+	-> KeyboardBuilder
+	{
+		self.keys.push(key);
+		self
+	}
+
+	// Adds a thumb key for `hand` at the next position. Thumb keys are off the
+	// grid, in column 6 like the default board's.
+	pub fn thumb(self, hand: Hand, coords: (f32, f32))
+	-> KeyboardBuilder
+	{
+		self.key(Key::new(hand, Finger::Thumb, Row::Thumb, 6, coords))
+	}
+
+	// Keeps the optimizer from moving whatever is at `pos`.
+	pub fn pin(mut self, pos: usize)
+	-> KeyboardBuilder
+	{
+		self.pinned.push(pos);
+		self
+	}
+
+	// Replaces the default `FINGER_SPEED`.
+	pub fn finger_speeds(mut self, speeds: [f32; 5])
+	-> KeyboardBuilder
+	{
+		self.speeds = speeds;
+		self
+	}
+
+	// Fails unless exactly `N` keys were added.
+	pub fn build<const N: usize>(&self)
+	-> Result<Keyboard<N>, KeyboardError>
+	{
+		let keys = <[Key; N]>::try_from(&self.keys[..])
+			.map_err(|_| KeyboardError::WrongKeyCount { expected: N, found: self.keys.len() })?;
+
+		let mut swappable = [true; N];
+		for &pos in &self.pinned {
+			match swappable.get_mut(pos) {
+				Some(s) => *s = false,
+				None => return Err(KeyboardError::PositionOutOfRange(pos)),
+			}
+		}
+
+		Ok(Keyboard {
+			keys: KeyMap(keys),
+			mask: LayoutShuffleMask(KeyMap(swappable)),
+			speeds: self.speeds,
+		})
+	}
+}
+
+impl Default for KeyboardBuilder
+{
+	fn default()
+	-> KeyboardBuilder
+	{
+		KeyboardBuilder::new()
+	}
+}
+
+impl fmt::Display for KeyboardError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		match *self {
+			KeyboardError::WrongKeyCount { expected, found } =>
+				write!(f, "expected {} keys, found {}", expected, found),
+			KeyboardError::PositionOutOfRange(pos) => write!(f, "position {} is not on the keyboard", pos),
+		}
+	}
+}
+
+impl Error for KeyboardError {}
@@ -1,9 +1,44 @@
-/// Data structures and methods for creating and shuffling keyboard layouts.
+//! Data structures and methods for creating and shuffling keyboard layouts.
 
 extern crate rand;
+#[cfg(feature = "serde")]
+extern crate serde;
 
+use std::array;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
 use std::fmt;
-use self::rand::random;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::iter;
+use std::mem;
+use std::ops::Index;
+use std::ops::IndexMut;
+use std::path::Path;
+use std::slice;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use self::rand::seq::SliceRandom;
+use self::rand::Rng;
+use self::rand::thread_rng;
+#[cfg(feature = "serde")]
+use self::serde::de::Error as DeError;
+#[cfg(feature = "serde")]
+use self::serde::Deserialize;
+#[cfg(feature = "serde")]
+use self::serde::Deserializer;
+#[cfg(feature = "serde")]
+use self::serde::Serialize;
+#[cfg(feature = "serde")]
+use self::serde::Serializer;
+
+use corpus::Ngrams;
+use keyboard::Key;
+use keyboard::Keyboard;
 
 /* ----- *
  * TYPES *
@@ -16,54 +51,94 @@ use self::rand::random;
 // 22 23 24 25 26 | 27 28 29 30 31
 //
 //             32 | 33 (thumb keys)
+//
+// 32 and 33 are the left and right thumbs, with `Finger::Thumb` and
+// `Row::Thumb`. A thumb press never makes a same-finger bigram or a roll with
+// another key, and metrics should leave the thumbs out the same way.
+//
+// Other geometries can pick a different key count N; the statics below all
+// describe the default 34-key board.
 
-pub struct KeyMap<T>(pub [T; 34]);
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct KeyMap<T, const N: usize = 34>(pub [T; N]);
 
-impl <T: Copy> Clone for KeyMap<T>
+impl <T: Copy, const N: usize> Clone for KeyMap<T, N>
 {
 	fn clone(&self)
-	-> KeyMap<T>
+	-> KeyMap<T, N>
 	{
 		KeyMap(self.0)
 	}
 }
 
-#[derive(Clone)]
-pub struct Layer(KeyMap<char>);
+impl <T: Default, const N: usize> Default for KeyMap<T, N>
+{
+	fn default()
+	-> KeyMap<T, N>
+	{
+		KeyMap(array::from_fn(|_| T::default()))
+	}
+}
 
-#[derive(Clone)]
-pub struct Layout(Layer, Layer);
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Layer<const N: usize = 34>(KeyMap<char, N>);
+
+// The lower and upper layers, then any further layers, such as an AltGr or a
+// number layer, in the order they are reached.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Layout<const N: usize = 34>(Layer<N>, Layer<N>, Vec<Layer<N>>);
 
 pub struct LayoutPermutations
 {
 	orig_layout: Layout,
+	swappable: Vec<usize>,
 	swap_idx: Vec<usize>,
 	started: bool,
 }
 
-pub struct LayoutPosMap([Option<KeyPress>; 128]);
-
+// Where each character of a layout sits. ASCII characters are looked up in a
+// dense table and others in a hash map, whose values are always `Some` so
+// that both can hand out an `&Option<KeyPress>`. `keys` are the keys the
+// layout was typed on, for `swap_positions`.
 #[derive(Clone)]
-pub struct LayoutShuffleMask(KeyMap<bool>);
+pub struct LayoutPosMap
+{
+	ascii: [Option<KeyPress>; 128],
+	other: HashMap<char, Option<KeyPress>>,
+	keys:  Vec<Key>,
+}
+
+// Stand-ins for characters that a layout has no key for, such as `e` for `é`.
+// They are only used when the layout lacks the character itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transliteration(pub HashMap<char, char>);
+
+#[derive(Clone, Debug)]
+pub struct LayoutShuffleMask<const N: usize = 34>(pub KeyMap<bool, N>);
 
-#[derive(Clone, Copy, PartialEq)]
+// Ordered from the outside of the hand in, so that `a < b` when going from
+// `a` to `b` rolls toward the thumb.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Finger 
 {
-	Thumb,
-	Index,
-	Middle,
-	Ring,
 	Pinky,
+	Ring,
+	Middle,
+	Index,
+	Thumb,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Hand
 {
 	Left,
 	Right,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Row
 {
 	Top,
@@ -72,15 +147,132 @@ pub enum Row
 	Thumb,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct KeyPress
 {
-	pub kc:     char,
+	pub kc:      char,
+	pub pos:     usize,
+	pub shifted: bool,
+	pub layer:   usize,
+	pub finger:  Finger,
+	pub hand:    Hand,
+	pub row:     Row,
+	pub column:  u8,
+	pub center:  bool,
+}
+
+// A position that two layouts fill differently, with the (lower, upper)
+// characters on each.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyDiff
+{
 	pub pos:    usize,
-	pub finger: Finger,
 	pub hand:   Hand,
+	pub finger: Finger,
 	pub row:    Row,
-	pub center: bool,
+	pub before: (char, char),
+	pub after:  (char, char),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutError
+{
+	DuplicateKey { layer: usize, key: char },
+	ShiftMismatch { pos: usize, lower: char, upper: char },
+	EmptyKey(usize),
+	NonAscii { pos: usize, layer: usize, key: char },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FromCharsError
+{
+	WrongKeyCount(usize),
+	DuplicateKey(char),
+	NonAscii(char),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SwapError
+{
+	NotFound(char),
+	Locked(char),
+}
+
+// A string that does not name a `Finger`, `Hand` or `Row`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnknownName(pub String);
+
+// A character that the layout has no key for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnknownChar(pub char);
+
+// What `LayoutPosMap::presses_with` does with `\n`, `\t` and `\r` when the
+// layout has no key of its own for them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum WhitespacePolicy
+{
+	#[default]
+	Skip,
+	// Type them on the key that types a space.
+	Thumb,
+}
+
+// Which pairs of swappable positions a shuffle may swap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SwapScope
+{
+	#[default]
+	Any,
+	SameHand,
+	SameRow,
+}
+
+// A change to a layout that can be undone. `Cycle3(i, j, k)` moves the keys
+// at `i` to `j`, `j` to `k` and `k` to `i`. Both move every layer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Move
+{
+	Swap(usize, usize),
+	Cycle3(usize, usize, usize),
+}
+
+impl Move
+{
+	// The swaps `Layout::apply` makes for this move, in order.
+	pub fn swaps(&self)
+	-> Vec<(usize, usize)>
+	{
+		match *self {
+			Move::Swap(i, j) => vec![(i, j)],
+			Move::Cycle3(i, j, k) => vec![(i, k), (j, k)],
+		}
+	}
+}
+
+// A position past the last key of the layout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionError(pub usize);
+
+// Stands in for an empty key in `to_full_string`, where a `'\0'` would not
+// show; `FromStr` reads it back as an empty key.
+pub const EMPTY_KEY: &str = "·";
+
+#[derive(Debug)]
+pub struct ParseLayoutError
+{
+	pub line: usize,
+	pub col:  usize,
+	pub kind: ParseLayoutErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ParseLayoutErrorKind
+{
+	WrongRowCount(usize),
+	WrongKeyCount { expected: usize, found: usize },
+	InvalidKey(String),
+	DuplicateKey(char),
+	NonAscii(char),
 }
 
 /* ------- *
@@ -95,7 +287,8 @@ pub static INIT_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['J', 'C', 'Y', 'F', 'K',   'Z', 'L', '<', 'U', 'Q', '+',
 	              'R', 'S', 'T', 'H', 'D',   'M', 'N', 'A', 'I', 'O', '"',
 	              '?', 'V', 'G', 'P', 'B',   'X', 'W', '>', ':', '_',
-	              'E', ' '])));
+	              'E', ' '])),
+	Vec::new());
 
 pub static QWERTY_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['q', 'w', 'e', 'r', 't',   'y', 'u', 'i', 'o', 'p', '-',
@@ -105,17 +298,19 @@ pub static QWERTY_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['Q', 'W', 'E', 'R', 'T',   'Y', 'U', 'I', 'O', 'P', '_',
 	              'A', 'S', 'D', 'F', 'G',   'H', 'J', 'K', 'L', ':', '"',
 	              'Z', 'X', 'C', 'V', 'B',   'N', 'M', '<', '>', '?',
-	              '\0', ' '])));
+	              '\0', ' '])),
+	Vec::new());
 
 pub static DVORAK_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['\'', ',', '.', 'p', 'y',   'f', 'g', 'c', 'r', 'l', '/',
 	              'a', 'o', 'e', 'u', 'i',   'd', 'h', 't', 'n', 's', '-',
 	              ';', 'q', 'j', 'k', 'x',   'b', 'm', 'w', 'v', 'z',
 	              '\0', ' '])),
-	Layer(KeyMap(['"', ',', '.', 'P', 'Y',   'F', 'G', 'C', 'R', 'L', '?',
+	Layer(KeyMap(['"', '<', '>', 'P', 'Y',   'F', 'G', 'C', 'R', 'L', '?',
 	              'A', 'O', 'E', 'U', 'I',   'D', 'H', 'T', 'N', 'S', '_',
 	              ':', 'Q', 'J', 'K', 'X',   'B', 'M', 'W', 'V', 'Z',
-	              '\0', ' '])));
+	              '\0', ' '])),
+	Vec::new());
 
 pub static COLEMAK_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['q', 'w', 'f', 'p', 'g',   'j', 'l', 'u', 'y', ';', '-',
@@ -125,7 +320,8 @@ pub static COLEMAK_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['Q', 'W', 'F', 'P', 'G',   'J', 'L', 'U', 'Y', ':', '_',
 	              'A', 'R', 'S', 'T', 'D',   'H', 'N', 'E', 'I', 'O', '"',
 	              'Z', 'X', 'C', 'V', 'B',   'K', 'M', '<', '>', '?',
-	              '\0', ' '])));
+	              '\0', ' '])),
+	Vec::new());
 
 pub static QGMLWY_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['q', 'g', 'm', 'l', 'w',   'y', 'f', 'u', 'b', ';', '-',
@@ -135,7 +331,8 @@ pub static QGMLWY_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['Q', 'G', 'M', 'L', 'W',   'Y', 'F', 'U', 'B', ':', '_',
 	              'D', 'S', 'T', 'N', 'R',   'I', 'A', 'E', 'O', 'H', '"',
 	              'Z', 'X', 'C', 'V', 'J',   'K', 'P', '<', '>', '?',
-	              '\0', ' '])));
+	              '\0', ' '])),
+	Vec::new());
 
 pub static WORKMAN_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['q', 'd', 'r', 'w', 'b',   'j', 'f', 'u', 'p', ';', '-',
@@ -145,7 +342,8 @@ pub static WORKMAN_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['Q', 'D', 'R', 'W', 'B',   'J', 'F', 'U', 'P', ':', '_',
 	              'A', 'S', 'H', 'T', 'G',   'Y', 'N', 'E', 'O', 'I', '"',
 	              'Z', 'X', 'M', 'C', 'V',   'K', 'L', '<', '>', '?',
-	              '\0', ' '])));
+	              '\0', ' '])),
+	Vec::new());
 
 pub static MALTRON_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['q', 'p', 'y', 'c', 'b',   'v', 'm', 'u', 'z', 'l', '=',
@@ -155,7 +353,11 @@ pub static MALTRON_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['Q', 'P', 'Y', 'C', 'B',   'V', 'M', 'U', 'Z', 'L', '+',
 	              'A', 'N', 'I', 'S', 'F',   'D', 'T', 'H', 'O', 'R', '"',
 	              '<', '>', 'J', 'G', '?',   ':', 'W', 'K', '_', 'X',
-	              'E', ' '])));
+	              'E', ' '])),
+	Vec::new());
+
+// Layouts whose upper layer pairs punctuation its own way on purpose.
+pub static SHIFT_EXCEPTIONS: [&Layout; 1] = [&MTGAP_LAYOUT];
 
 pub static MTGAP_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['y', 'p', 'o', 'u', '-',   'b', 'd', 'l', 'c', 'k', 'j',
@@ -165,17 +367,19 @@ pub static MTGAP_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['Y', 'P', 'O', 'U', ':',   'B', 'D', 'L', 'C', 'K', 'J',
 	              'I', 'N', 'E', 'A', ';',   'M', 'H', 'T', 'S', 'R', 'V',
 	              '&', '?', '*', '=', '<',   '>', 'F', 'W', 'G', 'X',
-	              'Z', ' '])));
+	              'Z', ' '])),
+	Vec::new());
 
 pub static CAPEWELL_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['.', 'y', 'w', 'd', 'f',   'j', 'p', 'l', 'u', 'q', '/',
 	              'a', 'e', 'r', 's', 'g',   'b', 't', 'n', 'i', 'o', '-',
-	              'x', 'z', 'c', 'v', ';',   'k', 'w', 'h', ',', '\'',
+	              'x', 'z', 'c', 'v', ';',   'k', 'm', 'h', ',', '\'',
 	              '\0', ' '])),
 	Layer(KeyMap(['>', 'Y', 'W', 'D', 'F',   'J', 'P', 'L', 'U', 'Q', '?',
 	              'A', 'E', 'R', 'S', 'G',   'B', 'T', 'N', 'I', 'O', '_',
-	              'X', 'Z', 'C', 'V', ':',   'K', 'W', 'H', '<', '"',
-	              '\0', ' '])));
+	              'X', 'Z', 'C', 'V', ':',   'K', 'M', 'H', '<', '"',
+	              '\0', ' '])),
+	Vec::new());
 
 pub static ARENSITO_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['q', 'l', ',', 'p', '\0',  '\0', 'f', 'u', 'd', 'k', '\0',
@@ -185,19 +389,14 @@ pub static ARENSITO_LAYOUT: Layout = Layout(
 	Layer(KeyMap(['Q', 'L', '<', 'P', '\0',  '\0', 'F', 'U', 'D', 'K', '\0',
 	              'A', 'R', 'E', 'N', 'B',   'G', 'S', 'I', 'T', 'O', '\0',
 	              'Z', 'W', '>', 'H', 'J',   'V', 'C', 'Y', 'M', 'X',
-	              '\0', ' '])));
-
-// static LAYOUT_MASK: LayoutShuffleMask = LayoutShuffleMask(KeyMap([
-// 	true,  true,  true,  true,  true,  true,  true,  true,  true,  true,  false,
-// 	true,  true,  true,  true,  true,  true,  true,  true,  true,  true,  true,
-// 	true,  true,  true,  true,  true,  true,  true,  true,  true,  true,
-// 	false]));
-static LAYOUT_MASK_SWAP_OFFSETS: [usize; 33] = [
-	0, 0, 0, 0, 0,    0, 0, 0, 0, 0,
-	1, 1, 1, 1, 1,    1, 1, 1, 1, 1, 1,
-	1, 1, 1, 1, 1,    1, 1, 1, 1, 1,
-	1, 1];
-static LAYOUT_MASK_NUM_SWAPPABLE: usize = 33;
+	              '\0', ' '])),
+	Vec::new());
+
+pub static LAYOUT_MASK: LayoutShuffleMask = LayoutShuffleMask(KeyMap([
+	true,  true,  true,  true,  true,     true,  true,  true,  true,  true,  false,
+	true,  true,  true,  true,  true,     true,  true,  true,  true,  true,  true,
+	true,  true,  true,  true,  true,     true,  true,  true,  true,  true,
+	true,  true]));
 
 static KEY_FINGERS: KeyMap<Finger> = KeyMap([
 	Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Index,    Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
@@ -214,218 +413,2889 @@ static KEY_ROWS: KeyMap<Row> = KeyMap([
 	Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,      Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,   Row::Home,
 	Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom,    Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom, Row::Bottom,
 	Row::Thumb, Row::Thumb]);
+// Columns count out from the middle of the board on each hand, so the centre
+// columns are 0 and the pinky columns 4, with the right hand's extra column
+// at 5. The thumb keys are not part of the grid and get column 6.
+static KEY_COLUMNS: KeyMap<u8> = KeyMap([
+	4, 3, 2, 1, 0,    0, 1, 2, 3, 4, 5,
+	4, 3, 2, 1, 0,    0, 1, 2, 3, 4, 5,
+	4, 3, 2, 1, 0,    0, 1, 2, 3, 4,
+	6, 6]);
 static KEY_CENTER_COLUMN: KeyMap<bool> = KeyMap([
 	false, false, false, false, true,    true, false, false, false, false, false,
 	false, false, false, false, true,    true, false, false, false, false, false,
 	false, false, false, false, true,    true, false, false, false, false,
 	false, false]);
 
+// Keys the index fingers have to stretch sideways for. On this board they are
+// the centre columns, but other boards may differ.
+static KEY_LATERAL: KeyMap<bool> = KeyMap([
+	false, false, false, false, true,    true, false, false, false, false, false,
+	false, false, false, false, true,    true, false, false, false, false, false,
+	false, false, false, false, true,    true, false, false, false, false,
+	false, false]);
+
+// Key centres in key widths on a standard row-staggered board, x to the right
+// and y down from the top letter row. The home row is shifted a quarter key
+// and the bottom row three quarters, and the thumb keys sit on the space bar.
+static KEY_COORDS: KeyMap<(f32, f32)> = KeyMap([
+	(0.0, 0.0),  (1.0, 0.0),  (2.0, 0.0),  (3.0, 0.0),  (4.0, 0.0),     (5.0, 0.0),  (6.0, 0.0),  (7.0, 0.0),  (8.0, 0.0),  (9.0, 0.0),  (10.0, 0.0),
+	(0.25, 1.0), (1.25, 1.0), (2.25, 1.0), (3.25, 1.0), (4.25, 1.0),    (5.25, 1.0), (6.25, 1.0), (7.25, 1.0), (8.25, 1.0), (9.25, 1.0), (10.25, 1.0),
+	(0.75, 2.0), (1.75, 2.0), (2.75, 2.0), (3.75, 2.0), (4.75, 2.0),    (5.75, 2.0), (6.75, 2.0), (7.75, 2.0), (8.75, 2.0), (9.75, 2.0),
+	(3.5, 3.0),  (6.5, 3.0)]);
+
+// How good each position is to type on, from 0.0 to 1.0, for
+// `greedy_seed`: the thumbs, then the home row, with the strong fingers
+// ahead of the weak ones on every row and the stretches last.
+static KEY_QUALITY: KeyMap<f32> = KeyMap([
+	0.3,  0.5,  0.7,  0.6,  0.3,     0.3,  0.6,  0.7,  0.5,  0.3,  0.1,
+	0.7,  0.8,  0.9,  0.95, 0.5,     0.5,  0.95, 0.9,  0.8,  0.7,  0.4,
+	0.2,  0.35, 0.5,  0.55, 0.25,    0.25, 0.55, 0.5,  0.35, 0.2,
+	1.0,  1.0]);
+
+// How fast each finger presses keys, relative to the index finger, indexed
+// by `Finger` from the pinky to the thumb.
+pub static FINGER_SPEED: [f32; 5] = [0.6, 0.75, 0.9, 1.0, 0.85];
+
 pub static KP_NONE: Option<KeyPress> = None;
 
+// The default `Transliteration`. Capitals of the letters are added from these.
+static TRANSLITERATIONS: [(char, char); 36] = [
+	('à', 'a'), ('á', 'a'), ('â', 'a'), ('ã', 'a'), ('ä', 'a'), ('å', 'a'),
+	('ç', 'c'), ('è', 'e'), ('é', 'e'), ('ê', 'e'), ('ë', 'e'),
+	('ì', 'i'), ('í', 'i'), ('î', 'i'), ('ï', 'i'), ('ñ', 'n'),
+	('ò', 'o'), ('ó', 'o'), ('ô', 'o'), ('õ', 'o'), ('ö', 'o'),
+	('ù', 'u'), ('ú', 'u'), ('û', 'u'), ('ü', 'u'), ('ý', 'y'), ('ÿ', 'y'),
+	('\u{2018}', '\''), ('\u{2019}', '\''), ('\u{201c}', '"'), ('\u{201d}', '"'),
+	('\u{2013}', '-'), ('\u{2014}', '-'), ('\u{2026}', '.'), ('\u{a0}', ' '), ('\u{2032}', '\'')];
+
 static LAYOUT_FILE_IDXS: KeyMap<usize> = KeyMap([
 	0,  1,  2,  3,  4,     6,  7,  8,  9,  10, 11,
 	13, 14, 15, 16, 17,    19, 20, 21, 22, 23, 24,
 	26, 27, 28, 29, 30,    32, 33, 34, 35, 36, 37, 38]);
 
+// The position each key moves to in `Layout::mirror`. The outer right column
+// has no counterpart on the left, so it stays put, as do the thumb keys.
+static MIRROR_POSITIONS: KeyMap<usize> = KeyMap([
+	9,  8,  7,  6,  5,     4,  3,  2,  1,  0,  10,
+	20, 19, 18, 17, 16,    15, 14, 13, 12, 11, 21,
+	31, 30, 29, 28, 27,    26, 25, 24, 23, 22,
+	32, 33]);
+
+// Rows of the grid printed by `Display for Layer`, as (first position, keys
+// left of the `|`, keys right of the `|`).
+pub static GRID_ROWS: [(usize, usize, usize); 4] = [
+	(0,  5, 6),
+	(11, 5, 6),
+	(22, 5, 5),
+	(32, 1, 1)];
+
+// `Layout::heatmap`'s scale from the least used key to the most used: ANSI
+// 256-colour backgrounds from blue through green to red, and characters from
+// sparse to dense.
+static HEAT_COLORS: [u8; 10] = [21, 27, 33, 39, 45, 49, 118, 226, 214, 196];
+static HEAT_DENSITY: [char; 10] = [' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
 /* ----- *
  * IMPLS *
  * ----- */
 
-impl Layout
+impl <T, const N: usize> KeyMap<T, N>
 {
-	pub fn from_string(s: &str)
-	-> Layout
+	pub fn iter(&self)
+	-> slice::Iter<'_, T>
 	{
-		let s: Vec<char> = s.chars().collect();
-		let mut lower: [char; 34] = ['\0'; 34];
-		let mut upper: [char; 34] = ['\0'; 34];
-		
-		for i in 0..34 {
-			let file_i = LAYOUT_FILE_IDXS.0[i];
-			lower[i] = *s.get(file_i).unwrap_or(&'\0');
-			upper[i] = *s.get(file_i + 40).unwrap_or(&'\0');
-		}
+		self.0.iter()
+	}
 
-		Layout(Layer(KeyMap(lower)), Layer(KeyMap(upper)))
+	pub fn iter_mut(&mut self)
+	-> slice::IterMut<'_, T>
+	{
+		self.0.iter_mut()
 	}
 
-	pub fn shuffle(&mut self, times: usize)
+	pub fn len(&self)
+	-> usize
 	{
-		for _ in 0..times {
-			let (i, j) = Layout::shuffle_position();
-			let Layout(ref mut lower, ref mut upper) = *self;
-			lower.swap(i, j);
-			upper.swap(i, j);
-		}
+		N
 	}
 
-	pub fn get_position_map(&self)
-	-> LayoutPosMap
+	pub fn is_empty(&self)
+	-> bool
 	{
-		let Layout(ref lower, ref upper) = *self;
-		let mut map = [None; 128];
-		lower.fill_position_map(&mut map);
-		upper.fill_position_map(&mut map);
+		N == 0
+	}
 
-		LayoutPosMap(map)
+	// Each entry along with its position.
+	pub fn enumerate_positions(&self)
+	-> impl Iterator<Item = (usize, &T)>
+	{
+		self.0.iter().enumerate()
 	}
+}
 
-	fn shuffle_position() 
-	-> (usize, usize)
+impl <T, const N: usize> Index<usize> for KeyMap<T, N>
+{
+	type Output = T;
+
+	fn index(&self, pos: usize)
+	-> &T
 	{
-		let mut i = random::<usize>() % LAYOUT_MASK_NUM_SWAPPABLE;
-		let mut j = random::<usize>() % (LAYOUT_MASK_NUM_SWAPPABLE - 1);
-		if j >= i {
-			j += 1;
-		}
-		i += LAYOUT_MASK_SWAP_OFFSETS[i];
-		j += LAYOUT_MASK_SWAP_OFFSETS[j];
+		&self.0[pos]
+	}
+}
 
-		(i, j)
+impl <T, const N: usize> IndexMut<usize> for KeyMap<T, N>
+{
+	fn index_mut(&mut self, pos: usize)
+	-> &mut T
+	{
+		&mut self.0[pos]
 	}
 }
 
-impl Layer
+impl <'a, T, const N: usize> IntoIterator for &'a KeyMap<T, N>
 {
-	fn swap(&mut self, i: usize, j: usize)
+	type Item = &'a T;
+	type IntoIter = slice::Iter<'a, T>;
+
+	fn into_iter(self)
+	-> slice::Iter<'a, T>
 	{
-		let Layer(KeyMap(ref mut layer)) = *self;
-		let temp = layer[i];
-		layer[i] = layer[j];
-		layer[j] = temp;
-	}
-
-	fn fill_position_map(&self, map: &mut [Option<KeyPress>; 128])
-	{
-		let Layer(KeyMap(ref layer)) = *self;
-		let KeyMap(ref fingers) = KEY_FINGERS;
-		let KeyMap(ref hands) = KEY_HANDS;
-		let KeyMap(ref rows) = KEY_ROWS;
-		let KeyMap(ref centers) = KEY_CENTER_COLUMN;
-		for (i, c) in layer.into_iter().enumerate() {
-			if *c < (128 as char) {
-				map[*c as usize] = Some(KeyPress {
-					kc: *c,
-					pos: i,
-					finger: fingers[i],
-					hand: hands[i],
-					row: rows[i],
-					center: centers[i],
-				});
-			}
-		}
+		self.iter()
 	}
 }
 
-impl LayoutPosMap
+impl <'a, T, const N: usize> IntoIterator for &'a mut KeyMap<T, N>
 {
-	pub fn get_key_position(&self, kc: char)
-	-> &Option<KeyPress>
+	type Item = &'a mut T;
+	type IntoIter = slice::IterMut<'a, T>;
+
+	fn into_iter(self)
+	-> slice::IterMut<'a, T>
 	{
-		let LayoutPosMap(ref map) = *self;
-		if kc < (128 as char) {
-			&map[kc as usize]
-		} else {
-			&KP_NONE
-		}
+		self.iter_mut()
 	}
 }
 
-impl LayoutPermutations
+impl <T, const N: usize> IntoIterator for KeyMap<T, N>
 {
-	pub fn new(layout: &Layout, depth: usize)
-	-> LayoutPermutations
+	type Item = T;
+	type IntoIter = array::IntoIter<T, N>;
+
+	fn into_iter(self)
+	-> array::IntoIter<T, N>
 	{
-		let mut swaps = Vec::with_capacity(depth * 2);
-		for _ in 0..(depth * 2) {
-			swaps.push(0);
-		}
-		LayoutPermutations {
-			orig_layout: layout.clone(),
-			swap_idx: swaps,
-			started: false,
-		}
+		IntoIterator::into_iter(self.0)
 	}
 }
 
-impl Iterator for LayoutPermutations
+impl <const N: usize> Layout<N>
 {
-	type Item = Layout;
+	// Derives the upper layer from `lower` with `Layer::shifted`.
+	pub fn from_lower(lower: Layer<N>)
+	-> Layout<N>
+	{
+		let upper = lower.shifted();
+		Layout::two_layer(lower, upper)
+	}
 
-	fn next(&mut self)
-	-> Option<Layout>
+	pub fn two_layer(lower: Layer<N>, upper: Layer<N>)
+	-> Layout<N>
 	{
-		let mut some = false;
-		let mut idx = 0;
-		let mut val = 0;
+		Layout(lower, upper, Vec::new())
+	}
 
-		if self.started {
-			for (i, e) in self.swap_idx.iter_mut().enumerate() {
-				if *e + 1 < LAYOUT_MASK_NUM_SWAPPABLE - i {
-					*e += 1;
-					some = true;
-					idx = i;
-					val = *e;
-					break;
-				}
+	// Adds `layer` after the existing ones.
+	pub fn with_layer(mut self, layer: Layer<N>)
+	-> Layout<N>
+	{
+		self.2.push(layer);
+		self
+	}
+
+	// Every layer, lower and upper first.
+	pub fn layers(&self)
+	-> impl Iterator<Item = &Layer<N>>
+	{
+		iter::once(&self.0).chain(iter::once(&self.1)).chain(self.2.iter())
+	}
+
+	pub fn layer(&self, i: usize)
+	-> Option<&Layer<N>>
+	{
+		match i {
+			0 => Some(&self.0),
+			1 => Some(&self.1),
+			_ => self.2.get(i - 2),
+		}
+	}
+
+	pub fn layer_count(&self)
+	-> usize
+	{
+		2 + self.2.len()
+	}
+
+	// The position map for this layout typed on `keyboard`. The layers go in
+	// from the last to the lower one, so that a character on several layers
+	// is typed on the earliest of them.
+	pub fn get_position_map_on(&self, keyboard: &Keyboard<N>, transliteration: &Transliteration)
+	-> LayoutPosMap
+	{
+		let layers: Vec<&Layer<N>> = self.layers().collect();
+		let mut map = LayoutPosMap {
+			ascii: [None; 128],
+			other: HashMap::new(),
+			keys: keyboard.keys().0.to_vec(),
+		};
+		for (n, layer) in layers.iter().enumerate().rev() {
+			for kp in layer.key_presses(keyboard, n) {
+				map.insert(kp.kc, kp);
 			}
-		} else {
-			self.started = true;
-			some = true;
-			idx = 1;
-			val = 0;
 		}
+		for (&from, &to) in &transliteration.0 {
+			if let (None, &Some(kp)) = (*map.get_key_position(from), map.get_key_position(to)) {
+				map.insert(from, kp);
+			}
+		}
+		map
+	}
 
-		if some {
-			for i in 0..idx {
-				self.swap_idx[i] =  val + idx - i;
+	// The character at `pos` on the upper layer if `shifted`, else on the
+	// lower one.
+	pub fn char_at(&self, pos: usize, shifted: bool)
+	-> Result<char, PositionError>
+	{
+		let layer = if shifted { &self.1 } else { &self.0 };
+		layer.0.0.get(pos).cloned().ok_or(PositionError(pos))
+	}
+
+	pub fn set_char_at(&mut self, pos: usize, shifted: bool, c: char)
+	-> Result<(), PositionError>
+	{
+		if pos >= N {
+			return Err(PositionError(pos));
+		}
+		let layer = if shifted { &mut self.1 } else { &mut self.0 };
+		layer.0[pos] = c;
+		Ok(())
+	}
+
+	// Where `c` is, looking through the layers in order and each layer in
+	// position order.
+	pub fn position_of(&self, c: char)
+	-> Option<usize>
+	{
+		self.layers().find_map(|layer| layer.keys().iter().position(|&k| k == c))
+	}
+
+	pub fn lower(&self)
+	-> &Layer<N>
+	{
+		&self.0
+	}
+
+	pub fn upper(&self)
+	-> &Layer<N>
+	{
+		&self.1
+	}
+
+	// The number of positions whose lower-layer character differs.
+	pub fn positional_difference(&self, other: &Layout<N>)
+	-> usize
+	{
+		let (a, b) = (self.lower().keys(), other.lower().keys());
+		a.iter().zip(b.iter()).filter(|&(x, y)| x != y).count()
+	}
+
+	// The fewest swaps that turn the lower layer into `other`'s, or `None` if
+	// the two are not arrangements of the same distinct characters. Each cycle
+	// of the permutation between them takes one swap less than its length.
+	pub fn min_swaps_to(&self, other: &Layout<N>)
+	-> Option<usize>
+	{
+		let (a, b) = (self.lower().keys(), other.lower().keys());
+		let mut target = HashMap::new();
+		for (i, c) in b.iter().enumerate() {
+			if target.insert(*c, i).is_some() {
+				return None;
 			}
+		}
 
-			let mut layout = self.orig_layout.clone();
-			let mut i = 0;
-			while i < self.swap_idx.len() {
-				let ref mut lower = ((layout.0).0).0;
-				let ref mut upper = ((layout.1).0).0;
-				let swap_left = self.swap_idx[i] + LAYOUT_MASK_SWAP_OFFSETS[self.swap_idx[i]];
-				let swap_right = self.swap_idx[i + 1] + LAYOUT_MASK_SWAP_OFFSETS[self.swap_idx[i + 1]];
-				lower.swap(swap_left, swap_right);
-				upper.swap(swap_left, swap_right);
-				i += 2;
+		let mut perm = [0; N];
+		let mut seen = [false; N];
+		for (i, c) in a.iter().enumerate() {
+			let j = *target.get(c)?;
+			if seen[j] {
+				return None;
 			}
+			seen[j] = true;
+			perm[i] = j;
+		}
 
-			Some(layout)
-		} else {
-			None
+		let mut visited = [false; N];
+		let mut swaps = 0;
+		for start in 0..N {
+			let mut i = start;
+			while !visited[i] {
+				visited[i] = true;
+				i = perm[i];
+				if !visited[i] {
+					swaps += 1;
+				}
+			}
 		}
+		Some(swaps)
 	}
 }
 
-impl fmt::Display for Layout
+impl <const N: usize> Layer<N>
 {
-	fn fmt(&self, f: &mut fmt::Formatter)
-	-> fmt::Result
+	// `keys` in KeyMap order.
+	pub fn from_keys(keys: [char; N])
+	-> Layer<N>
+	{
+		Layer(KeyMap(keys))
+	}
+
+	// The characters in KeyMap order.
+	pub fn keys(&self)
+	-> &[char; N]
+	{
+		let Layer(KeyMap(ref keys)) = *self;
+		keys
+	}
+
+	// The layer that shift gives on a US keyboard: capitals for letters and
+	// the usual pairs for punctuation.
+	pub fn shifted(&self)
+	-> Layer<N>
 	{
-		let Layout(ref lower, _) = *self;
-		lower.fmt(f)
+		let mut keys = self.0.clone();
+		for c in &mut keys {
+			*c = shift_char(*c);
+		}
+		Layer(keys)
 	}
 }
 
-impl fmt::Display for Layer
+impl Layout
 {
-	fn fmt(&self, f: &mut fmt::Formatter)
-	-> fmt::Result
+	pub fn from_string(s: &str)
+	-> Layout
+	{
+		let s: Vec<char> = s.chars().collect();
+		let mut lower: [char; 34] = ['\0'; 34];
+		let mut upper: [char; 34] = ['\0'; 34];
+		
+		for i in 0..34 {
+			let file_i = LAYOUT_FILE_IDXS.0[i];
+			lower[i] = *s.get(file_i).unwrap_or(&'\0');
+			upper[i] = *s.get(file_i + 40).unwrap_or(&'\0');
+		}
+
+		Layout::two_layer(Layer(KeyMap(lower)), Layer(KeyMap(upper)))
+	}
+
+	// Both layers are given in position order.
+	pub fn from_keys(lower: [char; 34], upper: [char; 34])
+	-> Layout
+	{
+		Layout::two_layer(Layer(KeyMap(lower)), Layer(KeyMap(upper)))
+	}
+
+	pub fn from_lower_keys(lower: [char; 34])
+	-> Layout
+	{
+		Layout::from_lower(Layer(KeyMap(lower)))
+	}
+
+	// The lower layer as one character per position, for example
+	// "jcyfkzl,uq=rsthdmnaio'/vgpbxw.;-e ". The upper layer is derived with
+	// `Layer::shifted`.
+	pub fn from_chars(s: &str)
+	-> Result<Layout, FromCharsError>
 	{
-		let Layer(KeyMap(ref layer)) = *self;
-		write!(f, "{} {} {} {} {} | {} {} {} {} {} {}
-{} {} {} {} {} | {} {} {} {} {} {}
-{} {} {} {} {} | {} {} {} {} {}
-        {} | {}",
-			layer[0], layer[1], layer[2], layer[3], layer[4],
-			layer[5], layer[6], layer[7], layer[8], layer[9], layer[10],
-			layer[11], layer[12], layer[13], layer[14], layer[15],
-			layer[16], layer[17], layer[18], layer[19], layer[20], layer[21],
-			layer[22], layer[23], layer[24], layer[25], layer[26],
-			layer[27], layer[28], layer[29], layer[30], layer[31],
-			layer[32], layer[33])
+		let chars: Vec<char> = s.chars().collect();
+		let lower = <[char; 34]>::try_from(&chars[..]).map_err(|_| FromCharsError::WrongKeyCount(chars.len()))?;
+		for (i, c) in lower.iter().enumerate() {
+			if !c.is_ascii() {
+				return Err(FromCharsError::NonAscii(*c));
+			}
+			if *c != '\0' && lower[..i].contains(c) {
+				return Err(FromCharsError::DuplicateKey(*c));
+			}
+		}
+		Ok(Layout::from_lower_keys(lower))
+	}
+
+	// Every layer in the grid format, lower layer first, with `EMPTY_KEY` on
+	// empty keys. Unlike `Display`, this has no labels and parses back to the
+	// same layout.
+	pub fn to_full_string(&self)
+	-> String
+	{
+		self.layers()
+			.map(|layer| layer.to_string().replace('\0', EMPTY_KEY))
+			.collect::<Vec<_>>()
+			.join("\n\n")
+	}
+
+	// The lower layer on its own, as `{}` prints it.
+	pub fn fmt_lower(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		fmt::Display::fmt(&self.0, f)
+	}
+
+	// Every layer, each under a label, as `{:#}` prints it. This is for
+	// reading; use `to_full_string` for something that parses back.
+	pub fn fmt_both(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		let Layout(ref lower, ref upper, ref extra) = *self;
+		write!(f, "lower\n{}\n\nshift\n{}", lower, upper)?;
+		for (i, layer) in extra.iter().enumerate() {
+			write!(f, "\n\n{}\n{}", layer_name(i + 2), layer)?;
+		}
+		Ok(())
+	}
+
+	// Writes `to_full_string` so that the file can be edited by hand and read
+	// back with `load_from_file`.
+	pub fn save_to_file(&self, path: &Path)
+	-> io::Result<()>
+	{
+		let mut f = File::create(path)?;
+		writeln!(f, "{}", self.to_full_string())
+	}
+
+	pub fn load_from_file(path: &Path)
+	-> io::Result<Layout>
+	{
+		let mut s = String::new();
+		File::open(path)?.read_to_string(&mut s)?;
+		Layout::from_str(&s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+	}
+
+	// The characters of `alphabet` in a uniformly random arrangement, with the
+	// upper layer derived by `Layer::shifted`. Up to 34 distinct characters
+	// may be given; positions left over are empty, starting with the ones
+	// `LAYOUT_MASK` keeps fixed. `None` if there are too many characters or
+	// one repeats.
+	pub fn random(alphabet: &[char], rng: &mut impl Rng)
+	-> Option<Layout>
+	{
+		let n = LAYOUT_MASK.0.len();
+		if alphabet.len() > n || alphabet.iter().enumerate().any(|(i, c)| alphabet[..i].contains(c)) {
+			return None;
+		}
+
+		let mut chars = alphabet.to_vec();
+		chars.shuffle(rng);
+		let mut positions: Vec<usize> = (0..n).filter(|&i| !LAYOUT_MASK.is_swappable(i)).collect();
+		let mut swappable = LAYOUT_MASK.swappable_positions();
+		swappable.shuffle(rng);
+		positions.extend(swappable);
+
+		let mut keys = ['\0'; 34];
+		for (&pos, c) in positions[n - chars.len()..].iter().zip(chars) {
+			keys[pos] = c;
+		}
+		Some(Layout::from_lower_keys(keys))
+	}
+
+	// `LAYOUT_MASK` with the keys of `locked` pinned as well, wherever they
+	// are on any layer, so that a shuffle with the mask leaves them in place.
+	// Fails on the first character that is not on the layout.
+	pub fn with_locked_chars(&self, locked: &[char])
+	-> Result<LayoutShuffleMask, UnknownChar>
+	{
+		let mut mask = LAYOUT_MASK.clone();
+		for &c in locked {
+			mask.pin(self.position_of(c).ok_or(UnknownChar(c))?);
+		}
+		Ok(mask)
+	}
+
+	// The keys of this layout rearranged so that the most common characters
+	// in `text` are on the best positions by `KEY_QUALITY`, as a quick
+	// starting point for the optimizer. Only positions `LAYOUT_MASK` lets
+	// move are rearranged, and each key keeps its characters on every layer.
+	// Ties go to the lower position.
+	pub fn greedy_seed<T: Ngrams + ?Sized>(&self, text: &T)
+	-> Layout
+	{
+		let weights = self.position_weights(text);
+		let mut by_weight = LAYOUT_MASK.swappable_positions();
+		by_weight.sort_by(|&a, &b| weights[b].cmp(&weights[a]));
+		let mut by_quality = LAYOUT_MASK.swappable_positions();
+		by_quality.sort_by(|&a, &b| KEY_QUALITY.0[b].partial_cmp(&KEY_QUALITY.0[a]).unwrap());
+
+		// `origin[pos]` is the position the key now at `pos` started on.
+		let mut layout = self.clone();
+		let mut origin: Vec<usize> = (0..layout.lower().keys().len()).collect();
+		for (&from, &to) in by_weight.iter().zip(&by_quality) {
+			let at = origin.iter().position(|&p| p == from).unwrap();
+			layout.swap_positions(at, to);
+			origin.swap(at, to);
+		}
+		layout
+	}
+
+	// Returns the swaps it made, in order, for `undo_swaps`.
+	pub fn shuffle(&mut self, times: usize)
+	-> Vec<(usize, usize)>
+	{
+		self.shuffle_seeded(times, &mut thread_rng())
+	}
+
+	// Same as `shuffle`, but draws the swaps from `rng` so that a seeded
+	// generator reproduces the same layouts.
+	pub fn shuffle_seeded(&mut self, times: usize, rng: &mut impl Rng)
+	-> Vec<(usize, usize)>
+	{
+		self.shuffle_masked(times, &LAYOUT_MASK, rng)
+	}
+
+	// Only swaps positions that `mask` marks as swappable.
+	pub fn shuffle_with_mask(&mut self, times: usize, mask: &LayoutShuffleMask)
+	-> Vec<(usize, usize)>
+	{
+		self.shuffle_masked(times, mask, &mut thread_rng())
+	}
+
+	// Same as `shuffle_with_mask`, drawing the swaps from `rng`.
+	pub fn shuffle_masked(&mut self, times: usize, mask: &LayoutShuffleMask, rng: &mut impl Rng)
+	-> Vec<(usize, usize)>
+	{
+		self.shuffle_scoped(times, mask, SwapScope::Any, rng)
+	}
+
+	// Same as `shuffle_masked`, but each swap stays within `scope`.
+	pub fn shuffle_scoped(&mut self, times: usize, mask: &LayoutShuffleMask, scope: SwapScope, rng: &mut impl Rng)
+	-> Vec<(usize, usize)>
+	{
+		let groups = mask.swap_groups(scope);
+		let mut swaps = Vec::with_capacity(times);
+		for _ in 0..times {
+			match Layout::pick_swap(&groups, rng) {
+				Some((i, j)) => {
+					self.swap_positions(i, j);
+					swaps.push((i, j));
+				},
+				None => break,
+			}
+		}
+		swaps
+	}
+
+	// Same as `shuffle_scoped`, but positions are picked in proportion to
+	// `weights`, which move along with the characters as they are swapped.
+	// The first position of each swap is drawn from all swappable positions
+	// and the second from the rest of its group.
+	pub fn shuffle_weighted(&mut self, times: usize, mask: &LayoutShuffleMask, scope: SwapScope,
+		weights: &KeyMap<u64>, rng: &mut impl Rng)
+	-> Vec<(usize, usize)>
+	{
+		let groups = mask.swap_groups(scope);
+		let mut weights = weights.clone();
+		let mut swaps = Vec::with_capacity(times);
+		for _ in 0..times {
+			match Layout::pick_weighted_swap(&groups, &weights, rng) {
+				Some((i, j)) => {
+					self.swap_positions(i, j);
+					weights.0.swap(i, j);
+					swaps.push((i, j));
+				},
+				None => break,
+			}
+		}
+		swaps
+	}
+
+	// Undoes `swaps`, as returned by the shuffles, by making them again in
+	// reverse order.
+	pub fn undo_swaps(&mut self, swaps: &[(usize, usize)])
+	{
+		for &(i, j) in swaps.iter().rev() {
+			self.swap_positions(i, j);
+		}
+	}
+
+	// How often the characters on each position are typed in `text`, on
+	// either layer, plus one, so that characters the text never uses can
+	// still be picked by `shuffle_weighted`.
+	pub fn position_weights<T: Ngrams + ?Sized>(&self, text: &T)
+	-> KeyMap<u64>
+	{
+		let mut weights = KeyMap([1; 34]);
+		self.get_position_map_with(&Transliteration::none()).each_press(text, |kp, n| weights[kp.pos] += n);
+		weights
+	}
+
+	// A pair of positions that `shuffle_masked` could swap, for callers that
+	// want to score a swap before making it with `swap_positions`. `None` if
+	// `mask` leaves fewer than two keys free.
+	pub fn propose_swap(mask: &LayoutShuffleMask, rng: &mut impl Rng)
+	-> Option<(usize, usize)>
+	{
+		Layout::propose_swap_scoped(mask, SwapScope::Any, rng)
+	}
+
+	// Same as `propose_swap`, but within `scope`.
+	pub fn propose_swap_scoped(mask: &LayoutShuffleMask, scope: SwapScope, rng: &mut impl Rng)
+	-> Option<(usize, usize)>
+	{
+		Layout::pick_swap(&mask.swap_groups(scope), rng)
+	}
+
+	// A swap, or with probability `cycle_rate` a 3-cycle, among the positions
+	// `mask` leaves free. `None` if there are fewer than two; a 3-cycle needs
+	// three, and falls back to a swap otherwise.
+	pub fn propose_move(mask: &LayoutShuffleMask, cycle_rate: f64, rng: &mut impl Rng)
+	-> Option<Move>
+	{
+		if cycle_rate > 0.0 && rng.gen::<f64>() < cycle_rate {
+			if let Some(mv) = Layout::propose_cycle(mask, rng) {
+				return Some(mv);
+			}
+		}
+		Layout::propose_swap(mask, rng).map(|(i, j)| Move::Swap(i, j))
+	}
+
+	// A 3-cycle of three distinct positions `mask` leaves free, every choice
+	// equally likely. `None` if there are fewer than three.
+	pub fn propose_cycle(mask: &LayoutShuffleMask, rng: &mut impl Rng)
+	-> Option<Move>
+	{
+		let swappable = mask.swappable_positions();
+		if swappable.len() < 3 {
+			return None;
+		}
+		let picked: Vec<usize> = swappable.choose_multiple(rng, 3).cloned().collect();
+		Some(Move::Cycle3(picked[0], picked[1], picked[2]))
+	}
+
+	pub fn apply(&mut self, mv: Move)
+	{
+		match mv {
+			Move::Swap(i, j) => self.swap_positions(i, j),
+			Move::Cycle3(i, j, k) => {
+				self.swap_positions(i, k);
+				self.swap_positions(j, k);
+			},
+		}
+	}
+
+	// Undoes `apply(mv)`.
+	pub fn revert(&mut self, mv: Move)
+	{
+		match mv {
+			Move::Swap(i, j) => self.swap_positions(i, j),
+			Move::Cycle3(i, j, k) => {
+				self.swap_positions(j, k);
+				self.swap_positions(i, k);
+			},
+		}
+	}
+
+	// Checks for authoring mistakes: characters that appear more than once
+	// within a layer, characters outside ASCII, keys whose upper layer is not
+	// their US shifted form, and empty keys that `LAYOUT_MASK` would let the
+	// optimizer move around. Layouts in `SHIFT_EXCEPTIONS` only have their
+	// letters checked against the shift table.
+	pub fn validate(&self)
+	-> Result<(), Vec<LayoutError>>
+	{
+		self.validate_masked(&LAYOUT_MASK)
+	}
+
+	// Same as `validate`, but only empty keys that `mask` can swap count.
+	pub fn validate_masked(&self, mask: &LayoutShuffleMask)
+	-> Result<(), Vec<LayoutError>>
+	{
+		let Layout(Layer(KeyMap(ref lower)), Layer(KeyMap(ref upper)), _) = *self;
+		let mut errors = Vec::new();
+
+		for (n, layer) in self.layers().enumerate() {
+			let keys = layer.keys();
+			for (i, c) in keys.iter().enumerate() {
+				let error = LayoutError::DuplicateKey { layer: n, key: *c };
+				if *c != '\0' && keys[..i].contains(c) && !errors.contains(&error) {
+					errors.push(error);
+				}
+			}
+		}
+
+		for (n, layer) in self.layers().enumerate() {
+			for (pos, c) in layer.keys().iter().enumerate() {
+				if !c.is_ascii() {
+					errors.push(LayoutError::NonAscii { pos, layer: n, key: *c });
+				}
+			}
+		}
+
+		let punctuation = !SHIFT_EXCEPTIONS.contains(&self);
+		for (pos, (l, u)) in lower.iter().zip(upper.iter()).enumerate() {
+			let checked = l.is_alphabetic() || u.is_alphabetic()
+				|| (punctuation && l.is_ascii() && u.is_ascii());
+			if checked && *u != shift_char(*l) {
+				errors.push(LayoutError::ShiftMismatch { pos, lower: *l, upper: *u });
+			}
+		}
+
+		for pos in mask.swappable_positions() {
+			if lower[pos] == '\0' {
+				errors.push(LayoutError::EmptyKey(pos));
+			}
+		}
+
+		if errors.is_empty() {
+			Ok(())
+		} else {
+			Err(errors)
+		}
+	}
+
+	// Counts adjacent pairs in `text` typed by the same finger on different
+	// keys, leaving out the thumbs. Characters that are not on the layout
+	// break the chain.
+	pub fn same_finger_bigrams<T: Ngrams + ?Sized>(&self, text: &T)
+	-> usize
+	{
+		let mut count = 0;
+		self.get_position_map().each_press_pair(text, |p, c, n| {
+			if p.same_finger(&c) && p.finger != Finger::Thumb && p.pos != c.pos {
+				count += n;
+			}
+		});
+		count as usize
+	}
+
+	// Counts presses in `text` on the same finger as the press two before,
+	// on a different key, leaving out the thumbs as `same_finger_bigrams`
+	// does. Characters that are not on the layout start the count over.
+	pub fn same_finger_skipgrams(&self, text: &str)
+	-> usize
+	{
+		let map = self.get_position_map();
+		let mut count = 0;
+		let (mut before, mut prev): (Option<KeyPress>, Option<KeyPress>) = (None, None);
+		for c in text.chars() {
+			let curr = *map.get_key_position(c);
+			if let (Some(b), Some(k)) = (before, curr) {
+				if b.same_finger(&k) && k.finger != Finger::Thumb && b.pos != k.pos {
+					count += 1;
+				}
+			}
+			if curr.is_none() {
+				before = None;
+				prev = None;
+			} else {
+				before = prev;
+				prev = curr;
+			}
+		}
+		count
+	}
+
+	// Counts runs of three presses in `text` on one hand that change
+	// direction partway, going toward the thumb and then away or the other
+	// way round by `Finger` order. Runs with a thumb or with a finger used
+	// twice are left out, and characters that are not on the layout start
+	// the count over.
+	pub fn redirects(&self, text: &str)
+	-> usize
+	{
+		let map = self.get_position_map();
+		let mut count = 0;
+		let (mut first, mut second): (Option<KeyPress>, Option<KeyPress>) = (None, None);
+		for c in text.chars() {
+			let curr = *map.get_key_position(c);
+			if let (Some(a), Some(b), Some(k)) = (first, second, curr) {
+				let (fa, fb, fc) = (a.finger, b.finger, k.finger);
+				if a.hand == b.hand && b.hand == k.hand
+					&& ![fa, fb, fc].contains(&Finger::Thumb)
+					&& fa != fb && fb != fc && fa != fc
+					&& (fa < fb) != (fb < fc) {
+					count += 1;
+				}
+			}
+			if curr.is_none() {
+				first = None;
+				second = None;
+			} else {
+				first = second;
+				second = curr;
+			}
+		}
+		count
+	}
+
+	// Adjacent pairs in `text` typed by two different fingers of the same
+	// hand, as (inward, outward): inward rolls move toward the index finger
+	// and outward rolls toward the pinky. Pairs involving a thumb are not
+	// rolls.
+	pub fn roll_counts<T: Ngrams + ?Sized>(&self, text: &T)
+	-> (usize, usize)
+	{
+		let mut inward = 0;
+		let mut outward = 0;
+		self.get_position_map().each_press_pair(text, |p, c, n| {
+			if p.hand != c.hand || p.finger == Finger::Thumb || c.finger == Finger::Thumb {
+				return;
+			}
+			if c.finger > p.finger {
+				inward += n;
+			} else if c.finger < p.finger {
+				outward += n;
+			}
+		});
+		(inward as usize, outward as usize)
+	}
+
+	// The share of key presses in `text` on the home row, from 0.0 to 1.0.
+	// Thumb presses count as home row presses if `thumbs` is set. Characters
+	// that are not on the layout are not counted.
+	pub fn home_row_rate<T: Ngrams + ?Sized>(&self, text: &T, thumbs: bool)
+	-> f64
+	{
+		let mut presses = 0;
+		let mut home = 0;
+		self.get_position_map().each_press(text, |kp, n| {
+			presses += n;
+			if kp.row == Row::Home || (thumbs && kp.row == Row::Thumb) {
+				home += n;
+			}
+		});
+		if presses == 0 {
+			0.0
+		} else {
+			home as f64 / presses as f64
+		}
+	}
+
+	// `lateral_reaches_on` the default keyboard.
+	pub fn lateral_reaches<T: Ngrams + ?Sized>(&self, text: &T)
+	-> usize
+	{
+		self.lateral_reaches_on(Keyboard::shared_default(), text)
+	}
+
+	// Key presses in `text` on the keys of `keyboard` marked `lateral`.
+	// Characters that are not on the layout are not counted.
+	pub fn lateral_reaches_on<T: Ngrams + ?Sized>(&self, keyboard: &Keyboard, text: &T)
+	-> usize
+	{
+		let mut count = 0;
+		self.get_position_map_on(keyboard, Transliteration::shared_default()).each_press(text, |kp, n| {
+			if keyboard.key(kp.pos).is_some_and(|key| key.lateral) {
+				count += n;
+			}
+		});
+		count as usize
+	}
+
+	// The share of key presses in `text` made with a thumb, from 0.0 to 1.0.
+	// Characters that are not on the layout are not counted.
+	pub fn thumb_usage_rate<T: Ngrams + ?Sized>(&self, text: &T)
+	-> f64
+	{
+		let mut presses = 0;
+		let mut thumb = 0;
+		self.get_position_map().each_press(text, |kp, n| {
+			presses += n;
+			if kp.finger == Finger::Thumb {
+				thumb += n;
+			}
+		});
+		if presses == 0 {
+			0.0
+		} else {
+			thumb as f64 / presses as f64
+		}
+	}
+
+	// Adjacent pairs in `text` where the same finger goes straight between
+	// the top and bottom rows, in either direction.
+	pub fn row_jumps<T: Ngrams + ?Sized>(&self, text: &T)
+	-> usize
+	{
+		let mut count = 0;
+		self.get_position_map().each_press_pair(text, |p, c, n| {
+			if p.hand == c.hand && p.finger == c.finger &&
+			   matches!((p.row, c.row), (Row::Top, Row::Bottom) | (Row::Bottom, Row::Top)) {
+				count += n;
+			}
+		});
+		count as usize
+	}
+
+	// The share of adjacent pairs in `text` typed with different hands, from
+	// 0.0 to 1.0. Characters that are not on the layout break the chain, and
+	// text with no pairs at all gives 0.0.
+	pub fn hand_alternation_rate<T: Ngrams + ?Sized>(&self, text: &T)
+	-> f64
+	{
+		let mut pairs = 0;
+		let mut alternations = 0;
+		self.get_position_map().each_press_pair(text, |p, c, n| {
+			pairs += n;
+			if p.hand != c.hand {
+				alternations += n;
+			}
+		});
+
+		if pairs == 0 {
+			0.0
+		} else {
+			alternations as f64 / pairs as f64
+		}
+	}
+
+	// `bonus_per_alt` for every adjacent pair in `text` typed with different
+	// hands, so a negative bonus rewards alternation in a score that is
+	// minimised. Pairs with a character that is not on the layout count for
+	// nothing.
+	pub fn alternation_score<T: Ngrams + ?Sized>(&self, text: &T, bonus_per_alt: f32)
+	-> f32
+	{
+		let mut alternations = 0;
+		self.get_position_map().each_press_pair(text, |p, c, n| {
+			if p.hand != c.hand {
+				alternations += n;
+			}
+		});
+		bonus_per_alt * alternations as f32
+	}
+
+	// `diff_on` the default keyboard.
+	pub fn diff(&self, other: &Layout)
+	-> Vec<KeyDiff>
+	{
+		self.diff_on(Keyboard::shared_default(), other)
+	}
+
+	// The positions where `other` differs from this layout on either layer,
+	// in position order, with the hand, finger and row `keyboard` gives them.
+	pub fn diff_on(&self, keyboard: &Keyboard, other: &Layout)
+	-> Vec<KeyDiff>
+	{
+		let keys = keyboard.keys();
+		(0..self.lower().keys().len())
+			.map(|pos| KeyDiff {
+				pos,
+				hand: keys[pos].hand,
+				finger: keys[pos].finger,
+				row: keys[pos].row,
+				before: (self.0.0[pos], self.1.0[pos]),
+				after: (other.0.0[pos], other.1.0[pos]),
+			})
+			.filter(|d| d.before != d.after)
+			.collect()
+	}
+
+	// The number of positions that `diff` reports.
+	pub fn distance(&self, other: &Layout)
+	-> usize
+	{
+		self.diff(other).len()
+	}
+
+	// The layout flipped left to right, for the other hand. All layers move
+	// together.
+	pub fn mirror(&self)
+	-> Layout
+	{
+		let mut mirrored = self.clone();
+		for (pos, &to) in MIRROR_POSITIONS.enumerate_positions() {
+			mirrored.0.0[to] = self.0.0[pos];
+			mirrored.1.0[to] = self.1.0[pos];
+			for (layer, from) in mirrored.2.iter_mut().zip(self.2.iter()) {
+				layer.0[to] = from.0[pos];
+			}
+		}
+		mirrored
+	}
+
+	// Keystrokes per finger over `text`, in order across the board from the
+	// left pinky to the left thumb and then the right thumb to the right
+	// pinky. Characters not on the layout are not counted.
+	pub fn finger_usage<T: Ngrams + ?Sized>(&self, text: &T)
+	-> [usize; 10]
+	{
+		let mut usage = [0; 10];
+		self.get_position_map().each_press(text, |kp, n| {
+			let idx = match kp.hand {
+				Hand::Left => kp.finger as usize,
+				Hand::Right => 9 - kp.finger as usize,
+			};
+			usage[idx] += n as usize;
+		});
+		usage
+	}
+
+	// `total_travel_on` the default keyboard.
+	pub fn total_travel<T: Ngrams + ?Sized>(&self, text: &T)
+	-> f32
+	{
+		self.total_travel_on(Keyboard::shared_default(), text)
+	}
+
+	// The distance, in key widths, that fingers travel to type `text` on
+	// `keyboard`, with every press counted as a trip out from that finger's
+	// home key. Thumbs rest on their own keys, and characters not on the
+	// layout are skipped.
+	pub fn total_travel_on<T: Ngrams + ?Sized>(&self, keyboard: &Keyboard, text: &T)
+	-> f32
+	{
+		let mut travel = 0.0;
+		self.get_position_map_on(keyboard, Transliteration::shared_default()).each_press(text, |kp, n| {
+			travel += keyboard.travel_to(kp.pos) * n as f32;
+		});
+		travel
+	}
+
+	// `estimated_time_on` the default keyboard.
+	pub fn estimated_time<T: Ngrams + ?Sized>(&self, text: &T)
+	-> f32
+	{
+		self.estimated_time_on(Keyboard::shared_default(), text)
+	}
+
+	// `estimated_time_with` the finger speeds of `keyboard`.
+	pub fn estimated_time_on<T: Ngrams + ?Sized>(&self, keyboard: &Keyboard, text: &T)
+	-> f32
+	{
+		self.estimated_time_with_on(keyboard, text, keyboard.finger_speeds())
+	}
+
+	// How long `text` takes to type on the default keyboard with `speeds`.
+	pub fn estimated_time_with<T: Ngrams + ?Sized>(&self, text: &T, speeds: &[f32; 5])
+	-> f32
+	{
+		self.estimated_time_with_on(Keyboard::shared_default(), text, speeds)
+	}
+
+	// How long `text` takes to type on `keyboard`, in presses of a home key
+	// with the index finger: each press takes one plus the key widths
+	// travelled to it, as `total_travel` counts them, divided by the finger's
+	// entry in `speeds`. Characters not on the layout are skipped.
+	pub fn estimated_time_with_on<T: Ngrams + ?Sized>(&self, keyboard: &Keyboard, text: &T, speeds: &[f32; 5])
+	-> f32
+	{
+		let mut time = 0.0;
+		self.get_position_map_on(keyboard, Transliteration::shared_default()).each_press(text, |kp, n| {
+			time += (1.0 + keyboard.travel_to(kp.pos)) / speeds[kp.finger as usize] * n as f32;
+		});
+		time
+	}
+
+	// Swaps two characters, along with their counterparts on the other layer.
+	// Either character may be given from either layer, so `E` swaps the same
+	// key as `e`. Only characters on the layout count: `é` is not found on a
+	// layout that only types it as `e`.
+	pub fn swap_chars(&mut self, a: char, b: char)
+	-> Result<(), SwapError>
+	{
+		self.swap_chars_masked(a, b, &LAYOUT_MASK)
+	}
+
+	// The old name of `swap_chars`.
+	pub fn swap_keys(&mut self, a: char, b: char)
+	-> Result<(), SwapError>
+	{
+		self.swap_chars(a, b)
+	}
+
+	// Same as `swap_chars`, but only keys that `mask` can swap may move.
+	pub fn swap_chars_masked(&mut self, a: char, b: char, mask: &LayoutShuffleMask)
+	-> Result<(), SwapError>
+	{
+		let (i, j) = {
+			let map = self.get_position_map_with(&Transliteration::none());
+			let find = |c| match *map.get_key_position(c) {
+				Some(ref kp) if mask.is_swappable(kp.pos) => Ok(kp.pos),
+				Some(_) => Err(SwapError::Locked(c)),
+				None => Err(SwapError::NotFound(c)),
+			};
+			(find(a)?, find(b)?)
+		};
+
+		self.swap_positions(i, j);
+		Ok(())
+	}
+
+	// Swaps the keys at two positions on every layer, whether or not a mask
+	// would allow it.
+	pub fn swap_positions(&mut self, i: usize, j: usize)
+	{
+		let Layout(ref mut lower, ref mut upper, ref mut extra) = *self;
+		lower.swap(i, j);
+		upper.swap(i, j);
+		for layer in extra {
+			layer.swap(i, j);
+		}
+	}
+
+	// Uses the default `Transliteration`, so that accented letters and
+	// typographic punctuation are typed as their plain forms.
+	pub fn get_position_map(&self)
+	-> LayoutPosMap
+	{
+		self.get_position_map_with(Transliteration::shared_default())
+	}
+
+	// `get_position_map_on` the default keyboard.
+	pub fn get_position_map_with(&self, transliteration: &Transliteration)
+	-> LayoutPosMap
+	{
+		self.get_position_map_on(Keyboard::shared_default(), transliteration)
+	}
+
+	// Two distinct positions from one of `groups`, every such pair equally
+	// likely. `None` if no group has two positions.
+	fn pick_swap(groups: &[Vec<usize>], rng: &mut impl Rng)
+	-> Option<(usize, usize)>
+	{
+		let groups: Vec<&Vec<usize>> = groups.iter().filter(|g| g.len() >= 2).collect();
+		if groups.len() < 2 {
+			return groups.first().map(|g| Layout::shuffle_position(g, rng));
+		}
+
+		let pairs = |g: &Vec<usize>| g.len() * (g.len() - 1) / 2;
+		let mut k = rng.gen_range(0..groups.iter().map(|g| pairs(g)).sum::<usize>());
+		for g in groups {
+			if k < pairs(g) {
+				return Some(Layout::shuffle_position(g, rng));
+			}
+			k -= pairs(g);
+		}
+		unreachable!()
+	}
+
+	// Same as `pick_swap`, but the first position is drawn in proportion to
+	// its weight from every group with two positions, and the second in
+	// proportion to its weight from the rest of that group.
+	fn pick_weighted_swap(groups: &[Vec<usize>], weights: &KeyMap<u64>, rng: &mut impl Rng)
+	-> Option<(usize, usize)>
+	{
+		let candidates: Vec<&Vec<usize>> = groups.iter().filter(|g| g.len() >= 2).collect();
+		let all: Vec<usize> = candidates.iter().flat_map(|g| g.iter().cloned()).collect();
+		let i = weighted_choice(&all, weights, rng)?;
+		let rest: Vec<usize> = candidates.iter()
+			.find(|g| g.contains(&i))
+			.unwrap()
+			.iter()
+			.cloned()
+			.filter(|&p| p != i)
+			.collect();
+		let j = weighted_choice(&rest, weights, rng)?;
+		Some((i, j))
+	}
+
+	// Two distinct entries of `swappable`, every pair equally likely. `j` is
+	// drawn from one fewer entries and skips over `i`.
+	fn shuffle_position(swappable: &[usize], rng: &mut impl Rng)
+	-> (usize, usize)
+	{
+		let i = rng.gen_range(0..swappable.len());
+		let mut j = rng.gen_range(0..swappable.len() - 1);
+		if j >= i {
+			j += 1;
+		}
+
+		(swappable[i], swappable[j])
+	}
+}
+
+impl Layer
+{
+	fn swap(&mut self, i: usize, j: usize)
+	{
+		let c = self.0[i];
+		self.0[i] = mem::replace(&mut self.0[j], c);
+	}
+}
+
+impl <const N: usize> Layer<N>
+{
+	// `layer` is the layer's index in its layout; 1 is the shift layer.
+	fn key_presses<'a>(&'a self, keyboard: &'a Keyboard<N>, layer: usize)
+	-> impl Iterator<Item = KeyPress> + 'a
+	{
+		self.0.enumerate_positions().zip(keyboard.keys()).map(move |((i, &c), key)| KeyPress {
+			kc: c,
+			pos: i,
+			shifted: layer == 1,
+			layer,
+			finger: key.finger,
+			hand: key.hand,
+			row: key.row,
+			column: key.column,
+			center: key.center,
+		})
+	}
+}
+
+impl LayoutPosMap
+{
+	pub fn get_key_position(&self, kc: char)
+	-> &Option<KeyPress>
+	{
+		if kc.is_ascii() {
+			&self.ascii[kc as usize]
+		} else {
+			self.other.get(&kc).unwrap_or(&KP_NONE)
+		}
+	}
+
+	fn insert(&mut self, kc: char, kp: KeyPress)
+	{
+		if kc.is_ascii() {
+			self.ascii[kc as usize] = Some(kp);
+		} else {
+			self.other.insert(kc, Some(kp));
+		}
+	}
+
+	// Brings the map up to date with `Layout::swap_positions(i, j)`, without
+	// building it again: every character typed on `i` is now typed on `j` on
+	// the same layer, and the other way round. A layout with a character
+	// twice on one layer may be typed on the other copy of it afterwards.
+	pub fn swap_positions(&mut self, i: usize, j: usize)
+	{
+		let (key_i, key_j) = (self.keys[i], self.keys[j]);
+		let moved = |kp: &mut KeyPress, pos: usize, key: &Key| {
+			kp.pos = pos;
+			kp.finger = key.finger;
+			kp.hand = key.hand;
+			kp.row = key.row;
+			kp.column = key.column;
+			kp.center = key.center;
+		};
+		for kp in self.ascii.iter_mut().chain(self.other.values_mut()).flatten() {
+			if kp.pos == i {
+				moved(kp, j, &key_j);
+			} else if kp.pos == j {
+				moved(kp, i, &key_i);
+			}
+		}
+	}
+
+	// Same as `Layout::undo_swaps`.
+	pub fn undo_swaps(&mut self, swaps: &[(usize, usize)])
+	{
+		for &(i, j) in swaps.iter().rev() {
+			self.swap_positions(i, j);
+		}
+	}
+
+	// The key press for each character of `text`, skipping characters the
+	// layout has no key for, and `\n`, `\t` and `\r` unless it has keys for
+	// them.
+	pub fn presses<'a>(&'a self, text: &'a str)
+	-> impl Iterator<Item = KeyPress> + 'a
+	{
+		self.presses_with(text, WhitespacePolicy::Skip)
+	}
+
+	// Same as `presses`, handling `\n`, `\t` and `\r` as `policy` says.
+	pub fn presses_with<'a>(&'a self, text: &'a str, policy: WhitespacePolicy)
+	-> impl Iterator<Item = KeyPress> + 'a
+	{
+		self.try_presses(text, policy).filter_map(Result::ok)
+	}
+
+	// Same as `presses_with`, but characters the layout has no key for come
+	// out as errors instead of being skipped, so they can be counted. Skipped
+	// whitespace is neither.
+	pub fn try_presses<'a>(&'a self, text: &'a str, policy: WhitespacePolicy)
+	-> impl Iterator<Item = Result<KeyPress, UnknownChar>> + 'a
+	{
+		text.chars().filter_map(move |c| {
+			if let Some(kp) = *self.get_key_position(c) {
+				return Some(Ok(kp));
+			}
+			match c {
+				'\n' | '\t' | '\r' => match policy {
+					WhitespacePolicy::Skip => None,
+					WhitespacePolicy::Thumb => Some(self.get_key_position(' ').ok_or(UnknownChar(c))),
+				},
+				_ => Some(Err(UnknownChar(c))),
+			}
+		})
+	}
+
+	// Calls `f` with the key press for each character of `text` and its
+	// count, skipping characters the layout has no key for.
+	pub fn each_press<T: Ngrams + ?Sized>(&self, text: &T, mut f: impl FnMut(KeyPress, u64))
+	{
+		text.each_char(&mut |c, n| {
+			if let Some(kp) = *self.get_key_position(c) {
+				f(kp, n);
+			}
+		});
+	}
+
+	// Same as `each_press`, but for adjacent pairs. Pairs that involve a
+	// character the layout has no key for are skipped.
+	pub fn each_press_pair<T: Ngrams + ?Sized>(&self, text: &T, mut f: impl FnMut(KeyPress, KeyPress, u64))
+	{
+		text.each_bigram(&mut |a, b, n| {
+			if let (Some(p), Some(c)) = (*self.get_key_position(a), *self.get_key_position(b)) {
+				f(p, c, n);
+			}
+		});
+	}
+
+	// Adjacent key presses in `text`, skipping pairs that involve a character
+	// the layout has no key for.
+	pub fn press_pairs<'a>(&'a self, text: &'a str)
+	-> impl Iterator<Item = (KeyPress, KeyPress)> + 'a
+	{
+		let mut prev = None;
+		text.chars().map(move |c| *self.get_key_position(c)).filter_map(move |curr| {
+			let pair = match (prev, curr) {
+				(Some(p), Some(c)) => Some((p, c)),
+				_ => None,
+			};
+			prev = curr;
+			pair
+		})
+	}
+}
+
+impl Transliteration
+{
+	// No stand-ins at all.
+	pub fn none()
+	-> Transliteration
+	{
+		Transliteration(HashMap::new())
+	}
+
+	pub fn insert(&mut self, from: char, to: char)
+	{
+		self.0.insert(from, to);
+	}
+
+	// The default transliteration, built the first time it is asked for and
+	// shared after that, since every position map needs it.
+	pub fn shared_default()
+	-> &'static Transliteration
+	{
+		static DEFAULT: OnceLock<Transliteration> = OnceLock::new();
+		DEFAULT.get_or_init(Transliteration::default)
+	}
+}
+
+// Latin letters with diacritics, in both cases, and curly quotes and dashes.
+impl Default for Transliteration
+{
+	fn default()
+	-> Transliteration
+	{
+		let mut t = Transliteration::none();
+		for &(from, to) in TRANSLITERATIONS.iter() {
+			t.insert(from, to);
+		}
+		for &(from, to) in TRANSLITERATIONS.iter().filter(|&&(from, _)| from.is_lowercase()) {
+			let upper = shift_char(from);
+			if upper != from {
+				t.insert(upper, shift_char(to));
+			}
+		}
+		t
+	}
+}
+
+impl KeyPress
+{
+	// The key that types `kc`, if the layout behind `map` has one.
+	pub fn new(kc: char, map: &LayoutPosMap)
+	-> Option<KeyPress>
+	{
+		*map.get_key_position(kc)
+	}
+
+	// Whether both keys are in the same column of the same hand.
+	pub fn same_column(&self, other: &KeyPress)
+	-> bool
+	{
+		self.hand == other.hand && self.column == other.column
+	}
+
+	// Whether both keys are pressed with the same finger of the same hand.
+	pub fn same_finger(&self, other: &KeyPress)
+	-> bool
+	{
+		self.hand == other.hand && self.finger == other.finger
+	}
+}
+
+impl <const N: usize> LayoutShuffleMask<N>
+{
+	// `None` unless there is exactly one flag per position.
+	pub fn from_bools(mask: &[bool])
+	-> Option<LayoutShuffleMask<N>>
+	{
+		<[bool; N]>::try_from(mask).ok().map(|mask| LayoutShuffleMask(KeyMap(mask)))
+	}
+
+	// Every position can be swapped, including the ones `LAYOUT_MASK` keeps.
+	pub fn all()
+	-> LayoutShuffleMask<N>
+	{
+		LayoutShuffleMask(KeyMap([true; N]))
+	}
+
+	// Nothing can be swapped.
+	pub fn none()
+	-> LayoutShuffleMask<N>
+	{
+		LayoutShuffleMask(KeyMap([false; N]))
+	}
+
+	// Everything but `pinned` can be swapped. Positions off the keyboard are
+	// ignored, as they are by `pin`.
+	pub fn from_pinned_positions(pinned: &[usize])
+	-> LayoutShuffleMask<N>
+	{
+		let mut mask = LayoutShuffleMask::all();
+		for &pos in pinned {
+			mask.pin(pos);
+		}
+		mask
+	}
+
+	pub fn swappable_positions(&self)
+	-> Vec<usize>
+	{
+		self.0.enumerate_positions()
+			.filter(|&(_, &swappable)| swappable)
+			.map(|(i, _)| i)
+			.collect()
+	}
+
+	pub fn is_swappable(&self, pos: usize)
+	-> bool
+	{
+		pos < self.0.len() && self.0[pos]
+	}
+
+	pub fn pin(&mut self, pos: usize)
+	{
+		if pos < self.0.len() {
+			self.0[pos] = false;
+		}
+	}
+}
+
+impl LayoutShuffleMask
+{
+	// Pins the keys that type `chars` on `layout`, looked up without
+	// transliteration, and gives their positions in the order of `chars`.
+	// Nothing is pinned if one of them is not on the layout.
+	pub fn pin_chars(&mut self, layout: &Layout, chars: &str)
+	-> Result<Vec<usize>, UnknownChar>
+	{
+		let map = layout.get_position_map_with(&Transliteration::none());
+		let positions = chars.chars()
+			.map(|c| map.get_key_position(c).map(|kp| kp.pos).ok_or(UnknownChar(c)))
+			.collect::<Result<Vec<usize>, UnknownChar>>()?;
+		for &pos in &positions {
+			self.pin(pos);
+		}
+		Ok(positions)
+	}
+
+	// `swap_groups_on` the default keyboard.
+	pub fn swap_groups(&self, scope: SwapScope)
+	-> Vec<Vec<usize>>
+	{
+		self.swap_groups_on(Keyboard::shared_default(), scope)
+	}
+
+	// The swappable positions, split into the groups that `scope` lets swap
+	// among themselves, by the hands and rows of `keyboard`.
+	pub fn swap_groups_on(&self, keyboard: &Keyboard, scope: SwapScope)
+	-> Vec<Vec<usize>>
+	{
+		let swappable = self.swappable_positions();
+		let keys = keyboard.keys();
+		let same = |i: usize, j: usize| match scope {
+			SwapScope::Any => true,
+			SwapScope::SameHand => keys[i].hand == keys[j].hand,
+			SwapScope::SameRow => keys[i].row == keys[j].row,
+		};
+
+		let mut groups: Vec<Vec<usize>> = Vec::new();
+		for pos in swappable {
+			match groups.iter_mut().find(|g| same(g[0], pos)) {
+				Some(g) => g.push(pos),
+				None => groups.push(vec![pos]),
+			}
+		}
+		groups
+	}
+}
+
+impl Default for LayoutShuffleMask
+{
+	fn default()
+	-> LayoutShuffleMask
+	{
+		LAYOUT_MASK.clone()
+	}
+}
+
+impl LayoutPermutations
+{
+	pub fn new(layout: &Layout, mask: &LayoutShuffleMask, depth: usize)
+	-> LayoutPermutations
+	{
+		let swaps = vec![0; depth * 2];
+		LayoutPermutations {
+			orig_layout: layout.clone(),
+			swappable: mask.swappable_positions(),
+			swap_idx: swaps,
+			started: false,
+		}
+	}
+}
+
+impl Iterator for LayoutPermutations
+{
+	type Item = Layout;
+
+	fn next(&mut self)
+	-> Option<Layout>
+	{
+		let mut some = false;
+		let mut idx = 0;
+		let mut val = 0;
+
+		if self.started {
+			for (i, e) in self.swap_idx.iter_mut().enumerate() {
+				if *e + 1 < self.swappable.len() - i {
+					*e += 1;
+					some = true;
+					idx = i;
+					val = *e;
+					break;
+				}
+			}
+		} else {
+			self.started = true;
+			some = true;
+			idx = 1;
+			val = 0;
+		}
+
+		if some {
+			for i in 0..idx {
+				self.swap_idx[i] =  val + idx - i;
+			}
+
+			let mut layout = self.orig_layout.clone();
+			let mut i = 0;
+			while i < self.swap_idx.len() {
+				let lower = &mut ((layout.0).0).0;
+				let upper = &mut ((layout.1).0).0;
+				let swap_left = self.swappable[self.swap_idx[i]];
+				let swap_right = self.swappable[self.swap_idx[i + 1]];
+				lower.swap(swap_left, swap_right);
+				upper.swap(swap_left, swap_right);
+				i += 2;
+			}
+
+			Some(layout)
+		} else {
+			None
+		}
+	}
+}
+
+impl fmt::Display for Layout
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		if f.alternate() {
+			self.fmt_both(f)
+		} else {
+			self.fmt_lower(f)
+		}
+	}
+}
+
+// Fingers, hands and rows print as their variant names, which `from_str`
+// takes back in any case.
+impl fmt::Display for Finger
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		fmt::Debug::fmt(self, f)
+	}
+}
+
+impl FromStr for Finger
+{
+	type Err = UnknownName;
+
+	fn from_str(s: &str)
+	-> Result<Finger, UnknownName>
+	{
+		match &s.to_lowercase()[..] {
+			"pinky" => Ok(Finger::Pinky),
+			"ring" => Ok(Finger::Ring),
+			"middle" => Ok(Finger::Middle),
+			"index" => Ok(Finger::Index),
+			"thumb" => Ok(Finger::Thumb),
+			_ => Err(UnknownName(s.to_string())),
+		}
+	}
+}
+
+impl fmt::Display for Hand
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		fmt::Debug::fmt(self, f)
+	}
+}
+
+impl FromStr for Hand
+{
+	type Err = UnknownName;
+
+	fn from_str(s: &str)
+	-> Result<Hand, UnknownName>
+	{
+		match &s.to_lowercase()[..] {
+			"left" => Ok(Hand::Left),
+			"right" => Ok(Hand::Right),
+			_ => Err(UnknownName(s.to_string())),
+		}
+	}
+}
+
+impl fmt::Display for Row
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		fmt::Debug::fmt(self, f)
+	}
+}
+
+impl FromStr for SwapScope
+{
+	type Err = UnknownName;
+
+	fn from_str(s: &str)
+	-> Result<SwapScope, UnknownName>
+	{
+		match &s.to_lowercase()[..] {
+			"any" => Ok(SwapScope::Any),
+			"same-hand" => Ok(SwapScope::SameHand),
+			"same-row" => Ok(SwapScope::SameRow),
+			_ => Err(UnknownName(s.to_string())),
+		}
+	}
+}
+
+impl FromStr for Row
+{
+	type Err = UnknownName;
+
+	fn from_str(s: &str)
+	-> Result<Row, UnknownName>
+	{
+		match &s.to_lowercase()[..] {
+			"top" => Ok(Row::Top),
+			"home" => Ok(Row::Home),
+			"bottom" => Ok(Row::Bottom),
+			"thumb" => Ok(Row::Thumb),
+			_ => Err(UnknownName(s.to_string())),
+		}
+	}
+}
+
+impl Layout
+{
+	// The lower layer in the grid `Display` prints, with each key's
+	// background coloured by how often it is pressed in `text`, from blue
+	// for unused keys to red for the busiest.
+	pub fn heatmap<T: Ngrams + ?Sized>(&self, text: &T)
+	-> String
+	{
+		self.render_heat(Keyboard::shared_default(), text, 3, |c, level| {
+			format!("\x1b[30;48;5;{}m {} \x1b[0m", HEAT_COLORS[level], c)
+		})
+	}
+
+	// `heatmap` without colour, for terminals and files that cannot show
+	// it: each key is followed by one of ` .:-=+*#%@`, densest for the
+	// busiest key.
+	pub fn heatmap_plain<T: Ngrams + ?Sized>(&self, text: &T)
+	-> String
+	{
+		self.render_heat(Keyboard::shared_default(), text, 2, |c, level| format!("{}{}", c, HEAT_DENSITY[level]))
+	}
+
+	// Lays out one `cell` of `width` characters per key, given the key's
+	// lower character, a space for an empty key, and its heat from 0 to 9
+	// as the layout is typed on `keyboard`.
+	fn render_heat<T: Ngrams + ?Sized>(&self, keyboard: &Keyboard, text: &T, width: usize, cell: impl Fn(char, usize) -> String)
+	-> String
+	{
+		let mut presses: KeyMap<u64> = KeyMap::default();
+		self.get_position_map_on(keyboard, Transliteration::shared_default())
+			.each_press(text, |kp, n| presses[kp.pos] += n);
+		// The space bar would wash out every other key, so the scale tops
+		// out at the busiest finger key, and the thumbs are clamped to it.
+		let most = (0..presses.len())
+			.filter(|&pos| keyboard.keys()[pos].finger != Finger::Thumb)
+			.map(|pos| presses[pos])
+			.max()
+			.unwrap_or(0)
+			.max(1);
+		let top = HEAT_COLORS.len() - 1;
+		let level = |n: u64| (((n as f64 / most as f64) * top as f64).round() as usize).min(top);
+
+		let keys = self.lower().keys();
+		let mut s = String::new();
+		for (row, &(start, left, right)) in GRID_ROWS.iter().enumerate() {
+			if row > 0 {
+				s.push('\n');
+			}
+			s.push_str(&" ".repeat((5 - left) * (width + 1)));
+			for i in 0..left + right {
+				let pos = start + i;
+				match i {
+					0 => (),
+					_ if i == left => s.push_str(" | "),
+					_ => s.push(' '),
+				}
+				let c = if keys[pos] == '\0' { ' ' } else { keys[pos] };
+				s.push_str(&cell(c, level(presses[pos])));
+			}
+		}
+		s
+	}
+}
+
+impl fmt::Display for Layer
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		let mut keys = self.0.iter();
+		for (row, &(_, left, right)) in GRID_ROWS.iter().enumerate() {
+			if row > 0 {
+				writeln!(f)?;
+			}
+			write!(f, "{}", " ".repeat((5 - left) * 2))?;
+			for (i, c) in keys.by_ref().take(left + right).enumerate() {
+				match i {
+					0 => write!(f, "{}", c)?,
+					_ if i == left => write!(f, " | {}", c)?,
+					_ => write!(f, " {}", c)?,
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+
+impl FromStr for Layout
+{
+	type Err = ParseLayoutError;
+
+	// Accepts the grid printed by `Display`, with or without the `|`
+	// separators: either a lower layer on its own, in which case the upper
+	// layer is derived with the US shift pairs, or a lower layer followed by
+	// an upper layer and any further layers. Blank lines and extra whitespace
+	// are ignored.
+	fn from_str(s: &str)
+	-> Result<Layout, ParseLayoutError>
+	{
+		let lines: Vec<(usize, &str)> = s.lines()
+			.enumerate()
+			.filter(|&(_, line)| !line.trim().is_empty())
+			.map(|(i, line)| (i + 1, line))
+			.collect();
+
+		match lines.len() {
+			4 => {
+				Ok(Layout::from_lower(parse_layer(&lines[0..4])?))
+			},
+			n if n >= 8 && n % 4 == 0 => {
+				let lower = parse_layer(&lines[0..4])?;
+				let upper = parse_layer(&lines[4..8])?;
+				let mut layout = Layout::two_layer(lower, upper);
+				for rows in lines[8..].chunks(4) {
+					layout = layout.with_layer(parse_layer(rows)?);
+				}
+				Ok(layout)
+			},
+			n => Err(ParseLayoutError {
+				line: lines.last().map_or(1, |&(i, _)| i),
+				col:  1,
+				kind: ParseLayoutErrorKind::WrongRowCount(n),
+			}),
+		}
+	}
+}
+
+// With the `serde` feature, a layer is a plain array of single-character
+// strings and a layout is an object with `lower` and `upper` layers, plus an
+// `extra` list when it has more.
+#[cfg(feature = "serde")]
+impl <T: Serialize, const N: usize> Serialize for KeyMap<T, N>
+{
+	fn serialize<S: Serializer>(&self, serializer: S)
+	-> Result<S::Ok, S::Error>
+	{
+		self.0[..].serialize(serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl <'de, T: Deserialize<'de>, const N: usize> Deserialize<'de> for KeyMap<T, N>
+{
+	fn deserialize<D: Deserializer<'de>>(deserializer: D)
+	-> Result<KeyMap<T, N>, D::Error>
+	{
+		let keys = Vec::<T>::deserialize(deserializer)?;
+		let len = keys.len();
+		<[T; N]>::try_from(keys)
+			.map(KeyMap)
+			.map_err(|_| D::Error::invalid_length(len, &format!("{} keys", N).as_str()))
+	}
+}
+
+#[cfg(feature = "serde")]
+impl <const N: usize> Serialize for Layer<N>
+{
+	fn serialize<S: Serializer>(&self, serializer: S)
+	-> Result<S::Ok, S::Error>
+	{
+		self.0.serialize(serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl <'de, const N: usize> Deserialize<'de> for Layer<N>
+{
+	fn deserialize<D: Deserializer<'de>>(deserializer: D)
+	-> Result<Layer<N>, D::Error>
+	{
+		KeyMap::deserialize(deserializer).map(Layer)
+	}
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct LayoutFieldsRef<'a, const N: usize>
+{
+	lower: &'a Layer<N>,
+	upper: &'a Layer<N>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	extra: &'a Vec<Layer<N>>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LayoutFields<const N: usize>
+{
+	lower: Layer<N>,
+	upper: Layer<N>,
+	#[serde(default)]
+	extra: Vec<Layer<N>>,
+}
+
+#[cfg(feature = "serde")]
+impl <const N: usize> Serialize for Layout<N>
+{
+	fn serialize<S: Serializer>(&self, serializer: S)
+	-> Result<S::Ok, S::Error>
+	{
+		let Layout(ref lower, ref upper, ref extra) = *self;
+		LayoutFieldsRef { lower, upper, extra }.serialize(serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl <'de, const N: usize> Deserialize<'de> for Layout<N>
+{
+	fn deserialize<D: Deserializer<'de>>(deserializer: D)
+	-> Result<Layout<N>, D::Error>
+	{
+		let LayoutFields { lower, upper, extra } = LayoutFields::deserialize(deserializer)?;
+		Ok(Layout(lower, upper, extra))
+	}
+}
+
+impl fmt::Display for FromCharsError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		match *self {
+			FromCharsError::WrongKeyCount(n) => write!(f, "expected 34 keys, found {}", n),
+			FromCharsError::DuplicateKey(c) => write!(f, "`{}` appears more than once", c),
+			FromCharsError::NonAscii(c) => write!(f, "`{}` is not ASCII", c),
+		}
+	}
+}
+
+impl Error for FromCharsError {}
+
+impl fmt::Display for SwapError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		match *self {
+			SwapError::NotFound(c) => write!(f, "`{}` is not on the layout", c),
+			SwapError::Locked(c) => write!(f, "`{}` is on a key that cannot be swapped", c),
+		}
+	}
+}
+
+impl Error for SwapError {}
+
+impl fmt::Display for UnknownName
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		write!(f, "unknown name `{}`", self.0)
+	}
+}
+
+impl Error for UnknownName {}
+
+impl fmt::Display for UnknownChar
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		write!(f, "no key for {:?}", self.0)
+	}
+}
+
+impl Error for UnknownChar {}
+
+impl fmt::Display for PositionError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		write!(f, "position {} is not on the layout", self.0)
+	}
+}
+
+impl Error for PositionError {}
+
+impl fmt::Display for LayoutError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		match *self {
+			LayoutError::DuplicateKey { layer, key } => write!(f, "`{}` appears more than once in the {} layer",
+				key, layer_name(layer)),
+			LayoutError::ShiftMismatch { pos, lower, upper } =>
+				write!(f, "position {} has `{}` shifting to `{}`", pos, lower, upper),
+			LayoutError::EmptyKey(pos) => write!(f, "position {} is empty but can be swapped", pos),
+			LayoutError::NonAscii { pos, layer, key } =>
+				write!(f, "`{}` at position {} of the {} layer is not ASCII", key, pos, layer_name(layer)),
+		}
+	}
+}
+
+impl Error for LayoutError {}
+
+impl fmt::Display for ParseLayoutError
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		write!(f, "line {}, column {}: ", self.line, self.col)?;
+		match self.kind {
+			ParseLayoutErrorKind::WrongRowCount(n) =>
+				write!(f, "expected 4 rows, or a multiple of 4 from 8 up, found {}", n),
+			ParseLayoutErrorKind::WrongKeyCount { expected, found } =>
+				write!(f, "expected {} keys, found {}", expected, found),
+			ParseLayoutErrorKind::InvalidKey(ref key) =>
+				write!(f, "`{}` is not a single character", key),
+			ParseLayoutErrorKind::DuplicateKey(c) =>
+				write!(f, "`{}` appears more than once in the layer", c),
+			ParseLayoutErrorKind::NonAscii(c) =>
+				write!(f, "`{}` is not ASCII", c),
+		}
+	}
+}
+
+impl Error for ParseLayoutError {}
+
+// One of `positions`, each in proportion to its weight. Falls back to a
+// uniform choice when all the weights are zero, and gives `None` only when
+// there are no positions.
+fn weighted_choice(positions: &[usize], weights: &KeyMap<u64>, rng: &mut impl Rng)
+-> Option<usize>
+{
+	if positions.is_empty() {
+		return None;
+	}
+	let total: u64 = positions.iter().map(|&p| weights[p]).sum();
+	if total == 0 {
+		return Some(positions[rng.gen_range(0..positions.len())]);
+	}
+	let mut k = rng.gen_range(0..total);
+	for &p in positions {
+		if k < weights[p] {
+			return Some(p);
+		}
+		k -= weights[p];
+	}
+	unreachable!()
+}
+
+// The physical key behind a KeyMap position, whatever is mapped there. `None`
+// when the position is off the keyboard.
+pub fn finger_for_pos(pos: usize)
+-> Option<Finger>
+{
+	KEY_FINGERS.0.get(pos).cloned()
+}
+
+pub fn hand_for_pos(pos: usize)
+-> Option<Hand>
+{
+	KEY_HANDS.0.get(pos).cloned()
+}
+
+pub fn row_for_pos(pos: usize)
+-> Option<Row>
+{
+	KEY_ROWS.0.get(pos).cloned()
+}
+
+pub fn column_for_pos(pos: usize)
+-> Option<u8>
+{
+	KEY_COLUMNS.0.get(pos).cloned()
+}
+
+pub fn center_for_pos(pos: usize)
+-> Option<bool>
+{
+	KEY_CENTER_COLUMN.0.get(pos).cloned()
+}
+
+pub fn lateral_for_pos(pos: usize)
+-> Option<bool>
+{
+	KEY_LATERAL.0.get(pos).cloned()
+}
+
+pub fn coords_for_pos(pos: usize)
+-> Option<(f32, f32)>
+{
+	KEY_COORDS.0.get(pos).cloned()
+}
+
+// The whole finger, hand and row assignments, for analysis outside the crate
+// that needs more than one position at a time.
+pub fn key_fingers()
+-> &'static KeyMap<Finger>
+{
+	&KEY_FINGERS
+}
+
+pub fn key_hands()
+-> &'static KeyMap<Hand>
+{
+	&KEY_HANDS
+}
+
+pub fn key_rows()
+-> &'static KeyMap<Row>
+{
+	&KEY_ROWS
+}
+
+// "lower", "upper", then "layer 2" and so on.
+fn layer_name(layer: usize)
+-> String
+{
+	match layer {
+		0 => "lower".to_string(),
+		1 => "upper".to_string(),
+		n => format!("layer {}", n),
+	}
+}
+
+// The lower layers of `a` and `b` side by side, followed by a third grid with
+// a `*` on every position where the two differ on either layer.
+pub fn render_comparison(a: &Layout, b: &Layout)
+-> String
+{
+	let mut markers = KeyMap([' '; 34]);
+	for (pos, m) in markers.iter_mut().enumerate() {
+		if a.char_at(pos, false) != b.char_at(pos, false) || a.char_at(pos, true) != b.char_at(pos, true) {
+			*m = '*';
+		}
+	}
+
+	let a = a.lower().to_string();
+	let b = b.lower().to_string();
+	let markers = Layer(markers).to_string();
+	let width = a.lines().chain(b.lines()).map(|line| line.chars().count()).max().unwrap_or(0);
+
+	let mut s = String::new();
+	for ((a, b), m) in a.lines().zip(b.lines()).zip(markers.lines()) {
+		let line = format!("{:<w$}    {:<w$}    {}", a, b, m, w = width);
+		s.push_str(line.trim_end());
+		s.push('\n');
+	}
+	s
+}
+
+fn parse_layer(lines: &[(usize, &str)])
+-> Result<Layer, ParseLayoutError>
+{
+	let mut keys = ['\0'; 34];
+	let mut locations = [(0, 0); 34];
+
+	for (&(line, text), &(start, left, right)) in lines.iter().zip(GRID_ROWS.iter()) {
+		let tokens = grid_tokens(text);
+
+		// The `|` between the hands is optional; without it, the row is split
+		// by key count.
+		let sep = if tokens.get(left).map(|&(_, t)| t) == Some("|") {
+			Some(left)
+		} else {
+			tokens.iter().position(|&(_, t)| t == "|")
+		};
+		let (left_half, right_half, sep_col) = match sep {
+			Some(sep) => (&tokens[..sep], &tokens[(sep + 1)..], tokens[sep].0),
+			None => {
+				let split = left.min(tokens.len());
+				let end_col = text.chars().count() + 1;
+				(&tokens[..split], &tokens[split..], tokens.get(split).map_or(end_col, |&(col, _)| col))
+			},
+		};
+
+		let halves = [(left_half, left, start), (right_half, right, start + left)];
+		for &(half, expected, first) in halves.iter() {
+			// A thumb key holding the space bar prints as nothing at all.
+			if half.is_empty() && expected == 1 {
+				keys[first] = ' ';
+				locations[first] = (line, sep_col);
+				continue;
+			}
+			if half.len() != expected {
+				return Err(ParseLayoutError {
+					line,
+					col:  half.first().map_or(sep_col, |&(col, _)| col),
+					kind: ParseLayoutErrorKind::WrongKeyCount { expected, found: half.len() },
+				});
+			}
+			for (i, &(col, token)) in half.iter().enumerate() {
+				let mut chars = token.chars();
+				let c = match (chars.next(), chars.next()) {
+					_ if token == EMPTY_KEY => '\0',
+					(Some(c), None) => c,
+					_ => return Err(ParseLayoutError {
+						line,
+						col,
+						kind: ParseLayoutErrorKind::InvalidKey(token.to_string()),
+					}),
+				};
+				if !c.is_ascii() {
+					return Err(ParseLayoutError { line, col, kind: ParseLayoutErrorKind::NonAscii(c) });
+				}
+				keys[first + i] = c;
+				locations[first + i] = (line, col);
+			}
+		}
+	}
+
+	for i in 0..34 {
+		if keys[i] != '\0' && keys[..i].contains(&keys[i]) {
+			let (line, col) = locations[i];
+			return Err(ParseLayoutError { line, col, kind: ParseLayoutErrorKind::DuplicateKey(keys[i]) });
+		}
+	}
+
+	Ok(Layer(KeyMap(keys)))
+}
+
+// Splits a grid line on whitespace, keeping the (1-based) column of each token.
+fn grid_tokens(line: &str)
+-> Vec<(usize, &str)>
+{
+	let mut tokens = Vec::new();
+	let mut start = None;
+	for (col, (i, c)) in line.char_indices().enumerate() {
+		if c.is_whitespace() {
+			if let Some((start_col, start_i)) = start.take() {
+				tokens.push((start_col + 1, &line[start_i..i]));
+			}
+		} else if start.is_none() {
+			start = Some((col, i));
+		}
+	}
+	if let Some((start_col, start_i)) = start {
+		tokens.push((start_col + 1, &line[start_i..]));
+	}
+
+	tokens
+}
+
+// The shifted counterpart of a key on a US keyboard, or the capital of any
+// other lowercase letter. Keys without one shift to themselves.
+fn shift_char(c: char)
+-> char
+{
+	match c {
+		'a'..='z' => c.to_ascii_uppercase(),
+		c if c.is_lowercase() => {
+			let mut upper = c.to_uppercase();
+			match (upper.next(), upper.next()) {
+				(Some(u), None) => u,
+				_ => c,
+			}
+		},
+		'`'  => '~',
+		'1'  => '!',
+		'2'  => '@',
+		'3'  => '#',
+		'4'  => '$',
+		'5'  => '%',
+		'6'  => '^',
+		'7'  => '&',
+		'8'  => '*',
+		'9'  => '(',
+		'0'  => ')',
+		'-'  => '_',
+		'='  => '+',
+		'['  => '{',
+		']'  => '}',
+		'\\' => '|',
+		';'  => ':',
+		'\'' => '"',
+		','  => '<',
+		'.'  => '>',
+		'/'  => '?',
+		_    => c,
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	extern crate rand_chacha;
+
+	use super::*;
+	use super::rand::SeedableRng;
+	use self::rand_chacha::ChaCha12Rng;
+
+	#[test]
+	fn swapping_a_character_with_itself_changes_nothing()
+	{
+		let mut layout = QWERTY_LAYOUT.clone();
+		assert_eq!(layout.swap_chars('e', 'e'), Ok(()));
+		assert_eq!(layout.swap_chars('e', 'E'), Ok(()));
+		assert_eq!(layout, QWERTY_LAYOUT);
+	}
+
+	#[test]
+	fn swapping_across_hands_moves_both_layers()
+	{
+		let mut layout = QWERTY_LAYOUT.clone();
+		let (a, semicolon) = (QWERTY_LAYOUT.position_of('a').unwrap(), QWERTY_LAYOUT.position_of(';').unwrap());
+		assert!(hand_for_pos(a) != hand_for_pos(semicolon));
+
+		layout.swap_keys('a', ':').unwrap();
+		assert_eq!(layout.char_at(a, false), Ok(';'));
+		assert_eq!(layout.char_at(a, true), Ok(':'));
+		assert_eq!(layout.char_at(semicolon, false), Ok('a'));
+		assert_eq!(layout.char_at(semicolon, true), Ok('A'));
+		let map = layout.get_position_map();
+		assert_eq!(map.get_key_position('A').map(|kp| kp.hand), hand_for_pos(semicolon));
+
+		layout.swap_chars(';', 'a').unwrap();
+		assert_eq!(layout, QWERTY_LAYOUT);
+	}
+
+	#[test]
+	fn transliterated_characters_cannot_be_swapped()
+	{
+		let mut layout = QWERTY_LAYOUT.clone();
+		assert_eq!(layout.swap_chars('é', 'x'), Err(SwapError::NotFound('é')));
+		assert_eq!(layout, QWERTY_LAYOUT);
+	}
+
+	// `LAYOUT_MASK` with the empty thumb key pinned, as `main` does.
+	fn thumb_pinned()
+	-> LayoutShuffleMask
+	{
+		let mut mask = LAYOUT_MASK.clone();
+		mask.pin(32);
+		mask
+	}
+
+	#[test]
+	fn validate_catches_a_letter_where_a_symbol_shifts()
+	{
+		let mut layout = COLEMAK_LAYOUT.clone();
+		layout.set_char_at(31, true, 'Z').unwrap();
+		assert_eq!(layout.validate_masked(&thumb_pinned()), Err(vec![
+			LayoutError::DuplicateKey { layer: 1, key: 'Z' },
+			LayoutError::ShiftMismatch { pos: 31, lower: '/', upper: 'Z' }]));
+	}
+
+	#[test]
+	fn validate_checks_punctuation_against_the_us_shift_table()
+	{
+		let mut layout = QWERTY_LAYOUT.clone();
+		layout.set_char_at(20, true, ';').unwrap();
+		assert_eq!(layout.validate_masked(&thumb_pinned()), Err(vec![LayoutError::ShiftMismatch { pos: 20, lower: ';', upper: ';' }]));
+		assert_eq!(MTGAP_LAYOUT.validate_masked(&thumb_pinned()), Ok(()));
+	}
+
+	#[test]
+	fn validate_rejects_characters_outside_ascii()
+	{
+		let mut layout = QWERTY_LAYOUT.clone();
+		layout.set_char_at(22, false, 'ß').unwrap();
+		layout.set_char_at(22, true, 'ẞ').unwrap();
+		assert_eq!(layout.validate_masked(&thumb_pinned()), Err(vec![
+			LayoutError::NonAscii { pos: 22, layer: 0, key: 'ß' },
+			LayoutError::NonAscii { pos: 22, layer: 1, key: 'ẞ' },
+			LayoutError::ShiftMismatch { pos: 22, lower: 'ß', upper: 'ẞ' }]));
+	}
+
+	#[test]
+	fn built_in_layouts_parse_back_from_display()
+	{
+		for (name, layout) in ::layouts::all() {
+			let parsed = Layout::from_str(&layout.to_string()).unwrap();
+			assert_eq!(parsed.lower(), layout.lower(), "{}", name);
+			// Only the lower layer is printed, so the upper one comes back as
+			// its US shift.
+			if !SHIFT_EXCEPTIONS.contains(&layout) {
+				assert_eq!(&parsed, layout, "{}", name);
+			}
+		}
+
+		let grid = QWERTY_LAYOUT.to_string().replacen('w', "é", 1).replacen('j', "ж", 1);
+		let e = Layout::from_str(&grid).unwrap_err();
+		assert_eq!(e.to_string(), "line 1, column 3: `é` is not ASCII");
+		let e = Layout::from_str(&grid.replacen('é', "w", 1)).unwrap_err();
+		assert_eq!(e.to_string(), "line 2, column 15: `ж` is not ASCII");
+	}
+
+	#[test]
+	fn row_counts_other_than_four_or_a_multiple_of_four_are_rejected()
+	{
+		let grid = QWERTY_LAYOUT.to_full_string();
+		let lines: Vec<&str> = grid.lines().filter(|line| !line.trim().is_empty()).collect();
+		for &n in &[1, 3, 5, 7] {
+			let e = Layout::from_str(&lines[..n].join("\n")).unwrap_err();
+			assert_eq!(e.to_string(), format!("line {}, column 1: expected 4 rows, or a multiple of 4 from 8 up, found {}", n, n));
+		}
+		assert!(Layout::from_str(&lines[..4].join("\n")).is_ok());
+		assert!(Layout::from_str(&lines[..8].join("\n")).is_ok());
+	}
+
+	#[test]
+	fn undoing_a_shuffle_restores_the_layout()
+	{
+		let mut rng = ChaCha12Rng::seed_from_u64(37);
+		let weights = INIT_LAYOUT.position_weights("the quick brown fox jumps over the lazy dog");
+		for times in 0..200 {
+			let mut layout = INIT_LAYOUT.clone();
+			let swaps = if times % 2 == 0 {
+				layout.shuffle_masked(times, &LAYOUT_MASK, &mut rng)
+			} else {
+				layout.shuffle_weighted(times, &LAYOUT_MASK, SwapScope::SameHand, &weights, &mut rng)
+			};
+			assert_eq!(swaps.len(), times);
+			layout.undo_swaps(&swaps);
+			assert_eq!(layout, INIT_LAYOUT, "{} swaps", times);
+		}
+	}
+
+	#[test]
+	fn proposed_swaps_are_two_distinct_free_positions_and_uniform()
+	{
+		let mut mask = LAYOUT_MASK.clone();
+		for &pos in &[0, 11, 22, 31] {
+			mask.pin(pos);
+		}
+		let free = mask.swappable_positions();
+		let mut rng = ChaCha12Rng::seed_from_u64(33);
+		let mut counts = HashMap::new();
+		let draws = 200_000;
+		for _ in 0..draws {
+			let (i, j) = Layout::propose_swap(&mask, &mut rng).unwrap();
+			assert!(mask.is_swappable(i) && mask.is_swappable(j), "{} {}", i, j);
+			assert!(i != j);
+			*counts.entry((i.min(j), i.max(j))).or_insert(0) += 1;
+		}
+
+		let pairs = free.len() * (free.len() - 1) / 2;
+		assert_eq!(counts.len(), pairs);
+		let expected = draws as f64 / pairs as f64;
+		for (pair, &n) in &counts {
+			assert!((n as f64 / expected - 1.0).abs() < 0.25, "{:?} drawn {} times, expected {}", pair, n, expected);
+		}
+	}
+
+	#[test]
+	fn same_hand_shuffles_never_cross_hands()
+	{
+		let mut rng = ChaCha12Rng::seed_from_u64(34);
+		let mut layout = INIT_LAYOUT.clone();
+		let swaps = layout.shuffle_scoped(10_000, &LAYOUT_MASK, SwapScope::SameHand, &mut rng);
+		assert_eq!(swaps.len(), 10_000);
+		assert!(swaps.iter().all(|&(i, j)| hand_for_pos(i) == hand_for_pos(j)));
+		assert!(layout != INIT_LAYOUT);
+		for layer in 0..2 {
+			for &c in INIT_LAYOUT.layer(layer).unwrap().keys() {
+				let (from, to) = (INIT_LAYOUT.position_of(c).unwrap(), layout.position_of(c).unwrap());
+				assert_eq!(hand_for_pos(from), hand_for_pos(to), "`{}` moved from {} to {}", c, from, to);
+			}
+		}
+	}
+
+	#[test]
+	fn weighted_swaps_pick_positions_in_proportion_to_their_weight()
+	{
+		let mut weights = KeyMap([1; 34]);
+		weights[12] = 40;
+		weights[13] = 20;
+		let groups = LAYOUT_MASK.swap_groups(SwapScope::Any);
+		let free = LAYOUT_MASK.swappable_positions();
+		let total: u64 = free.iter().map(|&p| weights[p]).sum();
+
+		let mut rng = ChaCha12Rng::seed_from_u64(35);
+		let mut firsts: KeyMap<u64> = KeyMap::default();
+		let draws = 200_000;
+		for _ in 0..draws {
+			let (i, j) = Layout::pick_weighted_swap(&groups, &weights, &mut rng).unwrap();
+			assert!(i != j);
+			firsts[i] += 1;
+		}
+
+		for &pos in &free {
+			let expected = draws as f64 * weights[pos] as f64 / total as f64;
+			assert!((firsts[pos] as f64 / expected - 1.0).abs() < 0.1,
+				"position {} picked {} times, expected {}", pos, firsts[pos], expected);
+		}
+		assert_eq!(free.iter().map(|&p| firsts[p]).sum::<u64>(), draws);
+	}
+
+	#[test]
+	fn reverting_a_move_restores_the_layout_and_moves_keep_every_character()
+	{
+		let sorted = |layer: &Layer| {
+			let mut keys = layer.keys().to_vec();
+			keys.sort();
+			keys
+		};
+		let mut rng = ChaCha12Rng::seed_from_u64(36);
+		let mut layout = INIT_LAYOUT.clone();
+		let mut moves = Vec::new();
+		for _ in 0..1000 {
+			let mv = Layout::propose_move(&LAYOUT_MASK, 0.5, &mut rng).unwrap();
+			let before = layout.clone();
+			layout.apply(mv);
+			let moved = match mv {
+				Move::Swap(..) => 2,
+				Move::Cycle3(..) => 3,
+			};
+			assert_eq!(layout.positional_difference(&before), moved, "{:?}", mv);
+			assert_eq!(sorted(layout.lower()), sorted(before.lower()));
+			assert_eq!(sorted(layout.upper()), sorted(before.upper()));
+
+			let mut reverted = layout.clone();
+			reverted.revert(mv);
+			assert_eq!(reverted, before, "{:?}", mv);
+			moves.push(mv);
+		}
+
+		for &mv in moves.iter().rev() {
+			layout.revert(mv);
+		}
+		assert_eq!(layout, INIT_LAYOUT);
+	}
+
+	#[test]
+	fn pinned_positions_never_move()
+	{
+		let pinned = [0, 10, 11, 21, 29, 30, 31, 32, 33];
+		let mask = LayoutShuffleMask::from_pinned_positions(&pinned);
+		let mut rng = ChaCha12Rng::seed_from_u64(31);
+		let mut layout = INIT_LAYOUT.clone();
+		for _ in 0..5000 {
+			layout.shuffle_masked(3, &mask, &mut rng);
+			let weights = layout.position_weights("pinned");
+			layout.shuffle_weighted(1, &mask, SwapScope::SameHand, &weights, &mut rng);
+			for &pos in &pinned {
+				assert_eq!(layout.lower().keys()[pos], INIT_LAYOUT.lower().keys()[pos]);
+				assert_eq!(layout.upper().keys()[pos], INIT_LAYOUT.upper().keys()[pos]);
+			}
+		}
+		assert!(layout.positional_difference(&INIT_LAYOUT) > 0);
+	}
+
+	#[test]
+	fn a_seeded_shuffle_is_reproducible()
+	{
+		let mut layout = INIT_LAYOUT.clone();
+		layout.shuffle_seeded(1000, &mut ChaCha12Rng::seed_from_u64(30));
+		assert_eq!(layout.to_string(), concat!(
+			"p g o a l | e k m j / =\n",
+			"r q s f . | y c , z u '\n",
+			"d i b h x | n   t ; -\n",
+			"        w | v"));
+	}
+
+	#[test]
+	fn mixed_case_text_is_typed_on_both_layers()
+	{
+		let map = DVORAK_LAYOUT.get_position_map();
+		let mut presses = Vec::new();
+		map.each_press("Hi, BOB: \"ok\".", |kp, _| presses.push((kp.kc, kp.pos, kp.shifted)));
+		let h = DVORAK_LAYOUT.position_of('h').unwrap();
+		let o = DVORAK_LAYOUT.position_of('o').unwrap();
+		assert_eq!(presses[0], ('H', h, true));
+		assert_eq!(presses[1], ('i', DVORAK_LAYOUT.position_of('i').unwrap(), false));
+		assert_eq!(presses[2], (',', 1, false));
+		assert_eq!(presses[5], ('O', o, true));
+		assert_eq!(presses[7], (':', 22, true));
+		assert_eq!(presses[10], ('o', o, false));
+		let shifted: String = presses.iter().filter(|p| p.2).map(|p| p.0).collect();
+		assert_eq!(shifted, "HBOB:\"\"");
+	}
+
+	#[test]
+	fn a_character_on_both_layers_is_typed_unshifted()
+	{
+		// `,` is on Dvorak's lower layer at 1; put it on the upper layer too.
+		let mut layout = DVORAK_LAYOUT.clone();
+		layout.set_char_at(22, true, ',').unwrap();
+		let map = layout.get_position_map();
+		let kp = map.get_key_position(',').unwrap();
+		assert_eq!((kp.pos, kp.shifted, kp.layer), (1, false, 0));
+		assert_eq!(map.get_key_position('<').map(|kp| (kp.pos, kp.shifted)), Some((1, true)));
+	}
+
+	#[test]
+	fn each_column_belongs_to_one_finger()
+	{
+		// Columns count outwards from the middle of the board.
+		let fingers = [Finger::Index, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky, Finger::Pinky,
+			Finger::Thumb];
+		let map = INIT_LAYOUT.get_position_map();
+		let presses: Vec<KeyPress> = INIT_LAYOUT.lower().keys().iter()
+			.map(|&c| map.get_key_position(c).unwrap())
+			.collect();
+		for kp in &presses {
+			assert_eq!(fingers[kp.column as usize], kp.finger, "position {}", kp.pos);
+			assert_eq!(kp.center, kp.column == 0, "position {}", kp.pos);
+			assert!(kp.column != 5 || kp.hand == Hand::Right, "position {}", kp.pos);
+		}
+		for p in &presses {
+			for c in &presses {
+				if p.same_column(c) {
+					assert!(p.same_finger(c), "positions {} and {}", p.pos, c.pos);
+				}
+			}
+		}
+
+		let qwerty = QWERTY_LAYOUT.get_position_map();
+		let key = |c| qwerty.get_key_position(c).unwrap();
+		assert!(key('t').same_column(&key('g')) && key('t').same_column(&key('b')));
+		assert!(!key('t').same_column(&key('f')) && key('t').same_finger(&key('f')));
+		assert!(!key('t').same_column(&key('y')) && !key('t').same_finger(&key('y')));
+	}
+
+	#[test]
+	fn from_chars_rejects_bad_strings()
+	{
+		let init = "jcyfkzl,uq=rsthdmnaio'/vgpbxw.;-e ";
+		assert_eq!(Layout::from_chars(init), Ok(INIT_LAYOUT.clone()));
+		assert_eq!(Layout::from_chars(&init[..33]), Err(FromCharsError::WrongKeyCount(33)));
+		assert_eq!(Layout::from_chars(""), Err(FromCharsError::WrongKeyCount(0)));
+		assert_eq!(Layout::from_chars(&format!("{}x", init)), Err(FromCharsError::WrongKeyCount(35)));
+		assert_eq!(Layout::from_chars(&init.replace('z', "j")), Err(FromCharsError::DuplicateKey('j')));
+		assert_eq!(Layout::from_chars(&init.replace('z', "ž")), Err(FromCharsError::NonAscii('ž')));
+		assert_eq!(FromCharsError::WrongKeyCount(33).to_string(), "expected 34 keys, found 33");
+	}
+
+	#[test]
+	fn qwerty_and_colemak_differ_on_17_keys()
+	{
+		let diff = QWERTY_LAYOUT.diff(&COLEMAK_LAYOUT);
+		let positions: Vec<usize> = diff.iter().map(|d| d.pos).collect();
+		assert_eq!(positions, [2, 3, 4, 5, 6, 7, 8, 9, 12, 13, 14, 15, 17, 18, 19, 20, 27]);
+		assert_eq!(QWERTY_LAYOUT.distance(&COLEMAK_LAYOUT), 17);
+
+		let n = &diff[16];
+		assert_eq!((n.hand, n.finger, n.row), (Hand::Right, Finger::Index, Row::Bottom));
+		assert_eq!((n.before, n.after), (('n', 'N'), ('k', 'K')));
+		assert_eq!(diff[7].before, ('p', 'P'));
+		assert_eq!(diff[7].after, (';', ':'));
+		assert!(COLEMAK_LAYOUT.diff(&COLEMAK_LAYOUT).is_empty());
+	}
+
+	#[test]
+	fn mirroring_flips_hands_and_twice_is_the_identity()
+	{
+		for (name, layout) in ::layouts::all() {
+			let mirrored = layout.mirror();
+			assert_eq!(&mirrored.mirror(), layout, "{}", name);
+			for layer in 0..2 {
+				for (pos, &c) in layout.layer(layer).unwrap().keys().iter().enumerate() {
+					let to = MIRROR_POSITIONS[pos];
+					assert_eq!(mirrored.char_at(to, layer == 1), Ok(c), "{} position {}", name, pos);
+					if to != pos {
+						assert!(hand_for_pos(to) != hand_for_pos(pos), "{} position {}", name, pos);
+					}
+				}
+			}
+		}
+
+		let fixed: Vec<usize> = (0..34).filter(|&pos| MIRROR_POSITIONS[pos] == pos).collect();
+		assert_eq!(fixed, [10, 21, 32, 33]);
+	}
+
+	#[test]
+	fn equal_layouts_hash_equal_and_a_swap_makes_them_unequal()
+	{
+		use std::collections::hash_map::DefaultHasher;
+		use std::hash::{Hash, Hasher};
+
+		let hash = |layout: &Layout| {
+			let mut hasher = DefaultHasher::new();
+			layout.hash(&mut hasher);
+			hasher.finish()
+		};
+		let built = Layout::from_chars("jcyfkzl,uq=rsthdmnaio'/vgpbxw.;-e ").unwrap();
+		let parsed = Layout::from_str(&INIT_LAYOUT.to_full_string()).unwrap();
+		assert_eq!(built, parsed);
+		assert_eq!(hash(&built), hash(&parsed));
+		assert_eq!(hash(&built), hash(&INIT_LAYOUT));
+
+		let mut shuffled = INIT_LAYOUT.clone();
+		shuffled.shuffle(1);
+		assert!(shuffled != INIT_LAYOUT);
+
+		// Only the upper layer differs.
+		let mut upper = INIT_LAYOUT.clone();
+		upper.set_char_at(10, true, '=').unwrap();
+		assert!(upper != INIT_LAYOUT);
+		assert!(hash(&upper) != hash(&INIT_LAYOUT));
+	}
+
+	#[test]
+	fn positions_past_the_last_key_are_errors()
+	{
+		let mut layout = INIT_LAYOUT.clone();
+		assert_eq!(layout.char_at(32, false), Ok('e'));
+		assert_eq!(layout.char_at(32, true), Ok('E'));
+		assert_eq!(layout.char_at(33, false), Ok(' '));
+		assert_eq!(layout.char_at(34, false), Err(PositionError(34)));
+		assert_eq!(layout.char_at(usize::MAX, true), Err(PositionError(usize::MAX)));
+
+		assert_eq!(layout.set_char_at(32, true, '~'), Ok(()));
+		assert_eq!(layout.char_at(32, true), Ok('~'));
+		assert_eq!(layout.set_char_at(33, false, '\0'), Ok(()));
+		assert_eq!(layout.set_char_at(34, false, 'x'), Err(PositionError(34)));
+		assert_eq!(layout.lower().keys()[33], '\0');
+		assert_eq!(layout.distance(&INIT_LAYOUT), 2);
+	}
+
+	#[test]
+	fn built_in_layouts_parse_back_from_their_full_form()
+	{
+		for (name, layout) in ::layouts::all() {
+			let full = layout.to_full_string();
+			assert_eq!(&Layout::from_str(&full).unwrap(), layout, "{}", name);
+			// The `|` separators are optional.
+			assert_eq!(&Layout::from_str(&full.replace('|', " ")).unwrap(), layout, "{}", name);
+		}
+	}
+
+	#[test]
+	fn sentence_presses_follow_the_position_tables()
+	{
+		let map = QWERTY_LAYOUT.get_position_map();
+		let text = "Hi there,\nyou.\tOK€";
+		let keys = |presses: &[KeyPress]| presses.iter().map(|kp| (kp.pos, kp.shifted)).collect::<Vec<_>>();
+		let check = |presses: &[KeyPress], positions: &[usize]| {
+			assert_eq!(presses.iter().map(|kp| kp.pos).collect::<Vec<_>>(), positions);
+			for kp in presses {
+				assert_eq!(Some(kp.hand), hand_for_pos(kp.pos), "{:?}", kp);
+				assert_eq!(Some(kp.finger), finger_for_pos(kp.pos), "{:?}", kp);
+			}
+		};
+
+		let skipped = [16, 7, 33, 4, 16, 2, 3, 2, 29, 5, 8, 6, 30, 8, 18];
+		let presses: Vec<KeyPress> = map.presses(text).collect();
+		check(&presses, &skipped);
+		assert_eq!(presses.iter().map(|kp| kp.shifted).collect::<Vec<_>>(), [
+			true, false, false, false, false, false, false, false,
+			false, false, false, false, false, true, true]);
+		assert_eq!(keys(&map.presses_with(text, WhitespacePolicy::Skip).collect::<Vec<_>>()), keys(&presses));
+
+		let thumbed: Vec<KeyPress> = map.presses_with(text, WhitespacePolicy::Thumb).collect();
+		check(&thumbed, &[16, 7, 33, 4, 16, 2, 3, 2, 29, 33, 5, 8, 6, 30, 33, 8, 18]);
+		assert_eq!(thumbed[9].kc, ' ');
+
+		for &policy in &[WhitespacePolicy::Skip, WhitespacePolicy::Thumb] {
+			let results: Vec<_> = map.try_presses(text, policy).collect();
+			assert_eq!(results.last().map(|r| r.as_ref().err()), Some(Some(&UnknownChar('€'))));
+			assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+			let presses: Vec<KeyPress> = results.into_iter().filter_map(Result::ok).collect();
+			assert_eq!(keys(&presses), keys(&map.presses_with(text, policy).collect::<Vec<_>>()));
+		}
+	}
+
+	#[test]
+	fn keymaps_iterate_in_position_order()
+	{
+		let mut map: KeyMap<usize> = KeyMap(array::from_fn(|i| i * 10));
+		let expected: Vec<usize> = (0..34).map(|i| i * 10).collect();
+		assert_eq!(map.iter().cloned().collect::<Vec<_>>(), expected);
+		assert_eq!((&map).into_iter().cloned().collect::<Vec<_>>(), expected);
+		for (pos, &v) in map.enumerate_positions() {
+			assert_eq!(v, pos * 10);
+		}
+		assert_eq!(map.enumerate_positions().count(), 34);
+
+		for v in map.iter_mut() {
+			*v += 1;
+		}
+		for (i, v) in (&mut map).into_iter().enumerate() {
+			*v += i;
+		}
+		assert_eq!(map.clone().into_iter().collect::<Vec<_>>(), (0..34).map(|i| i * 11 + 1).collect::<Vec<_>>());
+		assert_eq!(map[33], 33 * 11 + 1);
+	}
+
+	#[test]
+	fn fingers_hands_and_rows_parse_back_from_display()
+	{
+		for &finger in &[Finger::Pinky, Finger::Ring, Finger::Middle, Finger::Index, Finger::Thumb] {
+			assert_eq!(finger.to_string().parse::<Finger>(), Ok(finger));
+		}
+		for &hand in &[Hand::Left, Hand::Right] {
+			assert_eq!(hand.to_string().parse::<Hand>(), Ok(hand));
+		}
+		for &row in &[Row::Top, Row::Home, Row::Bottom, Row::Thumb] {
+			assert_eq!(row.to_string().parse::<Row>(), Ok(row));
+		}
+		assert_eq!("INDEX".parse::<Finger>(), Ok(Finger::Index));
+
+		assert_eq!("toe".parse::<Finger>(), Err(UnknownName("toe".to_string())));
+		assert_eq!("".parse::<Hand>(), Err(UnknownName(String::new())));
+		assert_eq!("left ".parse::<Hand>(), Err(UnknownName("left ".to_string())));
+		assert_eq!("number".parse::<Row>(), Err(UnknownName("number".to_string())));
+		assert_eq!(UnknownName("toe".to_string()).to_string(), "unknown name `toe`");
+	}
+
+	#[test]
+	fn diffs_and_swap_groups_follow_the_keyboard()
+	{
+		// The default board with every key moved to the left hand's home row,
+		// pressed by the index finger.
+		let mut builder = ::keyboard::KeyboardBuilder::new();
+		for key in Keyboard::default_split().keys() {
+			let key = ::keyboard::Key { hand: Hand::Left, finger: Finger::Index, row: Row::Home, ..*key };
+			builder = builder.key(key);
+		}
+		let keyboard: Keyboard = builder.build().unwrap();
+
+		let diff = QWERTY_LAYOUT.diff_on(&keyboard, &COLEMAK_LAYOUT);
+		let positions = |diff: &[KeyDiff]| diff.iter().map(|d| d.pos).collect::<Vec<_>>();
+		assert_eq!(positions(&diff), positions(&QWERTY_LAYOUT.diff(&COLEMAK_LAYOUT)));
+		assert!(diff.iter().all(|d| d.hand == Hand::Left && d.finger == Finger::Index && d.row == Row::Home));
+		assert!(QWERTY_LAYOUT.diff(&COLEMAK_LAYOUT).iter().any(|d| d.hand == Hand::Right));
+
+		for &scope in &[SwapScope::SameHand, SwapScope::SameRow] {
+			assert_eq!(LAYOUT_MASK.swap_groups_on(&keyboard, scope), vec![LAYOUT_MASK.swappable_positions()]);
+			assert!(LAYOUT_MASK.swap_groups(scope).len() > 1);
+		}
+	}
+
+	#[test]
+	fn saved_layouts_load_back_unchanged()
+	{
+		for (name, layout) in ::layouts::all() {
+			let path = ::std::env::temp_dir().join(format!("keygen-{}-{}.layout", name, ::std::process::id()));
+			layout.save_to_file(&path).unwrap();
+			let mut saved = String::new();
+			File::open(&path).unwrap().read_to_string(&mut saved).unwrap();
+			let loaded = Layout::load_from_file(&path);
+			::std::fs::remove_file(&path).unwrap();
+
+			assert!(!saved.contains('\0'), "{}", name);
+			assert_eq!(&loaded.unwrap(), layout, "{}", name);
+		}
+		assert!(QWERTY_LAYOUT.to_full_string().contains(EMPTY_KEY));
+	}
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests
+{
+	extern crate serde_json;
+
+	use super::*;
+
+	#[test]
+	fn layouts_round_trip_through_json()
+	{
+		for layout in &[&INIT_LAYOUT, &DVORAK_LAYOUT] {
+			let json = serde_json::to_string(layout).unwrap();
+			assert_eq!(&serde_json::from_str::<Layout>(&json).unwrap(), *layout);
+		}
+
+		let json = serde_json::to_value(&DVORAK_LAYOUT).unwrap();
+		assert_eq!(json["lower"][0], "'");
+		assert_eq!(json["upper"][0], "\"");
+		assert_eq!(json["lower"].as_array().map(|keys| keys.len()), Some(34));
+		assert!(json.get("extra").is_none());
+	}
+
+	#[test]
+	fn malformed_layers_are_rejected()
+	{
+		let mut json = serde_json::to_value(&INIT_LAYOUT).unwrap();
+		json["lower"].as_array_mut().unwrap().pop();
+		let e = serde_json::from_value::<Layout>(json).unwrap_err();
+		assert!(e.to_string().contains("invalid length 33, expected 34 keys"), "{}", e);
+
+		let mut json = serde_json::to_value(&INIT_LAYOUT).unwrap();
+		json["upper"][3] = "FF".into();
+		assert!(serde_json::from_value::<Layout>(json).is_err());
 	}
 }
@@ -0,0 +1,106 @@
+//! The built-in layouts, by name.
+
+use layout::*;
+
+// Names are lowercase; aliases only work with `by_name`.
+static LAYOUTS: [(&str, &Layout); 10] = [
+	("qwerty",   &QWERTY_LAYOUT),
+	("dvorak",   &DVORAK_LAYOUT),
+	("colemak",  &COLEMAK_LAYOUT),
+	("qgmlwy",   &QGMLWY_LAYOUT),
+	("workman",  &WORKMAN_LAYOUT),
+	("maltron",  &MALTRON_LAYOUT),
+	("mtgap",    &MTGAP_LAYOUT),
+	("capewell", &CAPEWELL_LAYOUT),
+	("arensito", &ARENSITO_LAYOUT),
+	("initial",  &INIT_LAYOUT)];
+
+static ALIASES: [(&str, &str); 4] = [
+	("cmk",  "colemak"),
+	("wkm",  "workman"),
+	("init", "initial"),
+	("carpalx", "qgmlwy")];
+
+pub fn by_name(name: &str)
+-> Option<&'static Layout>
+{
+	let name = name.to_lowercase();
+	let name = ALIASES.iter()
+		.find(|&&(alias, _)| alias == name)
+		.map_or(&name[..], |&(_, target)| target);
+	LAYOUTS.iter()
+		.find(|&&(n, _)| n == name)
+		.map(|&(_, layout)| layout)
+}
+
+pub fn all()
+-> impl Iterator<Item = (&'static str, &'static Layout)>
+{
+	LAYOUTS.iter().cloned()
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// The US layout's shifted punctuation; letters shift to their capitals.
+	static US_SHIFT_PAIRS: [(char, char); 21] = [
+		('`', '~'), ('1', '!'), ('2', '@'), ('3', '#'), ('4', '$'), ('5', '%'),
+		('6', '^'), ('7', '&'), ('8', '*'), ('9', '('), ('0', ')'), ('-', '_'),
+		('=', '+'), ('[', '{'), (']', '}'), ('\\', '|'), (';', ':'), ('\'', '"'),
+		(',', '<'), ('.', '>'), ('/', '?')];
+
+	// Layouts that pair punctuation their own way; only their letters are
+	// checked.
+	static OWN_PUNCTUATION: [&str; 1] = ["mtgap"];
+
+	fn us_shift(c: char)
+	-> char
+	{
+		if c.is_ascii_lowercase() {
+			return c.to_ascii_uppercase();
+		}
+		US_SHIFT_PAIRS.iter()
+			.find(|&&(lower, _)| lower == c)
+			.map_or(c, |&(_, upper)| upper)
+	}
+
+	#[test]
+	fn upper_layers_are_the_us_shift_of_lower_ones()
+	{
+		for (name, layout) in all() {
+			let punctuation = !OWN_PUNCTUATION.contains(&name);
+			let pairs = layout.lower().keys().iter().zip(layout.upper().keys().iter());
+			for (pos, (&lower, &upper)) in pairs.enumerate() {
+				if punctuation || lower.is_ascii_alphabetic() || upper.is_ascii_alphabetic() {
+					assert_eq!(upper, us_shift(lower), "{} position {}", name, pos);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn the_allowlist_only_names_layouts_that_need_it()
+	{
+		for name in OWN_PUNCTUATION.iter() {
+			let layout = by_name(name).unwrap();
+			let mut pairs = layout.lower().keys().iter().zip(layout.upper().keys().iter());
+			assert!(pairs.any(|(&lower, &upper)| upper != us_shift(lower)), "{}", name);
+		}
+	}
+
+	#[test]
+	fn every_registered_layout_is_valid()
+	{
+		for (name, layout) in all() {
+			let empty: Vec<usize> = (0..34).filter(|&pos| layout.char_at(pos, false) == Ok('\0')).collect();
+			let mask = LayoutShuffleMask::from_pinned_positions(&empty);
+			assert_eq!(layout.validate_masked(&mask), Ok(()), "{}", name);
+			assert_eq!(by_name(&name.to_uppercase()), Some(layout));
+		}
+		for &(alias, target) in ALIASES.iter() {
+			assert_eq!(by_name(alias), by_name(target));
+		}
+	}
+}
@@ -0,0 +1,17 @@
+#![feature(linked_list_cursors)]
+
+pub mod annealing;
+pub mod builder;
+pub mod checkpoint;
+pub mod config;
+pub mod corpus;
+pub mod export;
+pub mod import;
+pub mod keyboard;
+pub mod layout;
+pub mod layouts;
+pub mod optimizer;
+pub mod penalty;
+pub mod progress;
+pub mod scoring;
+pub mod simulator;
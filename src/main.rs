@@ -1,25 +1,55 @@
-#![feature(linked_list_cursors)]
-
-mod annealing;
-mod layout;
-mod penalty;
-mod simulator;
-
 extern crate getopts;
+extern crate keygen;
+#[cfg(unix)]
+extern crate libc;
+extern crate rand;
+extern crate rand_chacha;
 
 use getopts::Options;
+use keygen::annealing;
+use keygen::checkpoint;
+use keygen::config;
+use keygen::corpus;
+use keygen::export;
+use keygen::import;
+use keygen::layout;
+use keygen::layouts;
+use keygen::optimizer;
+use keygen::optimizer::genetic;
+use keygen::optimizer::tempering;
+use keygen::penalty;
+use keygen::progress;
+use keygen::scoring;
+use keygen::scoring::LayoutScorer;
+use keygen::simulator;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
 use std::env;
+use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 fn main() {
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("d", "debug", "show debug logging");
+    opts.optflag(
+        "",
+        "weighted-swaps",
+        "swap the keys of common characters more often than rare ones",
+    );
     opts.optopt(
         "t",
         "top",
-        "number of top layouts to print (default: 1)",
+        "number of distinct top layouts to print (default: 1)",
         "TOP_LAYOUTS",
     );
     opts.optopt(
@@ -28,6 +58,215 @@ fn main() {
         "maximum number of swaps per iteration (default: 3)",
         "SWAPS",
     );
+    opts.optopt(
+        "",
+        "swap-schedule",
+        "which swaps each part of a run tries, such as \"any:80,same-hand:20\" (default: any)",
+        "SCHEDULE",
+    );
+    opts.optopt(
+        "",
+        "iterations",
+        "number of annealing iterations per run (default: 15000)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "start-temp",
+        "annealing temperature at the start of a run (default: 1.5)",
+        "TEMP",
+    );
+    opts.optopt(
+        "",
+        "end-temp",
+        "annealing temperature at the end of a run (default: 1.5e^-10)",
+        "TEMP",
+    );
+    opts.optopt(
+        "",
+        "cooling",
+        "how the temperature falls: linear, exponential or geometric (default: exponential)",
+        "SCHEDULE",
+    );
+    opts.optopt(
+        "",
+        "iters-per-temp",
+        "hold each temperature for this many iterations (default: 1)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "target-acceptance",
+        "adjust the temperature to accept this share of moves, falling from START to END over a run, such as \"0.8:0.02\"",
+        "START:END",
+    );
+    opts.optopt(
+        "",
+        "acceptance-window",
+        "number of recent moves the acceptance rate is measured over (default: 100)",
+        "N",
+    );
+    opts.optflag(
+        "",
+        "log-stages",
+        "print the temperature and how many moves were accepted at each stage of a run",
+    );
+    opts.optopt(
+        "",
+        "tabu",
+        "remember this many recently accepted swaps and do not make them again for a while (default: 0)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "tabu-tenure",
+        "number of iterations a remembered swap stays forbidden (default: 100)",
+        "ITERS",
+    );
+    opts.optopt(
+        "",
+        "patience",
+        "stop a run once this many moves in a row find no new best layout, or with --target-acceptance once the temperature is below --end-temp; 0 for never (default: 0)",
+        "MOVES",
+    );
+    opts.optopt(
+        "",
+        "cycle-rate",
+        "chance that an iteration rotates three keys instead of swapping (default: 0)",
+        "P",
+    );
+    opts.optopt(
+        "",
+        "start",
+        "start from a built-in layout (see list-layouts), a random one, or \"greedy\" to put the commonest characters on the best keys",
+        "NAME",
+    );
+    opts.optopt(
+        "",
+        "layout-string",
+        "start from the layout whose keys, in position order, are KEYS",
+        "KEYS",
+    );
+    opts.optopt(
+        "c",
+        "config",
+        "TOML file with the starting layout and pinned keys",
+        "CONFIG",
+    );
+    opts.optopt(
+        "",
+        "import-kla",
+        "start from a keyboard-layout-analyzer JSON layout",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "apply-swaps",
+        "swap pairs of characters on the starting layout, such as \"et,io\"",
+        "PAIRS",
+    );
+    opts.optopt(
+        "",
+        "pin",
+        "keep these characters where they are on the starting layout, such as \"zxcv,.\"",
+        "KEYS",
+    );
+    opts.optopt(
+        "",
+        "restarts",
+        "run the optimizer this many times from shuffled starts, then print a summary and stop",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "multi-start",
+        "run the optimizer once from every built-in layout with the same budget, then compare the runs and stop: builtin",
+        "MODE",
+    );
+    opts.optopt(
+        "",
+        "algorithm",
+        "search with simulated annealing, a genetic algorithm or parallel tempering: anneal, ga or tempering (default: anneal)",
+        "NAME",
+    );
+    opts.optflag("", "tempering", "same as --algorithm tempering");
+    opts.optopt(
+        "",
+        "population",
+        "number of layouts in each generation of the genetic algorithm (default: 200)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "generations",
+        "number of generations the genetic algorithm runs for (default: 500)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "replicas",
+        "number of chains parallel tempering runs, at temperatures from --start-temp to --end-temp (default: 8)",
+        "N",
+    );
+    opts.optflag(
+        "",
+        "no-polish",
+        "do not finish each result with greedy swaps until none improves it",
+    );
+    opts.optopt(
+        "",
+        "checkpoint",
+        "save the progress of the run to this file as it goes, for --resume; later runs save to FILE with .1, .2 and so on before the extension",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "checkpoint-every",
+        "save a checkpoint after this many new best layouts; 0 for never (default: 100)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "checkpoint-interval",
+        "also save a checkpoint once this many seconds have passed since the last; 0 for never (default: 300)",
+        "SECS",
+    );
+    opts.optopt(
+        "",
+        "resume",
+        "carry on the run saved in this checkpoint, with the same corpus and options",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "threads",
+        "run the restarts, or the --multi-start chains, on this many threads; without either, run one restart per thread (default: 1)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "restart-shuffle",
+        "number of swaps that shuffle the starting layout for each restart (default: 10)",
+        "SWAPS",
+    );
+    opts.optopt(
+        "",
+        "random-baselines",
+        "also score this many random layouts, for comparison (default: 0)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "seed",
+        "seed the random number generator, so that runs can be repeated",
+        "SEED",
+    );
+    opts.optopt(
+        "e",
+        "export",
+        "also write the best layout in this format (qmk, xkb, klc, kanata, keylayout, csv, kle)",
+        "FORMAT",
+    );
 
     let args: Vec<String> = env::args().collect();
     let progname = &args[0];
@@ -39,7 +278,7 @@ fn main() {
     let matches = match opts.parse(&args[2..]) {
         Ok(m) => m,
         Err(f) => {
-            panic!(f.to_string())
+            panic!("{}", f)
         }
     };
 
@@ -49,8 +288,35 @@ fn main() {
         return;
     }
 
+    if command == "list-layouts" {
+        list_layouts();
+        return;
+    }
+
+    if command == "diff" {
+        match (matches.free.first(), matches.free.get(1)) {
+            (Some(a), Some(b)) => diff(&named_layout(a), &named_layout(b)),
+            _ => print_usage(progname, opts),
+        }
+        return;
+    }
+
+    // With the same seed, corpus and options, runs give the same layouts.
+    // This is the generator behind `StdRng`, used by name so that checkpoints
+    // can save its position.
+    let mut rng = match matches.opt_str("seed") {
+        None => ChaCha12Rng::from_entropy(),
+        Some(seed) => match seed.parse::<u64>() {
+            Ok(seed) => ChaCha12Rng::seed_from_u64(seed),
+            Err(e) => {
+                println!("Error: {}", e);
+                panic!("could not parse options");
+            }
+        },
+    };
+
     // Read corpus.
-    let corpus_filename = match matches.free.get(0) {
+    let corpus_filename = match matches.free.first() {
         Some(f) => f,
         None => {
             print_usage(progname, opts);
@@ -78,47 +344,586 @@ fn main() {
     let layout = match matches.free.get(1) {
         None => &layout::INIT_LAYOUT,
         Some(layout_filename) => {
-            let mut f = match File::open(layout_filename) {
-                Ok(f) => f,
+            _layout = match layout::Layout::load_from_file(Path::new(layout_filename)) {
+                Ok(l) => l,
                 Err(e) => {
                     println!("Error: {}", e);
                     panic!("could not read layout");
                 }
             };
-            let mut layout_str = String::new();
-            match f.read_to_string(&mut layout_str) {
-                Ok(_) => (),
+            &_layout
+        }
+    };
+
+    // Layout string, if applicable.
+    let _from_chars;
+    let layout = match matches.opt_str("layout-string") {
+        None => layout,
+        Some(chars) => {
+            _from_chars = match layout::Layout::from_chars(&chars) {
+                Ok(l) => l,
                 Err(e) => {
                     println!("Error: {}", e);
                     panic!("could not read layout");
                 }
             };
-            _layout = layout::Layout::from_string(&layout_str[..]);
-            &_layout
+            &_from_chars
+        }
+    };
+
+    // Built-in starting layout, if applicable.
+    let _started;
+    let layout = match matches.opt_str("start") {
+        None => layout,
+        Some(ref name) if name == "random" => {
+            _started = random_layout(layout, &mut rng);
+            &_started
+        }
+        Some(ref name) if name == "greedy" => {
+            _started = layout.greedy_seed(&corpus[..]);
+            &_started
+        }
+        Some(name) => match layouts::by_name(&name) {
+            Some(l) => l,
+            None => {
+                println!("Error: no built-in layout named {}", name);
+                panic!("could not find layout");
+            }
+        },
+    };
+
+    // Import layout, if applicable.
+    let _imported;
+    let layout = match matches.opt_str("import-kla") {
+        None => layout,
+        Some(kla_filename) => {
+            let json = match fs::read_to_string(&kla_filename) {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    panic!("could not read layout");
+                }
+            };
+            _imported = match import::kla::parse(&json) {
+                Ok(i) => i,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    panic!("could not import layout");
+                }
+            };
+            if !_imported.skipped.is_empty() {
+                let keys: String = _imported.skipped.iter().collect();
+                println!("Warning: skipped keys outside the layout: {}", keys);
+            }
+            &_imported.layout
+        }
+    };
+
+    // Read config, if applicable. Its layout takes precedence over the ones
+    // given on the command line.
+    // Without a config, empty keys stay where they are rather than being
+    // shuffled in among the characters.
+    let _config;
+    let default_mask = empty_keys_pinned(layout);
+    let (layout, mask) = match matches.opt_str("c") {
+        None => (layout, &default_mask),
+        Some(config_filename) => {
+            _config = match config::Config::from_file(Path::new(&config_filename)) {
+                Ok(c) => c,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    panic!("could not read config");
+                }
+            };
+            (&_config.layout, &_config.mask)
+        }
+    };
+
+    // Apply swaps, if applicable.
+    let _swapped;
+    let layout = match matches.opt_str("apply-swaps") {
+        None => layout,
+        Some(pairs) => {
+            let mut swapped = layout.clone();
+            for pair in pairs.split(',') {
+                let chars: Vec<char> = pair.trim().chars().collect();
+                if chars.len() != 2 {
+                    println!("Error: `{}` is not a pair of characters", pair);
+                    panic!("could not parse options");
+                }
+                if let Err(e) = swapped.swap_chars_masked(chars[0], chars[1], mask) {
+                    println!("Error: {}", e);
+                    panic!("could not apply swaps");
+                }
+            }
+            _swapped = swapped;
+            &_swapped
+        }
+    };
+
+    // Pin keys, if applicable.
+    let _pinned;
+    let mask = match matches.opt_str("pin") {
+        None => mask,
+        Some(keys) => {
+            _pinned = pin_keys(layout, mask, &keys);
+            &_pinned
         }
     };
 
     // Parse options.
     let debug = matches.opt_present("d");
+    let weighted = matches.opt_present("weighted-swaps");
+    let log_stages = matches.opt_present("log-stages");
+    let polish = !matches.opt_present("no-polish");
+    let algorithm = match matches.opt_str("algorithm") {
+        None if matches.opt_present("tempering") => optimizer::Algorithm::Tempering,
+        None => optimizer::Algorithm::Anneal,
+        Some(name) => match name.parse() {
+            Ok(optimizer::Algorithm::Tempering) => optimizer::Algorithm::Tempering,
+            Ok(_) if matches.opt_present("tempering") => {
+                println!("Error: --tempering picks the algorithm, so --algorithm `{}` cannot be used with it", name);
+                panic!("could not parse options");
+            }
+            Ok(a) => a,
+            Err(e) => {
+                println!("Error: {}", e);
+                panic!("could not parse options");
+            }
+        },
+    };
+    let genetic_defaults = genetic::GeneticParams::default();
+    let genetic_params = genetic::GeneticParams {
+        population: numopt(matches.opt_str("population"), genetic_defaults.population),
+        generations: numopt(matches.opt_str("generations"), genetic_defaults.generations),
+        ..genetic_defaults
+    };
     let top = numopt(matches.opt_str("t"), 1usize);
-    let swaps = numopt(matches.opt_str("s"), 3usize);
+    let swaps = numopt(matches.opt_str("s"), simulator::DEFAULT_NUM_SWAPS);
+    let baselines = numopt(matches.opt_str("random-baselines"), 0usize);
+    let threads = numopt(matches.opt_str("threads"), 1usize).max(1);
+    let restart_shuffle = numopt(matches.opt_str("restart-shuffle"), 10usize);
+    let multi_start = match matches.opt_str("multi-start") {
+        None => false,
+        Some(ref mode) if mode == "builtin" => true,
+        Some(mode) => {
+            println!(
+                "Error: `{}` is not a multi-start mode (expected builtin)",
+                mode
+            );
+            panic!("could not parse options");
+        }
+    };
+    let restarts = match matches.opt_str("restarts") {
+        None if threads > 1 && !multi_start => Some((threads, restart_shuffle)),
+        None => None,
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) if n > 0 => Some((n, restart_shuffle)),
+            _ => {
+                println!("Error: `{}` is not a number of restarts", n);
+                panic!("could not parse options");
+            }
+        },
+    };
+    if multi_start && (restarts.is_some() || algorithm != optimizer::Algorithm::Anneal) {
+        println!("Error: --multi-start runs one annealing chain per layout, without restarts");
+        panic!("could not parse options");
+    }
+    let defaults = annealing::AnnealingParams::default();
+    let params = annealing::AnnealingParams {
+        iterations: numopt(matches.opt_str("iterations"), defaults.iterations),
+        start_temp: numopt(matches.opt_str("start-temp"), defaults.start_temp),
+        end_temp: numopt(matches.opt_str("end-temp"), defaults.end_temp),
+        iters_per_temp: numopt(matches.opt_str("iters-per-temp"), defaults.iters_per_temp),
+        cycle_rate: numopt(matches.opt_str("cycle-rate"), defaults.cycle_rate),
+        tabu: numopt(matches.opt_str("tabu"), defaults.tabu),
+        tabu_tenure: numopt(matches.opt_str("tabu-tenure"), defaults.tabu_tenure),
+        patience: numopt(matches.opt_str("patience"), defaults.patience),
+        adaptive: match matches.opt_str("target-acceptance") {
+            None => None,
+            Some(target) => match target.parse::<annealing::AdaptiveTarget>() {
+                Ok(t) => Some(annealing::AdaptiveTarget {
+                    window: numopt(matches.opt_str("acceptance-window"), t.window),
+                    ..t
+                }),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    panic!("could not parse options");
+                }
+            },
+        },
+        schedule: match matches.opt_str("cooling") {
+            None => defaults.schedule,
+            Some(cooling) => match cooling.parse() {
+                Ok(c) => c,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    panic!("could not parse options");
+                }
+            },
+        },
+    };
+    let tempering_defaults = tempering::TemperingParams::default();
+    let tempering_params = tempering::TemperingParams {
+        replicas: numopt(matches.opt_str("replicas"), tempering_defaults.replicas),
+        hot_temp: params.start_temp,
+        cold_temp: params.end_temp,
+        sweeps: (params.iterations / tempering_defaults.moves_per_sweep).max(1),
+        num_swaps: swaps,
+        ..tempering_defaults
+    };
+    let schedule = match matches.opt_str("swap-schedule") {
+        None => simulator::SwapSchedule::default(),
+        Some(schedule) => match schedule.parse() {
+            Ok(s) => s,
+            Err(e) => {
+                println!("Error: {}", e);
+                panic!("could not parse options");
+            }
+        },
+    };
+    let export = match matches.opt_str("e") {
+        None => None,
+        Some(format) => match format.parse::<export::Format>() {
+            Ok(f) => Some(f),
+            Err(e) => {
+                println!("Error: {}", e);
+                panic!("could not parse options");
+            }
+        },
+    };
+
+    // Checkpoints go to --checkpoint, or back to the file being resumed.
+    let resume = matches.opt_str("resume").map(|path| {
+        match checkpoint::Checkpoint::load(Path::new(&path)) {
+            Ok(c) => (path, c),
+            Err(e) => {
+                println!("Error: {}", e);
+                panic!("could not read checkpoint");
+            }
+        }
+    });
+    let checkpoint_path = matches
+        .opt_str("checkpoint")
+        .or_else(|| resume.as_ref().map(|r| r.0.clone()));
+    let checkpoints = match checkpoint_path {
+        None => None,
+        Some(_)
+            if restarts.is_some() || multi_start || algorithm != optimizer::Algorithm::Anneal =>
+        {
+            println!("Error: checkpoints only work with a single annealing run");
+            panic!("could not parse options");
+        }
+        Some(path) => {
+            let secs = numopt(matches.opt_str("checkpoint-interval"), 300u64);
+            let policy = checkpoint::CheckpointPolicy {
+                path: PathBuf::from(path),
+                every: numopt(matches.opt_str("checkpoint-every"), 100usize),
+                interval: (secs > 0).then_some(Duration::from_secs(secs)),
+                corpus_hash: checkpoint::corpus_hash(&corpus),
+            };
+            Some((policy, resume.map(|r| r.1)))
+        }
+    };
 
     match command.as_ref() {
-        "run" => run(&corpus[..], layout, debug, top, swaps),
+        "run" if algorithm == optimizer::Algorithm::Genetic => run_genetic(
+            &corpus[..],
+            layout,
+            mask,
+            export,
+            &genetic_params,
+            polish,
+            &mut rng,
+        ),
+        "run" if algorithm == optimizer::Algorithm::Tempering => run_tempering(
+            &corpus[..],
+            layout,
+            mask,
+            export,
+            &tempering_params,
+            polish,
+            &mut rng,
+        ),
+        "run" => run(
+            &corpus[..],
+            layout,
+            mask,
+            debug,
+            top,
+            swaps,
+            baselines,
+            export,
+            &schedule,
+            &params,
+            weighted,
+            log_stages,
+            restarts,
+            threads,
+            multi_start,
+            polish,
+            checkpoints,
+            &mut rng,
+        ),
         "run-ref" => run_ref(&corpus[..]),
-        "refine" => refine(&corpus[..], layout, debug, top, swaps),
+        "refine" => refine(
+            &corpus[..],
+            layout,
+            mask,
+            debug,
+            top,
+            swaps,
+            baselines,
+            export,
+            &mut rng,
+        ),
         _ => print_usage(progname, opts),
     };
 }
 
-fn run(s: &str, layout: &layout::Layout, debug: bool, top: usize, swaps: usize) {
+#[allow(clippy::too_many_arguments)]
+fn run(
+    s: &str,
+    layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    debug: bool,
+    top: usize,
+    swaps: usize,
+    baselines: usize,
+    export: Option<export::Format>,
+    schedule: &simulator::SwapSchedule,
+    params: &annealing::AnnealingParams,
+    weighted: bool,
+    log_stages: bool,
+    restarts: Option<(usize, usize)>,
+    threads: usize,
+    multi_start: bool,
+    polish: bool,
+    checkpoints: Option<(checkpoint::CheckpointPolicy, Option<checkpoint::Checkpoint>)>,
+    rng: &mut ChaCha12Rng,
+) {
+    check_layout(layout, mask);
+    let penalties = penalty::init();
+    let init_pos_map = layout::INIT_LAYOUT.get_position_map();
+    let quartads = penalty::prepare_quartad_list(s, &init_pos_map);
+    let len = s.len();
+    let scorer = scoring::DefaultScorer::new(&quartads, len, &penalties);
+    print_random_baselines(&quartads, len, layout, &penalties, baselines, rng);
+    let mut sink = progress::StdoutProgress::new(&quartads, len, &penalties, debug, log_stages);
+    let weights = if weighted {
+        Some(corpus::Corpus::from_text(s))
+    } else {
+        None
+    };
+
+    if multi_start {
+        run_multi_start(
+            &quartads,
+            len,
+            &penalties,
+            top,
+            swaps,
+            schedule,
+            params,
+            weights.as_ref(),
+            &mut sink,
+            threads,
+            polish,
+            export,
+            rng,
+        );
+        return;
+    }
+
+    if let Some((count, shuffle)) = restarts {
+        // Each restart draws from its own generator, seeded from `rng`, so
+        // that a seeded run is reproducible restart by restart, on any number
+        // of threads.
+        let seeds: Vec<u64> = (0..count).map(|_| rng.gen()).collect();
+        let mut results = Vec::new();
+        let mut interrupted = false;
+        if threads > 1 {
+            let (threaded, stopped) = run_threaded(
+                &scorer,
+                layout,
+                mask,
+                swaps,
+                shuffle,
+                schedule,
+                params,
+                weights.as_ref(),
+                &seeds,
+                threads,
+            );
+            results = threaded;
+            interrupted = stopped;
+        }
+        if interrupted {
+            println!();
+            println!("Interrupted; keeping the best layouts found so far.");
+            if results.is_empty() {
+                return;
+            }
+        } else {
+            for (restart, &seed) in seeds.iter().enumerate().skip(results.len()) {
+                let mut restart_rng = StdRng::seed_from_u64(seed);
+                let mut start = layout.clone();
+                start.shuffle_masked(shuffle, mask, &mut restart_rng);
+                let best = simulator::simulate(
+                    &scorer,
+                    &start,
+                    mask,
+                    top,
+                    swaps,
+                    schedule,
+                    params,
+                    weights.as_ref(),
+                    &mut sink,
+                    &mut restart_rng,
+                );
+                let penalty = scorer.score(&best);
+                results.push((restart, (best, penalty)));
+            }
+        }
+
+        println!();
+        println!("restart  penalty");
+        for &(restart, (_, penalty)) in &results {
+            println!("{:>7}  {}", restart + 1, penalty);
+        }
+        let &(restart, (ref best, penalty)) = results
+            .iter()
+            .min_by(|a, b| (a.1).1.partial_cmp(&(b.1).1).unwrap())
+            .unwrap();
+        println!();
+        println!(
+            "Best of {} restarts: restart {}, penalty {}",
+            results.len(),
+            restart + 1,
+            penalty
+        );
+        println!("{}", best.to_full_string());
+        let best = polish_result(&quartads, len, best, mask, &penalties, polish);
+        if let Some(format) = export {
+            export_layout(&best, format, &quartads);
+        }
+        return;
+    }
+
+    // Only the first run carries on from the checkpoint; the runs after it
+    // start afresh, each saving to a checkpoint of its own so that the one
+    // resumed from is kept.
+    let (policy, mut resume) = match checkpoints {
+        Some((policy, resume)) => (Some(policy), resume),
+        None => (None, None),
+    };
+    for run in 0.. {
+        let best = match policy {
+            Some(ref policy) => match simulator::simulate_checkpointed(
+                &scorer,
+                layout,
+                mask,
+                top,
+                swaps,
+                schedule,
+                params,
+                weights.as_ref(),
+                &mut sink,
+                rng,
+                resume.take(),
+                &policy.for_run(run),
+            ) {
+                Ok(best) => best,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    panic!("could not checkpoint the run");
+                }
+            },
+            None => simulator::simulate(
+                &scorer,
+                layout,
+                mask,
+                top,
+                swaps,
+                schedule,
+                params,
+                weights.as_ref(),
+                &mut sink,
+                rng,
+            ),
+        };
+        let best = polish_result(&quartads, len, &best, mask, &penalties, polish);
+        if let Some(format) = export {
+            export_layout(&best, format, &quartads);
+        }
+    }
+}
+
+// Runs the genetic algorithm once, printing the best and mean penalty of
+// every generation, then prints and exports the best layout.
+fn run_genetic(
+    s: &str,
+    layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    export: Option<export::Format>,
+    params: &genetic::GeneticParams,
+    polish: bool,
+    rng: &mut ChaCha12Rng,
+) {
+    check_layout(layout, mask);
     let penalties = penalty::init();
     let init_pos_map = layout::INIT_LAYOUT.get_position_map();
     let quartads = penalty::prepare_quartad_list(s, &init_pos_map);
     let len = s.len();
 
-    loop {
-        simulator::simulate(&quartads, len, layout, &penalties, debug, top, swaps);
+    let scorer = scoring::DefaultScorer::new(&quartads, len, &penalties);
+
+    let (best, _) = genetic::evolve(&scorer, layout, mask, params, rng, |generation, stats| {
+        println!(
+            "Generation {}: best {}, mean {}",
+            generation, stats.best, stats.mean
+        );
+    });
+    println!();
+    let penalty = penalty::calculate_penalty(&quartads, len, &best, &penalties, true);
+    simulator::print_result(&best, &penalty);
+    let best = polish_result(&quartads, len, &best, mask, &penalties, polish);
+    if let Some(format) = export {
+        export_layout(&best, format, &quartads);
+    }
+}
+
+// Runs parallel tempering once, printing how the coldest level stands after
+// every sweep, then prints and exports the best layout.
+fn run_tempering(
+    s: &str,
+    layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    export: Option<export::Format>,
+    params: &tempering::TemperingParams,
+    polish: bool,
+    rng: &mut ChaCha12Rng,
+) {
+    check_layout(layout, mask);
+    let penalties = penalty::init();
+    let init_pos_map = layout::INIT_LAYOUT.get_position_map();
+    let quartads = penalty::prepare_quartad_list(s, &init_pos_map);
+    let len = s.len();
+
+    let scorer = scoring::DefaultScorer::new(&quartads, len, &penalties);
+
+    let (best, _) = tempering::temper(&scorer, layout, mask, params, rng, |sweep, stats| {
+        println!(
+            "Sweep {}: best {}, coldest {}, {} exchanges",
+            sweep, stats.best, stats.coldest, stats.exchanges
+        );
+    });
+    println!();
+    let penalty = penalty::calculate_penalty(&quartads, len, &best, &penalties, true);
+    simulator::print_result(&best, &penalty);
+    let best = polish_result(&quartads, len, &best, mask, &penalties, polish);
+    if let Some(format) = export {
+        export_layout(&best, format, &quartads);
     }
 }
 
@@ -128,77 +933,532 @@ fn run_ref(s: &str) {
     let quartads = penalty::prepare_quartad_list(s, &init_pos_map);
     let len = s.len();
 
-    let penalty =
-        penalty::calculate_penalty(&quartads, len, &layout::QWERTY_LAYOUT, &penalties, true);
-    println!("Reference: QWERTY");
-    simulator::print_result(&layout::QWERTY_LAYOUT, &penalty);
-    println!("");
-
-    let penalty =
-        penalty::calculate_penalty(&quartads, len, &layout::DVORAK_LAYOUT, &penalties, true);
-    println!("Reference: DVORAK");
-    simulator::print_result(&layout::DVORAK_LAYOUT, &penalty);
-    println!("");
-
-    let penalty =
-        penalty::calculate_penalty(&quartads, len, &layout::COLEMAK_LAYOUT, &penalties, true);
-    println!("Reference: COLEMAK");
-    simulator::print_result(&layout::COLEMAK_LAYOUT, &penalty);
-    println!("");
-
-    let penalty =
-        penalty::calculate_penalty(&quartads, len, &layout::QGMLWY_LAYOUT, &penalties, true);
-    println!("Reference: QGMLWY");
-    simulator::print_result(&layout::QGMLWY_LAYOUT, &penalty);
-    println!("");
-
-    let penalty =
-        penalty::calculate_penalty(&quartads, len, &layout::WORKMAN_LAYOUT, &penalties, true);
-    println!("Reference: WORKMAN");
-    simulator::print_result(&layout::WORKMAN_LAYOUT, &penalty);
-    println!("");
-
-    let penalty =
-        penalty::calculate_penalty(&quartads, len, &layout::MALTRON_LAYOUT, &penalties, true);
-    println!("Reference: MALTRON");
-    simulator::print_result(&layout::MALTRON_LAYOUT, &penalty);
-    println!("");
-
-    let penalty =
-        penalty::calculate_penalty(&quartads, len, &layout::MTGAP_LAYOUT, &penalties, true);
-    println!("Reference: MTGAP");
-    simulator::print_result(&layout::MTGAP_LAYOUT, &penalty);
-    println!("");
-
-    let penalty =
-        penalty::calculate_penalty(&quartads, len, &layout::CAPEWELL_LAYOUT, &penalties, true);
-    println!("Reference: CAPEWELL");
-    simulator::print_result(&layout::CAPEWELL_LAYOUT, &penalty);
-    println!("");
-
-    let penalty =
-        penalty::calculate_penalty(&quartads, len, &layout::ARENSITO_LAYOUT, &penalties, true);
-    println!("Reference: ARENSITO");
-    simulator::print_result(&layout::ARENSITO_LAYOUT, &penalty);
-    println!("");
-
-    let penalty =
-        penalty::calculate_penalty(&quartads, len, &layout::INIT_LAYOUT, &penalties, true);
-    println!("Reference: INITIAL");
-    simulator::print_result(&layout::INIT_LAYOUT, &penalty);
-}
-
-fn refine(s: &str, layout: &layout::Layout, debug: bool, top: usize, swaps: usize) {
+    let corpus = corpus::Corpus::from_text(s);
+
+    for (i, (name, layout)) in layouts::all().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let penalty = penalty::calculate_penalty(&quartads, len, layout, &penalties, true);
+        println!("Reference: {}", name.to_uppercase());
+        simulator::print_result(layout, &penalty);
+        println!(
+            "same-finger bigrams: {}",
+            layout.same_finger_bigrams(&corpus)
+        );
+        println!("same-finger skipgrams: {}", layout.same_finger_skipgrams(s));
+        println!(
+            "hand alternation: {:.1}%",
+            layout.hand_alternation_rate(&corpus) * 100.0
+        );
+        let (inward, outward) = layout.roll_counts(&corpus);
+        println!("rolls: {} inward, {} outward", inward, outward);
+        println!("redirects: {}", layout.redirects(s));
+        println!("finger travel: {:.1}", layout.total_travel(&corpus));
+        println!("estimated time: {:.1}", layout.estimated_time(&corpus));
+        println!(
+            "thumb usage: {:.1}%",
+            layout.thumb_usage_rate(&corpus) * 100.0
+        );
+        println!(
+            "home row: {:.1}%",
+            layout.home_row_rate(&corpus, false) * 100.0
+        );
+        println!("lateral reaches: {}", layout.lateral_reaches(&corpus));
+    }
+}
+
+fn list_layouts() {
+    for (name, layout) in layouts::all() {
+        println!("{}", name);
+        println!("{:#}", layout);
+        println!();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn refine(
+    s: &str,
+    layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    debug: bool,
+    top: usize,
+    swaps: usize,
+    baselines: usize,
+    export: Option<export::Format>,
+    rng: &mut ChaCha12Rng,
+) {
+    check_layout(layout, mask);
     let penalties = penalty::init();
     let init_pos_map = layout::INIT_LAYOUT.get_position_map();
     let quartads = penalty::prepare_quartad_list(s, &init_pos_map);
     let len = s.len();
 
-    simulator::refine(&quartads, len, layout, &penalties, debug, top, swaps);
+    print_random_baselines(&quartads, len, layout, &penalties, baselines, rng);
+
+    let best = simulator::refine(&quartads, len, layout, mask, &penalties, debug, top, swaps);
+    if let Some(format) = export {
+        export_layout(&best, format, &quartads);
+    }
+}
+
+// `mask` with the positions of `keys` on `layout` pinned as well. Prints the
+// positions it pinned.
+fn pin_keys(
+    layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    keys: &str,
+) -> layout::LayoutShuffleMask {
+    let mut pinned = mask.clone();
+    match pinned.pin_chars(layout, keys) {
+        Ok(positions) => {
+            let positions: Vec<String> = positions
+                .iter()
+                .zip(keys.chars())
+                .map(|(pos, c)| format!("{} ({})", pos, c))
+                .collect();
+            println!("pinned positions: {}", positions.join(", "));
+        }
+        Err(e) => {
+            println!("Error: `{}` is not on the starting layout", e.0);
+            panic!("could not pin keys");
+        }
+    }
+    pinned
+}
+
+// The characters `base` can swap, in a random arrangement. Keys that cannot
+// be swapped stay as they are in `base`.
+fn random_layout(base: &layout::Layout, rng: &mut ChaCha12Rng) -> layout::Layout {
+    let mask = &layout::LAYOUT_MASK;
+    let keys = base.lower().keys();
+    let alphabet: Vec<char> = mask
+        .swappable_positions()
+        .into_iter()
+        .map(|pos| keys[pos])
+        .filter(|&c| c != '\0')
+        .collect();
+    let mut random = match layout::Layout::random(&alphabet, rng) {
+        Some(l) => l,
+        None => {
+            println!("Error: the starting layout repeats a character");
+            panic!("could not make a random layout");
+        }
+    };
+    for pos in (0..keys.len()).filter(|&pos| !mask.is_swappable(pos)) {
+        for &shifted in &[false, true] {
+            let c = base.char_at(pos, shifted).unwrap();
+            random.set_char_at(pos, shifted, c).unwrap();
+        }
+    }
+    random
+}
+
+// Scores `n` random arrangements of `base` and prints the mean and standard
+// deviation of their scaled penalties.
+fn print_random_baselines<'a>(
+    quartads: &penalty::QuartadList<'a>,
+    len: usize,
+    base: &layout::Layout,
+    penalties: &Vec<penalty::KeyPenalty<'a>>,
+    n: usize,
+    rng: &mut ChaCha12Rng,
+) {
+    if n == 0 {
+        return;
+    }
+    let scores: Vec<f64> = (0..n)
+        .map(|_| {
+            penalty::calculate_penalty(quartads, len, &random_layout(base, rng), penalties, false).1
+        })
+        .collect();
+    let mean = scores.iter().sum::<f64>() / n as f64;
+    let variance = scores.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+    println!(
+        "random baselines ({}): mean {}; stddev {}",
+        n,
+        mean,
+        variance.sqrt()
+    );
+}
+
+fn check_layout(layout: &layout::Layout, mask: &layout::LayoutShuffleMask) {
+    if let Err(errors) = layout.validate_masked(mask) {
+        for e in errors {
+            println!("Error: {}", e);
+        }
+        panic!("invalid starting layout");
+    }
+}
+
+fn export_layout(layout: &layout::Layout, format: export::Format, quartads: &penalty::QuartadList) {
+    let unmapped = format.unmapped_keys(layout);
+    if !unmapped.is_empty() {
+        let keys: String = unmapped.into_iter().collect();
+        println!("Warning: no {:?} equivalent for keys: {}", format, keys);
+    }
+
+    let path = format.file_name();
+    let stats = penalty::calculate_position_stats(quartads, layout);
+    match fs::write(path, format.export_with_stats(layout, Some(&stats))) {
+        Ok(_) => println!("Exported to {}", path),
+        Err(e) => {
+            println!("Error: {}", e);
+            panic!("could not write export");
+        }
+    }
+}
+
+// `layout` polished with `simulator::polish`, printed if that changed it, or
+// `layout` itself if `polish` is off.
+fn polish_result(
+    quartads: &penalty::QuartadList,
+    len: usize,
+    layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    penalties: &Vec<penalty::KeyPenalty>,
+    polish: bool,
+) -> layout::Layout {
+    if !polish {
+        return layout.clone();
+    }
+    let scorer = scoring::DefaultScorer::new(quartads, len, penalties);
+    let (polished, _, swaps) = simulator::polish(&scorer, layout, mask);
+    println!();
+    if swaps == 0 {
+        println!("Polish: no swap improves the layout");
+    } else {
+        println!("Polished with {} swaps:", swaps);
+        let penalty = penalty::calculate_penalty(quartads, len, &polished, penalties, true);
+        simulator::print_result(&polished, &penalty);
+    }
+    polished
+}
+
+// Runs one chain from each built-in layout, with its empty keys pinned and
+// the same budget for every start, then prints each start's penalty before
+// and after and how much lower it ended up, and which start did best. As
+// with restarts, each chain has its own generator seeded from `rng`, so the
+// table is the same on any number of threads.
+#[allow(clippy::too_many_arguments)]
+fn run_multi_start<'a>(
+    quartads: &penalty::QuartadList<'a>,
+    len: usize,
+    penalties: &Vec<penalty::KeyPenalty<'a>>,
+    top: usize,
+    swaps: usize,
+    schedule: &simulator::SwapSchedule,
+    params: &annealing::AnnealingParams,
+    weights: Option<&corpus::Corpus>,
+    sink: &mut dyn progress::ProgressSink,
+    threads: usize,
+    polish: bool,
+    export: Option<export::Format>,
+    rng: &mut ChaCha12Rng,
+) {
+    let scorer = scoring::DefaultScorer::new(quartads, len, penalties);
+    let results = multi_start_chains(
+        &scorer, top, swaps, schedule, params, weights, sink, threads, rng,
+    );
+
+    println!();
+    println!(
+        "{:<10}  {:>12}  {:>12}  {:>11}",
+        "start", "initial", "final", "improvement"
+    );
+    for &(name, initial, _, penalty, _) in &results {
+        let improvement = if initial == 0.0 {
+            0.0
+        } else {
+            (initial - penalty) / initial * 100.0
+        };
+        println!(
+            "{:<10}  {:>12.6}  {:>12.6}  {:>10.2}%",
+            name, initial, penalty, improvement
+        );
+    }
+    let &(name, _, ref best, penalty, ref mask) = results
+        .iter()
+        .min_by(|a, b| a.3.partial_cmp(&b.3).unwrap())
+        .unwrap();
+    println!();
+    println!(
+        "Best of {} starts: {}, penalty {}",
+        results.len(),
+        name,
+        penalty
+    );
+    println!("{}", best.to_full_string());
+    let best = polish_result(quartads, len, best, mask, penalties, polish);
+    if let Some(format) = export {
+        export_layout(&best, format, quartads);
+    }
+}
+
+// One start of `run_multi_start`: its name, penalty, best layout, that
+// layout's penalty and the mask it was annealed with.
+type MultiStartResult = (
+    &'static str,
+    f64,
+    layout::Layout,
+    f64,
+    layout::LayoutShuffleMask,
+);
+
+// The chains of `run_multi_start`, in the order of `layouts::all`. On one
+// thread each chain reports to `sink`; on more, the chains run with
+// `simulator::anneal_penalty`, which finds the same layouts without
+// reporting.
+#[allow(clippy::too_many_arguments)]
+fn multi_start_chains<S: LayoutScorer + Sync>(
+    scorer: &S,
+    top: usize,
+    swaps: usize,
+    schedule: &simulator::SwapSchedule,
+    params: &annealing::AnnealingParams,
+    weights: Option<&corpus::Corpus>,
+    sink: &mut dyn progress::ProgressSink,
+    threads: usize,
+    rng: &mut ChaCha12Rng,
+) -> Vec<MultiStartResult> {
+    let starts: Vec<_> = layouts::all()
+        .map(|(name, start)| {
+            let mask = empty_keys_pinned(start);
+            check_layout(start, &mask);
+            (name, start, mask, rng.gen::<u64>())
+        })
+        .collect();
+
+    let chain = |k: usize, sink: Option<&mut dyn progress::ProgressSink>| {
+        let (name, start, ref mask, seed) = starts[k];
+        let mut start_rng = StdRng::seed_from_u64(seed);
+        let initial = scorer.score(start);
+        let best = match sink {
+            Some(sink) => simulator::simulate(
+                scorer,
+                start,
+                mask,
+                top,
+                swaps,
+                schedule,
+                params,
+                weights,
+                sink,
+                &mut start_rng,
+            ),
+            None => {
+                simulator::anneal_penalty(
+                    scorer,
+                    start,
+                    mask,
+                    swaps,
+                    schedule,
+                    params,
+                    weights,
+                    &mut start_rng,
+                    |_, _| (),
+                )
+                .0
+            }
+        };
+        let penalty = scorer.score(&best);
+        (name, initial, best, penalty, mask.clone())
+    };
+
+    if threads <= 1 {
+        return (0..starts.len())
+            .map(|k| chain(k, Some(&mut *sink)))
+            .collect();
+    }
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|t| {
+                let chain = &chain;
+                let count = starts.len();
+                scope.spawn(move || {
+                    (t..count)
+                        .step_by(threads)
+                        .map(|k| (k, chain(k, None)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut results: Vec<_> = workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect();
+        results.sort_by_key(|&(k, _)| k);
+        results.into_iter().map(|(_, result)| result).collect()
+    })
+}
+
+// `LAYOUT_MASK` with the empty keys of `layout` pinned.
+fn empty_keys_pinned(layout: &layout::Layout) -> layout::LayoutShuffleMask {
+    let mut mask = layout::LAYOUT_MASK.clone();
+    for (pos, c) in layout.lower().keys().iter().enumerate() {
+        if *c == '\0' {
+            mask.pin(pos);
+        }
+    }
+    mask
+}
+
+// Set by the Ctrl-C handler that `catch_interrupts` installs.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// While `catch` is set, Ctrl-C sets `INTERRUPTED` instead of ending the
+// process, and a second Ctrl-C ends it as usual.
+#[cfg(unix)]
+fn catch_interrupts(catch: bool) {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    let handler = if catch {
+        on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t
+    } else {
+        libc::SIG_DFL
+    };
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+#[cfg(not(unix))]
+fn catch_interrupts(_catch: bool) {}
+
+// A restart's number in the seeds, best layout and penalty.
+type RestartResult = (usize, (layout::Layout, f64));
+
+// Runs one restart per seed in `seeds` on `threads` worker threads, each
+// chain starting from `layout` shuffled by `shuffle` swaps. The workers share
+// `scorer` and report each new best penalty over a channel, which is
+// printed as the best so far across all workers. Returns each restart's
+// number in `seeds`, best layout and penalty, in the order of `seeds`,
+// however the chains were spread over the threads. On Ctrl-C the running
+// chains stop with what they have, no more are started, and the last flag
+// returned is set.
+#[allow(clippy::too_many_arguments)]
+fn run_threaded<S: LayoutScorer + Sync>(
+    scorer: &S,
+    layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    swaps: usize,
+    shuffle: usize,
+    schedule: &simulator::SwapSchedule,
+    params: &annealing::AnnealingParams,
+    weights: Option<&corpus::Corpus>,
+    seeds: &[u64],
+    threads: usize,
+) -> (Vec<RestartResult>, bool) {
+    catch_interrupts(true);
+    let (tx, rx) = mpsc::channel();
+    let mut results: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|t| {
+                let tx = tx.clone();
+                scope.spawn(move || {
+                    let mut results = Vec::new();
+                    for restart in (t..seeds.len()).step_by(threads) {
+                        if INTERRUPTED.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let mut rng = StdRng::seed_from_u64(seeds[restart]);
+                        let mut start = layout.clone();
+                        start.shuffle_masked(shuffle, mask, &mut rng);
+                        let best = simulator::anneal_penalty_until(
+                            scorer,
+                            &start,
+                            mask,
+                            swaps,
+                            schedule,
+                            params,
+                            weights,
+                            &INTERRUPTED,
+                            &mut rng,
+                            |_, penalty| {
+                                let _ = tx.send((restart, penalty));
+                            },
+                        );
+                        results.push((restart, best));
+                    }
+                    results
+                })
+            })
+            .collect();
+        drop(tx);
+
+        let mut best = f64::INFINITY;
+        for (restart, penalty) in rx {
+            if penalty < best {
+                best = penalty;
+                println!("best so far: {} (restart {})", penalty, restart + 1);
+            }
+        }
+
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect()
+    });
+    let interrupted = INTERRUPTED.load(Ordering::SeqCst);
+    catch_interrupts(false);
+
+    results.sort_by_key(|&(restart, _)| restart);
+    (results, interrupted)
+}
+
+// A built-in layout, or else a layout file in the grid format.
+fn named_layout(name: &str) -> layout::Layout {
+    if let Some(layout) = layouts::by_name(name) {
+        return layout.clone();
+    }
+    match layout::Layout::load_from_file(Path::new(name)) {
+        Ok(layout) => layout,
+        Err(e) => {
+            println!("Error: {}", e);
+            panic!("could not read layout");
+        }
+    }
+}
+
+fn diff(a: &layout::Layout, b: &layout::Layout) {
+    let changed: Vec<usize> = a.diff(b).iter().map(|d| d.pos).collect();
+    print_marked(a, &changed);
+    println!();
+    print_marked(b, &changed);
+    println!();
+    println!("{} keys changed", changed.len());
+}
+
+// The lower layer, with the keys at `marked` positions in brackets.
+fn print_marked(layout: &layout::Layout, marked: &[usize]) {
+    let keys = layout.lower().keys();
+    for &(start, left, right) in layout::GRID_ROWS.iter() {
+        let mut line = "   ".repeat(5 - left);
+        for (pos, c) in keys.iter().enumerate().skip(start).take(left + right) {
+            if pos == start + left {
+                line.push_str(" |");
+            }
+            if marked.contains(&pos) {
+                line.push_str(&format!("[{}]", c));
+            } else {
+                line.push_str(&format!(" {} ", c));
+            }
+        }
+        println!("{}", line.trim_end());
+    }
 }
 
 fn print_usage(progname: &String, opts: Options) {
-    let brief = format!("Usage: {} (run|run-ref) <corpus> [OPTIONS]", progname);
+    let brief = format!(
+        "Usage: {} (run|run-ref|refine) <corpus> [OPTIONS]\n       {} list-layouts\n       {} diff <layout> <layout>",
+        progname, progname, progname
+    );
     print!("{}", opts.usage(&brief));
 }
 
@@ -220,3 +1480,45 @@ where
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TEXT: &str = "The quick brown fox jumps over the lazy dog, and then sleeps.";
+
+    fn table(threads: usize, seed: u64) -> Vec<(&'static str, f64, layout::Layout, f64)> {
+        let penalties = penalty::init();
+        let map = layout::INIT_LAYOUT.get_position_map();
+        let quartads = penalty::prepare_quartad_list(TEXT, &map);
+        let scorer = scoring::DefaultScorer::new(&quartads, TEXT.len(), &penalties);
+        let params = annealing::AnnealingParams {
+            iterations: 300,
+            ..annealing::AnnealingParams::default()
+        };
+        let mut rng = ChaCha12Rng::seed_from_u64(seed);
+        multi_start_chains(
+            &scorer,
+            1,
+            3,
+            &simulator::SwapSchedule::default(),
+            &params,
+            None,
+            &mut progress::NoProgress,
+            threads,
+            &mut rng,
+        )
+        .into_iter()
+        .map(|(name, initial, best, penalty, _)| (name, initial, best, penalty))
+        .collect()
+    }
+
+    #[test]
+    fn multi_start_table_is_the_same_on_any_number_of_threads() {
+        let on_one = table(1, 11);
+        assert_eq!(on_one.len(), layouts::all().count());
+        assert_eq!(on_one, table(1, 11));
+        assert_eq!(on_one, table(3, 11));
+        assert_eq!(on_one, table(4, 11));
+    }
+}
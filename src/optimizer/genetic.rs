@@ -0,0 +1,175 @@
+//! A genetic algorithm over layouts.
+//!
+//! Each individual is an arrangement of the starting layout's keys, kept as
+//! the position each key started on. Parents are picked by tournament and
+//! combined with cycle crossover, which takes every key from one parent or
+//! the other and so always gives a valid arrangement; positions the mask
+//! pins are the same in every individual and stay put. Children are mutated
+//! with a single swap, and the best individual always survives.
+
+extern crate rand;
+extern crate rayon;
+
+use self::rand::Rng;
+use self::rayon::prelude::*;
+
+use layout::Layout;
+use layout::LayoutShuffleMask;
+use scoring::LayoutScorer;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeneticParams
+{
+	pub population:    usize,
+	pub generations:   usize,
+	// How many individuals each tournament draws; the best of them wins.
+	pub tournament:    usize,
+	pub mutation_rate: f64,
+}
+
+// The penalties of one generation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GenerationStats
+{
+	pub best: f64,
+	pub mean: f64,
+}
+
+// For each position, the position in the starting layout its key came from.
+type Arrangement = Vec<usize>;
+
+impl Default for GeneticParams
+{
+	fn default()
+	-> GeneticParams
+	{
+		GeneticParams {
+			population: 200,
+			generations: 500,
+			tournament: 3,
+			mutation_rate: 0.2,
+		}
+	}
+}
+
+// Evolves arrangements of `init`, scored by `scorer`, and returns
+// the best layout found with its penalty. The first generation is `init`
+// and shuffles of it. `report` is called after every generation, counting
+// from 1. Everything random is drawn from `rng`, so a seeded generator
+// reproduces the run.
+pub fn evolve<S: LayoutScorer + Sync>(scorer: &S, init: &Layout, mask: &LayoutShuffleMask,
+	params: &GeneticParams, rng: &mut impl Rng,
+	mut report: impl FnMut(usize, &GenerationStats))
+-> (Layout, f64)
+{
+	let identity: Arrangement = (0..init.lower().keys().len()).collect();
+	let mut arrangements = vec![identity.clone()];
+	while arrangements.len() < params.population.max(2) {
+		let mut a = identity.clone();
+		for _ in 0..a.len() {
+			mutate(&mut a, mask, rng);
+		}
+		arrangements.push(a);
+	}
+	let mut population = score(scorer, init, arrangements);
+
+	for generation in 1..=params.generations {
+		let elite = best(&population).clone();
+		let mut children = vec![elite.0];
+		while children.len() < population.len() {
+			let a = tournament(&population, params.tournament, rng);
+			let b = tournament(&population, params.tournament, rng);
+			let mut child = cycle_crossover(&population[a].0, &population[b].0);
+			if rng.gen::<f64>() < params.mutation_rate {
+				mutate(&mut child, mask, rng);
+			}
+			children.push(child);
+		}
+		population = score(scorer, init, children);
+
+		let mean = population.iter().map(|i| i.1).sum::<f64>() / population.len() as f64;
+		report(generation, &GenerationStats { best: best(&population).1, mean });
+	}
+
+	let (arrangement, penalty) = best(&population).clone();
+	(arrange(init, &arrangement), penalty)
+}
+
+// `init` with its keys moved as `arrangement` says, on every layer.
+fn arrange(init: &Layout, arrangement: &[usize])
+-> Layout
+{
+	// `origin[pos]` is the position the key now at `pos` started on.
+	let mut layout = init.clone();
+	let mut origin: Vec<usize> = (0..arrangement.len()).collect();
+	for (to, &from) in arrangement.iter().enumerate() {
+		let at = origin.iter().position(|&p| p == from).unwrap();
+		layout.swap_positions(at, to);
+		origin.swap(at, to);
+	}
+	layout
+}
+
+// Penalties for `arrangements`, worked out in parallel.
+fn score<S: LayoutScorer + Sync>(scorer: &S, init: &Layout, arrangements: Vec<Arrangement>)
+-> Vec<(Arrangement, f64)>
+{
+	arrangements.into_par_iter().map(|a| {
+		let penalty = scorer.score(&arrange(init, &a));
+		(a, penalty)
+	}).collect()
+}
+
+// The first of the lowest penalties.
+fn best(population: &[(Arrangement, f64)])
+-> &(Arrangement, f64)
+{
+	population.iter().fold(&population[0], |b, i| if i.1 < b.1 { i } else { b })
+}
+
+// The index of the best of `size` individuals drawn at random.
+fn tournament(population: &[(Arrangement, f64)], size: usize, rng: &mut impl Rng)
+-> usize
+{
+	let mut winner = rng.gen_range(0..population.len());
+	for _ in 1..size.max(1) {
+		let i = rng.gen_range(0..population.len());
+		if population[i].1 < population[winner].1 {
+			winner = i;
+		}
+	}
+	winner
+}
+
+// Cycle crossover: the positions split into cycles, where following a key of
+// `a` to where `b` has it leads back to the start, and the child takes its
+// keys from `a` and `b` on alternate cycles.
+fn cycle_crossover(a: &[usize], b: &[usize])
+-> Arrangement
+{
+	let mut child = vec![usize::MAX; a.len()];
+	let mut from_a = true;
+	for start in 0..a.len() {
+		if child[start] != usize::MAX {
+			continue;
+		}
+		let mut pos = start;
+		loop {
+			child[pos] = if from_a { a[pos] } else { b[pos] };
+			pos = a.iter().position(|&k| k == b[pos]).unwrap();
+			if pos == start {
+				break;
+			}
+		}
+		from_a = !from_a;
+	}
+	child
+}
+
+// One swap of two positions `mask` leaves free, as `Layout::shuffle` makes.
+fn mutate(arrangement: &mut Arrangement, mask: &LayoutShuffleMask, rng: &mut impl Rng)
+{
+	if let Some((i, j)) = Layout::propose_swap(mask, rng) {
+		arrangement.swap(i, j);
+	}
+}
@@ -0,0 +1,48 @@
+//! Search strategies other than the annealing in `simulator`, scored with the
+//! same penalty model.
+
+pub mod genetic;
+pub mod tempering;
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// Which search `run` uses.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Algorithm
+{
+	Anneal,
+	Genetic,
+	Tempering,
+}
+
+#[derive(Debug)]
+pub struct UnknownAlgorithm(pub String);
+
+impl FromStr for Algorithm
+{
+	type Err = UnknownAlgorithm;
+
+	fn from_str(s: &str)
+	-> Result<Algorithm, UnknownAlgorithm>
+	{
+		match &s.to_lowercase()[..] {
+			"anneal" | "sa" => Ok(Algorithm::Anneal),
+			"genetic" | "ga" => Ok(Algorithm::Genetic),
+			"tempering" | "pt" => Ok(Algorithm::Tempering),
+			_ => Err(UnknownAlgorithm(s.to_string())),
+		}
+	}
+}
+
+impl fmt::Display for UnknownAlgorithm
+{
+	fn fmt(&self, f: &mut fmt::Formatter)
+	-> fmt::Result
+	{
+		write!(f, "unknown algorithm `{}` (expected anneal, ga or tempering)", self.0)
+	}
+}
+
+impl Error for UnknownAlgorithm {}
@@ -0,0 +1,164 @@
+//! Parallel tempering, also known as replica exchange.
+//!
+//! Several annealing chains, the replicas, run side by side, each at a fixed
+//! temperature on a geometric ladder from hot to cold. After every sweep of
+//! moves a coordinator offers each pair of neighbouring levels the chance to
+//! trade layouts, by the Metropolis exchange criterion, so that a layout the
+//! hot chains have carried out of a local optimum can be settled by the cold
+//! ones.
+
+extern crate rand;
+extern crate rayon;
+
+use self::rand::rngs::StdRng;
+use self::rand::Rng;
+use self::rand::SeedableRng;
+use self::rayon::prelude::*;
+
+use annealing;
+use layout::Layout;
+use layout::LayoutPosMap;
+use layout::LayoutShuffleMask;
+use scoring::LayoutScorer;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperingParams
+{
+	pub replicas:        usize,
+	pub hot_temp:        f64,
+	pub cold_temp:       f64,
+	pub sweeps:          usize,
+	pub moves_per_sweep: usize,
+	// The most swaps one move makes, as in `simulator::simulate`.
+	pub num_swaps:       usize,
+}
+
+// How a run stood after one sweep and its exchanges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SweepStats
+{
+	// The lowest penalty seen at the coldest level so far.
+	pub best:      f64,
+	// The penalty of the coldest replica's current layout.
+	pub coldest:   f64,
+	pub exchanges: usize,
+}
+
+// One chain, at the temperature of its level. `best` is the best layout seen
+// at this level, whichever replica brought it there.
+struct Replica
+{
+	layout:      Layout,
+	map:         LayoutPosMap,
+	penalty:     f64,
+	temperature: f64,
+	best:        (Layout, f64),
+	rng:         StdRng,
+}
+
+impl Default for TemperingParams
+{
+	fn default()
+	-> TemperingParams
+	{
+		let annealing = annealing::AnnealingParams::default();
+		TemperingParams {
+			replicas: 8,
+			hot_temp: annealing.start_temp,
+			cold_temp: annealing.end_temp,
+			sweeps: 150,
+			moves_per_sweep: 100,
+			num_swaps: 3,
+		}
+	}
+}
+
+impl TemperingParams
+{
+	// The temperature of each level, hottest first: `hot_temp` to
+	// `cold_temp` in equal ratios.
+	pub fn temperatures(&self)
+	-> Vec<f64>
+	{
+		let n = self.replicas.max(1);
+		(0..n).map(|k| {
+			let t = if n == 1 { 1.0 } else { k as f64 / (n - 1) as f64 };
+			self.hot_temp * (self.cold_temp / self.hot_temp).powf(t)
+		}).collect()
+	}
+}
+
+// Runs `params.replicas` chains from `init` and returns the best layout seen
+// at the coldest level, with its penalty. `report` is called after every
+// sweep, counting from 1. Each replica draws its moves from a generator
+// seeded from `rng`, and the exchanges are drawn from `rng` itself, so a
+// seeded run is reproducible however the replicas are spread over threads.
+pub fn temper<S: LayoutScorer + Sync>(scorer: &S, init: &Layout, mask: &LayoutShuffleMask,
+	params: &TemperingParams, rng: &mut impl Rng,
+	mut report: impl FnMut(usize, &SweepStats))
+-> (Layout, f64)
+{
+	let penalty = scorer.score(init);
+	let mut replicas: Vec<Replica> = params.temperatures().into_iter().map(|temperature| Replica {
+		layout: init.clone(),
+		map: init.get_position_map(),
+		penalty,
+		temperature,
+		best: (init.clone(), penalty),
+		rng: StdRng::seed_from_u64(rng.gen()),
+	}).collect();
+
+	for sweep in 1..=params.sweeps {
+		replicas.par_iter_mut().for_each(|r| {
+			for _ in 0..params.moves_per_sweep {
+				r.step(scorer, mask, params.num_swaps);
+			}
+		});
+
+		let mut exchanges = 0;
+		for k in 0..replicas.len() - 1 {
+			let (hot, cold) = (&replicas[k], &replicas[k + 1]);
+			let delta = (1.0 / hot.temperature - 1.0 / cold.temperature) * (hot.penalty - cold.penalty);
+			if delta >= 0.0 || rng.gen::<f64>() < delta.exp() {
+				let (left, right) = replicas.split_at_mut(k + 1);
+				let (hot, cold) = (&mut left[k], &mut right[0]);
+				std::mem::swap(&mut hot.layout, &mut cold.layout);
+				std::mem::swap(&mut hot.map, &mut cold.map);
+				std::mem::swap(&mut hot.penalty, &mut cold.penalty);
+				hot.note_best();
+				cold.note_best();
+				exchanges += 1;
+			}
+		}
+
+		let coldest = replicas.last().unwrap();
+		report(sweep, &SweepStats { best: coldest.best.1, coldest: coldest.penalty, exchanges });
+	}
+
+	replicas.pop().unwrap().best
+}
+
+impl Replica
+{
+	// One move at this level's temperature.
+	fn step<S: LayoutScorer>(&mut self, scorer: &S, mask: &LayoutShuffleMask, num_swaps: usize)
+	{
+		let times = self.rng.gen_range(1..=num_swaps.max(1));
+		let swaps = self.layout.shuffle_masked(times, mask, &mut self.rng);
+		let penalty = scorer.rescore(&self.layout, &mut self.map, self.penalty, &swaps);
+		if annealing::accept_at(penalty - self.penalty, self.temperature, &mut self.rng) {
+			self.penalty = penalty;
+			self.note_best();
+		} else {
+			self.layout.undo_swaps(&swaps);
+			self.map.undo_swaps(&swaps);
+		}
+	}
+
+	fn note_best(&mut self)
+	{
+		if self.penalty < self.best.1 {
+			self.best = (self.layout.clone(), self.penalty);
+		}
+	}
+}
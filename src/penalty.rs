@@ -1,8 +1,7 @@
-/// Methods for calculating the penalty of a keyboard layout given an input
-/// corpus string.
+//! Methods for calculating the penalty of a keyboard layout given an input
+//! corpus string.
 
 use std::vec::Vec;
-use std::ops::Range;
 use std::collections::HashMap;
 use std::fmt;
 
@@ -29,6 +28,23 @@ pub struct KeyPenaltyResult<'a>
 
 pub struct QuartadList<'a>(HashMap<&'a str, usize>);
 
+// The quartads of a `QuartadList` with the ones each character is in, so that
+// the penalty of the quartads a swap touches can be summed without going
+// through the rest.
+pub struct QuartadIndex<'a>
+{
+	quartads:   Vec<(&'a str, usize)>,
+	containing: Vec<(char, Vec<usize>)>,
+}
+
+// What one key position contributed to a score.
+#[derive(Clone, Copy, Default)]
+pub struct PositionStats
+{
+	pub presses: usize,
+	pub penalty: f64,
+}
+
 impl <'a> fmt::Display for KeyPenaltyResult<'a>
 {
 	fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -42,6 +58,7 @@ static BASE_PENALTY: KeyMap<f64> = KeyMap([
 	2.0, 2.0, 1.5, 1.5, 2.5,    2.5, 1.5, 1.5, 2.0, 2.0,
 	                    0.0,    0.0]);
 
+#[allow(clippy::vec_init_then_push)]
 pub fn init<'a>()
 -> Vec<KeyPenalty<'a>>
 {
@@ -130,21 +147,22 @@ pub fn prepare_quartad_list<'a>(
 	position_map: &'a LayoutPosMap)
 -> QuartadList<'a>
 {
-	let mut range: Range<usize> = 0..0;
+	// Byte offsets of the last four characters that are on the layout.
+	let mut starts: Vec<usize> = Vec::with_capacity(4);
 	let mut quartads: HashMap<&str, usize> = HashMap::new();
-	for (i, c) in string.chars().enumerate() {
+	for (i, c) in string.char_indices() {
 		match *position_map.get_key_position(c) {
 			Some(_) => {
-				range.end = i + 1;
-				if range.end > 3 && range.start < range.end - 4 {
-					range.start = range.end - 4;
+				if starts.len() == 4 {
+					starts.remove(0);
 				}
-				let quartad = &string[range.clone()];
+				starts.push(i);
+				let quartad = &string[starts[0]..(i + c.len_utf8())];
 				let entry = quartads.entry(quartad).or_insert(0);
 				*entry += 1;
 			},
 			None => {
-				range = (i + 1)..(i + 1);
+				starts.clear();
 			}
 		}
 	}
@@ -182,6 +200,80 @@ pub fn calculate_penalty<'a>(
 	(total, total / (len as f64), result)
 }
 
+impl <'a> QuartadIndex<'a>
+{
+	pub fn new(quartads: &QuartadList<'a>)
+	-> QuartadIndex<'a>
+	{
+		let QuartadList(ref quartads) = *quartads;
+		let quartads: Vec<(&'a str, usize)> = quartads.iter().map(|(&s, &n)| (s, n)).collect();
+		let mut containing: HashMap<char, Vec<usize>> = HashMap::new();
+		for (i, &(string, _)) in quartads.iter().enumerate() {
+			for c in string.chars() {
+				let list = containing.entry(c).or_default();
+				if list.last() != Some(&i) {
+					list.push(i);
+				}
+			}
+		}
+		let mut containing: Vec<(char, Vec<usize>)> = containing.into_iter().collect();
+		containing.sort_by_key(|&(c, _)| c);
+		QuartadIndex { quartads, containing }
+	}
+
+	// How much the total penalty changes from `before` to `after`, where
+	// `after` is `before` with keys swapped among `positions`. Only the
+	// quartads with a character on one of `positions` can change.
+	pub fn penalty_change(&self, positions: &[usize], before: &LayoutPosMap, after: &LayoutPosMap)
+	-> f64
+	{
+		let mut seen = vec![false; self.quartads.len()];
+		let mut unused = Vec::new();
+		let mut change = 0.0;
+		for &(c, ref list) in &self.containing {
+			match *before.get_key_position(c) {
+				Some(ref kp) if positions.contains(&kp.pos) => (),
+				_ => continue,
+			}
+			for &i in list {
+				if seen[i] {
+					continue;
+				}
+				seen[i] = true;
+				let (string, count) = self.quartads[i];
+				change += penalty_for_quartad(string, count, after, &mut unused, false)
+				        - penalty_for_quartad(string, count, before, &mut unused, false);
+			}
+		}
+		change
+	}
+}
+
+// Splits the score of `layout` by key: each quartad's presses and penalty go
+// to the position of its last character, which is the key being pressed.
+pub fn calculate_position_stats<'a>(
+	quartads: &QuartadList<'a>,
+	layout:   &Layout)
+-> KeyMap<PositionStats>
+{
+	let QuartadList(ref quartads) = *quartads;
+	let mut stats = KeyMap([PositionStats::default(); 34]);
+	let mut unused = Vec::new();
+
+	let position_map = layout.get_position_map();
+	for (string, count) in quartads {
+		let pos = match string.chars().last().map(|c| position_map.get_key_position(c)) {
+			Some(Some(kp)) => kp.pos,
+			_ => continue,
+		};
+		let penalty = penalty_for_quartad(string, *count, &position_map, &mut unused, false);
+		stats.0[pos].presses += *count;
+		stats.0[pos].penalty += penalty;
+	}
+
+	stats
+}
+
 fn penalty_for_quartad<'a, 'b>(
 	string:       &'a str,
 	count:            usize,
@@ -190,16 +282,16 @@ fn penalty_for_quartad<'a, 'b>(
 	detailed:         bool)
 -> f64
 {
-	let mut chars = string.chars().into_iter().rev();
+	let mut chars = string.chars().rev();
 	let opt_curr = chars.next();
 	let opt_old1 = chars.next();
 	let opt_old2 = chars.next();
 	let opt_old3 = chars.next();
 
 	let curr = match opt_curr {
-		Some(c) => match position_map.get_key_position(c) {
-			&Some(ref kp) => kp,
-			&None => { return 0.0 }
+		Some(c) => match *position_map.get_key_position(c) {
+			Some(ref kp) => kp,
+			None => { return 0.0 }
 		},
 		None => panic!("unreachable")
 	};
@@ -216,26 +308,26 @@ fn penalty_for_quartad<'a, 'b>(
 		None => &KP_NONE
 	};
 
-	penalize(string, count, &curr, old1, old2, old3, result, detailed)
+	penalize(string, count, curr, old1, old2, old3, result, detailed)
 }
 
-fn penalize<'a, 'b>(
+#[allow(clippy::too_many_arguments)]
+fn penalize<'a>(
 	string: &'a     str,
 	count:          usize,
 	curr:   &              KeyPress,
 	old1:   &       Option<KeyPress>,
 	old2:   &       Option<KeyPress>,
 	old3:   &       Option<KeyPress>,
-	result: &'b mut Vec<KeyPenaltyResult<'a>>,
+	result: &mut [KeyPenaltyResult<'a>],
 	detailed:       bool)
 -> f64
 {
-	let len = string.len();
 	let count = count as f64;
 	let mut total = 0.0;
 
 	// One key penalties.
-	let slice1 = &string[(len - 1)..len];
+	let slice1 = last_chars(string, 1);
 
 	// 0: Base penalty.
 	let base = BASE_PENALTY.0[curr.pos] * count;
@@ -252,10 +344,11 @@ fn penalize<'a, 'b>(
 	};
 
 	if curr.hand == old1.hand {
-		let slice2 = &string[(len - 2)..len];
+		let slice2 = last_chars(string, 2);
 
-		// 1: Same finger.
-		if curr.finger == old1.finger && curr.pos != old1.pos {
+		// 1: Same finger. Two thumb keys on one hand are not one finger's
+		// work.
+		if curr.finger == old1.finger && curr.finger != Finger::Thumb && curr.pos != old1.pos {
 			let penalty = 5.0 + if curr.center { 5.0 } else { 0.0 }
 			                  + if old1.center { 5.0 } else { 0.0 };
 			let penalty = penalty * count;
@@ -278,35 +371,33 @@ fn penalize<'a, 'b>(
 		}
 
 		// 3: Long jump.
-		if curr.hand == old1.hand && curr.finger == old1.finger {
-			if curr.row == Row::Top && old1.row == Row::Bottom ||
-			   curr.row == Row::Bottom && old1.row == Row::Top {
-				let penalty = 10.0 * count;
-				if detailed {
-					*result[3].high_keys.entry(slice2).or_insert(0.0) += penalty;
-					result[3].total += penalty;
-				}
-				total += penalty;
+		if curr.hand == old1.hand && curr.finger == old1.finger &&
+		   (curr.row == Row::Top && old1.row == Row::Bottom ||
+		    curr.row == Row::Bottom && old1.row == Row::Top) {
+			let penalty = 10.0 * count;
+			if detailed {
+				*result[3].high_keys.entry(slice2).or_insert(0.0) += penalty;
+				result[3].total += penalty;
 			}
+			total += penalty;
 		}
 
 		// 4: Long jump consecutive.
-		if curr.row == Row::Top && old1.row == Row::Bottom ||
-		   curr.row == Row::Bottom && old1.row == Row::Top {
-			if curr.finger == Finger::Ring   && old1.finger == Finger::Pinky  ||
-			   curr.finger == Finger::Pinky  && old1.finger == Finger::Ring   ||
-			   curr.finger == Finger::Middle && old1.finger == Finger::Ring   ||
-			   curr.finger == Finger::Ring   && old1.finger == Finger::Middle ||
-			  (curr.finger == Finger::Index  && (old1.finger == Finger::Middle ||
-			                                     old1.finger == Finger::Ring) &&
-			   curr.row == Row::Top && old1.row == Row::Bottom) {
-				let penalty = 5.0 * count;
-				if detailed {
-					*result[4].high_keys.entry(slice2).or_insert(0.0) += penalty;
-					result[4].total += penalty;
-				}
-				total += penalty;
+		if (curr.row == Row::Top && old1.row == Row::Bottom ||
+		    curr.row == Row::Bottom && old1.row == Row::Top) &&
+		   (curr.finger == Finger::Ring   && old1.finger == Finger::Pinky  ||
+		    curr.finger == Finger::Pinky  && old1.finger == Finger::Ring   ||
+		    curr.finger == Finger::Middle && old1.finger == Finger::Ring   ||
+		    curr.finger == Finger::Ring   && old1.finger == Finger::Middle ||
+		   (curr.finger == Finger::Index  && (old1.finger == Finger::Middle ||
+		                                      old1.finger == Finger::Ring) &&
+		    curr.row == Row::Top && old1.row == Row::Bottom)) {
+			let penalty = 5.0 * count;
+			if detailed {
+				*result[4].high_keys.entry(slice2).or_insert(0.0) += penalty;
+				result[4].total += penalty;
 			}
+			total += penalty;
 		}
 
 		// 5: Pinky/ring twist.
@@ -336,8 +427,11 @@ fn penalize<'a, 'b>(
 			total += penalty;
 		}
 
-		// 10: Roll in.
-		if curr.hand == old1.hand && is_roll_in(curr.finger, old1.finger) {
+		// 10: Roll in. `Finger::Thumb` sorts after the index finger, but going
+		// on to a thumb is not a roll.
+		if curr.hand == old1.hand &&
+		   curr.finger != Finger::Thumb &&
+		   is_roll_in(curr.finger, old1.finger) {
 			let penalty = -0.125 * count;
 			if detailed {
 				*result[10].high_keys.entry(slice2).or_insert(0.0) += penalty;
@@ -357,7 +451,7 @@ fn penalize<'a, 'b>(
 		// 6: Roll reversal.
 		if (curr.finger == Finger::Middle && old1.finger == Finger::Pinky && old2.finger == Finger::Ring) ||
 		    curr.finger == Finger::Ring && old1.finger == Finger::Pinky && old2.finger == Finger::Middle {
-			let slice3 = &string[(len - 3)..len];
+			let slice3 = last_chars(string, 3);
 			let penalty = 20.0 * count;
 			if detailed {
 				*result[6].high_keys.entry(slice3).or_insert(0.0) += penalty;
@@ -371,7 +465,7 @@ fn penalize<'a, 'b>(
 		    (curr.row == Row::Bottom && old1.row == Row::Home && old2.row == Row::Top)) &&
 		   ((is_roll_out(curr.finger, old1.finger) && is_roll_out(old1.finger, old2.finger)) ||
 		   	(is_roll_in(curr.finger, old1.finger) && is_roll_in(old1.finger, old2.finger))) {
-			let slice3 = &string[(len - 3)..len];
+			let slice3 = last_chars(string, 3);
 			let penalty = 10.0 * count;
 			if detailed {
 				*result[12].high_keys.entry(slice3).or_insert(0.0) += penalty;
@@ -382,17 +476,16 @@ fn penalize<'a, 'b>(
 	}
 
 	// 11: Long jump sandwich.
-	if curr.hand == old2.hand && curr.finger == old2.finger {
-		if curr.row == Row::Top && old2.row == Row::Bottom ||
-		   curr.row == Row::Bottom && old2.row == Row::Top {
-			let penalty = 3.0 * count;
-			if detailed {
-				let slice3 = &string[(len - 3)..len];
-				*result[11].high_keys.entry(slice3).or_insert(0.0) += penalty;
-				result[11].total += penalty;
-			}
-			total += penalty;
+	if curr.hand == old2.hand && curr.finger == old2.finger &&
+	   (curr.row == Row::Top && old2.row == Row::Bottom ||
+	    curr.row == Row::Bottom && old2.row == Row::Top) {
+		let penalty = 3.0 * count;
+		if detailed {
+			let slice3 = last_chars(string, 3);
+			*result[11].high_keys.entry(slice3).or_insert(0.0) += penalty;
+			result[11].total += penalty;
 		}
+		total += penalty;
 	}
 
 	// Four key penalties.
@@ -403,7 +496,7 @@ fn penalize<'a, 'b>(
 
 	if curr.hand == old1.hand && old1.hand == old2.hand && old2.hand == old3.hand {
 		// 7: Same hand.
-		let slice4 = &string[(len - 4)..len];
+		let slice4 = last_chars(string, 4);
 		let penalty = 0.5 * count;
 		if detailed {
 			*result[7].high_keys.entry(slice4).or_insert(0.0) += penalty;
@@ -412,7 +505,7 @@ fn penalize<'a, 'b>(
 		total += penalty;
 	} else if curr.hand != old1.hand && old1.hand != old2.hand && old2.hand != old3.hand {
 		// 8: Alternating hand.
-		let slice4 = &string[(len - 4)..len];
+		let slice4 = last_chars(string, 4);
 		let penalty = 0.5 * count;
 		if detailed {
 			*result[8].high_keys.entry(slice4).or_insert(0.0) += penalty;
@@ -425,21 +518,63 @@ fn penalize<'a, 'b>(
 }
 
 fn is_roll_out(curr: Finger, prev: Finger) -> bool {
-	match curr {
-		Finger::Thumb  => false,
-		Finger::Index  => prev == Finger::Thumb,
-		Finger::Middle => prev == Finger::Thumb || prev == Finger::Index,
-		Finger::Ring   => prev != Finger::Pinky && prev != Finger::Ring,
-		Finger::Pinky  => prev != Finger::Pinky
-	}
+	curr < prev
 }
 
 fn is_roll_in(curr: Finger, prev: Finger) -> bool {
-	match curr {
-		Finger::Thumb  => prev != Finger::Thumb,
-		Finger::Index  => prev != Finger::Thumb && prev != Finger::Index,
-		Finger::Middle => prev == Finger::Pinky || prev == Finger::Ring,
-		Finger::Ring   => prev == Finger::Pinky,
-		Finger::Pinky  => false,
+	curr > prev
+}
+
+// The last `n` characters of `string`, which may be more than `n` bytes.
+fn last_chars(string: &str, n: usize)
+-> &str
+{
+	match string.char_indices().rev().nth(n - 1) {
+		Some((i, _)) => &string[i..],
+		None => string,
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use layout::QWERTY_LAYOUT;
+
+	static FRENCH: &str = "Où est l’élève ? Déjà à l’école, près de la fenêtre.";
+	static FRENCH_PLAIN: &str = "Ou est l'eleve ? Deja a l'ecole, pres de la fenetre.";
+
+	fn score(text: &str)
+	-> f64
+	{
+		let map = QWERTY_LAYOUT.get_position_map();
+		let quartads = prepare_quartad_list(text, &map);
+		calculate_penalty(&quartads, text.len(), &QWERTY_LAYOUT, &init(), false).0
+	}
+
+	#[test]
+	fn accented_characters_are_typed_as_their_plain_forms()
+	{
+		assert_eq!(score(FRENCH), score(FRENCH_PLAIN));
+		let dropped: String = FRENCH.chars().filter(|c| c.is_ascii()).collect();
+		assert!(score(FRENCH) != score(&dropped));
+	}
+
+	#[test]
+	fn every_french_character_is_pressed()
+	{
+		let map = QWERTY_LAYOUT.get_position_map();
+		let QuartadList(quartads) = prepare_quartad_list(FRENCH, &map);
+		assert_eq!(quartads.values().sum::<usize>(), FRENCH.chars().count());
+	}
+
+	#[test]
+	fn quartads_end_on_character_boundaries()
+	{
+		let map = QWERTY_LAYOUT.get_position_map();
+		let QuartadList(quartads) = prepare_quartad_list("àéb", &map);
+		let mut keys: Vec<&str> = quartads.keys().cloned().collect();
+		keys.sort();
+		assert_eq!(keys, vec!["à", "àé", "àéb"]);
 	}
 }
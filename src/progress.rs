@@ -0,0 +1,145 @@
+//! How an annealing run reports on itself as it goes.
+//!
+//! `simulator::simulate` prints nothing of its own: it tells a `ProgressSink`
+//! what happened, and the sink decides what, if anything, to show.
+//! `StdoutProgress` prints what the command line tool does and `NoProgress`
+//! throws everything away.
+
+use annealing::EarlyStop;
+use layout::Layout;
+use penalty;
+use simulator::print_result;
+
+// One stage of a run, as `AnnealingParams::stage_len` divides it up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StageSummary
+{
+	// The iterations the stage covers, inclusive.
+	pub first:       usize,
+	pub last:        usize,
+	// The temperature the stage's last move was judged at.
+	pub temperature: f64,
+	// Moves tried, accepted and accepted though worse.
+	pub tried:       usize,
+	pub accepted:    usize,
+	pub worse:       usize,
+	// The penalty of the accepted layout at the end of the stage.
+	pub penalty:     f64,
+}
+
+// What a run ended with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunSummary
+{
+	// The iterations done, which are fewer than planned if the run stopped
+	// early, and why it did.
+	pub iterations: usize,
+	pub stopped:    Option<EarlyStop>,
+	// The best layouts found and their penalties, best first.
+	pub best:       Vec<(Layout, f64)>,
+}
+
+// Receives the events of a run. Every method does nothing unless overridden.
+pub trait ProgressSink
+{
+	// Before the first iteration of a run that is not being resumed.
+	fn on_start(&mut self, _layout: &Layout, _penalty: f64) {}
+
+	// Whenever a move is accepted.
+	fn on_accept(&mut self, _iteration: usize, _penalty: f64) {}
+
+	// Whenever an accepted layout is the best one so far.
+	fn on_improvement(&mut self, _iteration: usize, _penalty: f64, _layout: &Layout) {}
+
+	// At the end of every stage.
+	fn on_temperature(&mut self, _stage: &StageSummary) {}
+
+	fn on_finish(&mut self, _summary: &RunSummary) {}
+}
+
+// Ignores every event.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+// The command line tool's output: the top layouts when the run finishes, one
+// line per stage with `log_stages`, and with `debug` the starting layout and
+// every accepted move.
+pub struct StdoutProgress<'a, 'b>
+{
+	quartads:       &'b penalty::QuartadList<'a>,
+	len:            usize,
+	penalties:      &'b Vec<penalty::KeyPenalty<'a>>,
+	pub debug:      bool,
+	pub log_stages: bool,
+}
+
+impl StageSummary
+{
+	// The share of the moves tried that were accepted, or 0 if none were.
+	pub fn acceptance_rate(&self)
+	-> f64
+	{
+		self.accepted as f64 / self.tried.max(1) as f64
+	}
+}
+
+impl ProgressSink for NoProgress {}
+
+impl <'a, 'b> StdoutProgress<'a, 'b>
+{
+	// Layouts are printed with their penalties against `quartads`.
+	pub fn new(quartads: &'b penalty::QuartadList<'a>, len: usize, penalties: &'b Vec<penalty::KeyPenalty<'a>>,
+		debug: bool, log_stages: bool)
+	-> StdoutProgress<'a, 'b>
+	{
+		StdoutProgress { quartads, len, penalties, debug, log_stages }
+	}
+}
+
+impl <'a, 'b> ProgressSink for StdoutProgress<'a, 'b>
+{
+	fn on_start(&mut self, layout: &Layout, _penalty: f64)
+	{
+		if self.debug {
+			println!("Initial layout:");
+			let penalty = penalty::calculate_penalty(self.quartads, self.len, layout, self.penalties, true);
+			print_result(layout, &penalty);
+		}
+	}
+
+	fn on_accept(&mut self, iteration: usize, penalty: f64)
+	{
+		if self.debug {
+			println!("Iteration {} accepted with penalty {}", iteration, penalty);
+		}
+	}
+
+	fn on_temperature(&mut self, stage: &StageSummary)
+	{
+		if self.log_stages {
+			println!("Iterations {}-{}: temperature {:.6}, accepted {} of {} ({} worse), penalty {}",
+				stage.first, stage.last, stage.temperature,
+				stage.accepted, stage.tried, stage.worse, stage.penalty);
+		}
+	}
+
+	// Each layout is printed with how many positions it differs from the
+	// best one in.
+	fn on_finish(&mut self, summary: &RunSummary)
+	{
+		if let Some(reason) = summary.stopped {
+			println!();
+			println!("Stopped early after {} iterations: {}", summary.iterations, reason);
+		}
+		let best = match summary.best.first() {
+			Some((best, _)) => best,
+			None => return,
+		};
+		for (rank, (layout, _)) in summary.best.iter().enumerate() {
+			let penalty = penalty::calculate_penalty(self.quartads, self.len, layout, self.penalties, true);
+			println!();
+			println!("#{}, {} positions from the best:", rank + 1, layout.distance(best));
+			print_result(layout, &penalty);
+		}
+	}
+}
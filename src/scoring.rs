@@ -0,0 +1,501 @@
+//! Fast scoring from bigram frequencies.
+//!
+//! The cost of typing one bigram only depends on the two positions involved,
+//! so it can be worked out once per pair of positions. A corpus reduced to
+//! ASCII bigram counts is then scored with one pass over the counts, however
+//! long the text was.
+//!
+//! `LayoutScorer` is what the optimizer minimises. `DefaultScorer` is the
+//! quartad penalty model in penalty.rs, `BigramScorer` uses the bigram costs
+//! here and `WeightedScorer` the metrics on `Layout`.
+
+use corpus::Corpus;
+use corpus::Ngrams;
+use layout::finger_for_pos;
+use layout::hand_for_pos;
+use layout::row_for_pos;
+use layout::Finger;
+use layout::Layout;
+use layout::LayoutPosMap;
+use layout::Row;
+use penalty;
+use penalty::KeyPenalty;
+use penalty::QuartadIndex;
+use penalty::QuartadList;
+
+// Counts of each ASCII bigram, indexed by the two characters' codes.
+pub type BigramFreq = [[u64; 128]; 128];
+
+// What each kind of bigram costs. A negative weight is a bonus.
+#[derive(Clone, Copy, Debug)]
+pub struct BigramWeights
+{
+	pub same_finger: f32,
+	pub row_jump:    f32,
+	pub alternation: f32,
+}
+
+// Lower is better. A scorer holds whatever it scores against. Scores should
+// not grow with the length of the corpus, so that the annealing temperature
+// means the same thing for every corpus.
+pub trait LayoutScorer
+{
+	fn score(&self, layout: &Layout) -> f64;
+
+	// The score of `layout`, which scored `before` until `swaps` were made to
+	// it. `map` is the position map of the layout as it was, and is brought
+	// up to date with the swaps. Scorers that can tell what a swap changes
+	// look at that much of the layout instead of scoring all of it again.
+	fn rescore(&self, layout: &Layout, map: &mut LayoutPosMap, _before: f64, swaps: &[(usize, usize)])
+	-> f64
+	{
+		for &(i, j) in swaps {
+			map.swap_positions(i, j);
+		}
+		self.score(layout)
+	}
+}
+
+// `penalty::calculate_penalty`'s scaled penalty, which is what the command
+// line tool optimises.
+pub struct DefaultScorer<'a, 'b>
+{
+	quartads:  &'b QuartadList<'a>,
+	len:       usize,
+	penalties: &'b Vec<KeyPenalty<'a>>,
+	index:     QuartadIndex<'a>,
+}
+
+// `score_from_freqs_with` per character of the corpus. The bigram counts and
+// the cost table are worked out once, when the scorer is made, so that
+// scoring a layout is one pass over the counts.
+#[derive(Clone, Debug)]
+pub struct BigramScorer<const N: usize = 34>
+{
+	freqs: Box<BigramFreq>,
+	table: [[f32; N]; N],
+	len:   f32,
+}
+
+// A weighted sum of the rates of same-finger bigrams, rolls, row jumps and
+// hand alternations per character, plus how unevenly the fingers share the
+// presses, from 0.125 when the eight fingers are used equally to 1.0 when
+// one finger does everything, plus the share of presses off the home row.
+// Thumbs are left out of the finger load, and count as home row presses if
+// `thumbs_on_home` is set. A negative weight is a bonus.
+#[derive(Clone, Copy, Debug)]
+pub struct WeightedScorer<'c>
+{
+	pub same_finger:    f32,
+	pub rolls:          f32,
+	pub row_jumps:      f32,
+	pub alternation:    f32,
+	pub finger_load:    f32,
+	pub off_home_row:   f32,
+	pub thumbs_on_home: bool,
+	corpus:             &'c Corpus,
+}
+
+impl Default for BigramWeights
+{
+	fn default()
+	-> BigramWeights
+	{
+		BigramWeights {
+			same_finger: 1.0,
+			row_jump: 0.5,
+			alternation: -0.1,
+		}
+	}
+}
+
+impl <'a, 'b> DefaultScorer<'a, 'b>
+{
+	// `len` is the length of the text `quartads` were taken from.
+	pub fn new(quartads: &'b QuartadList<'a>, len: usize, penalties: &'b Vec<KeyPenalty<'a>>)
+	-> DefaultScorer<'a, 'b>
+	{
+		DefaultScorer { quartads, len, penalties, index: QuartadIndex::new(quartads) }
+	}
+}
+
+impl <'a, 'b> LayoutScorer for DefaultScorer<'a, 'b>
+{
+	fn score(&self, layout: &Layout)
+	-> f64
+	{
+		penalty::calculate_penalty(self.quartads, self.len, layout, self.penalties, false).1
+	}
+
+	// Only the quartads with a character on a swapped key are scored, before
+	// and after the swaps.
+	fn rescore(&self, _layout: &Layout, map: &mut LayoutPosMap, before: f64, swaps: &[(usize, usize)])
+	-> f64
+	{
+		let positions: Vec<usize> = swaps.iter().flat_map(|&(i, j)| [i, j]).collect();
+		let old = map.clone();
+		for &(i, j) in swaps {
+			map.swap_positions(i, j);
+		}
+		let change = self.index.penalty_change(&positions, &old, map);
+
+		// Every penalty is a whole number of eighths, so the total `before`
+		// was scaled from comes back exactly, and so does the new total.
+		let len = self.len as f64;
+		let total = (before * len * 8.0).round() / 8.0;
+		(total + change) / len
+	}
+}
+
+impl <const N: usize> BigramScorer<N>
+{
+	// Scores against `corpus` with the default weights.
+	pub fn new(corpus: &Corpus)
+	-> BigramScorer<N>
+	{
+		BigramScorer::with_weights(corpus, &BigramWeights::default())
+	}
+
+	pub fn with_weights(corpus: &Corpus, weights: &BigramWeights)
+	-> BigramScorer<N>
+	{
+		BigramScorer {
+			freqs: bigram_freqs(corpus),
+			table: cost_table(weights),
+			len: corpus.len().max(1) as f32,
+		}
+	}
+}
+
+impl LayoutScorer for BigramScorer
+{
+	fn score(&self, layout: &Layout)
+	-> f64
+	{
+		(layout.score_from_freqs_with(&self.freqs, &self.table) / self.len) as f64
+	}
+
+	// Adds up `swap_delta_with` for each swap in turn.
+	fn rescore(&self, _layout: &Layout, map: &mut LayoutPosMap, before: f64, swaps: &[(usize, usize)])
+	-> f64
+	{
+		let mut positions = [None; 128];
+		for (c, pos) in positions.iter_mut().enumerate() {
+			*pos = map.get_key_position(c as u8 as char).map(|kp| kp.pos);
+		}
+		let mut delta = 0.0;
+		for &(i, j) in swaps {
+			delta += swap_delta_with(&positions, i, j, &self.freqs, &self.table);
+			map.swap_positions(i, j);
+			for pos in positions.iter_mut().flatten() {
+				if *pos == i {
+					*pos = j;
+				} else if *pos == j {
+					*pos = i;
+				}
+			}
+		}
+		before + (delta / self.len) as f64
+	}
+}
+
+impl <'c> WeightedScorer<'c>
+{
+	// The default weights, scoring against `corpus`.
+	pub fn new(corpus: &'c Corpus)
+	-> WeightedScorer<'c>
+	{
+		WeightedScorer {
+			same_finger: 1.0,
+			rolls: -0.1,
+			row_jumps: 0.5,
+			alternation: 0.0,
+			finger_load: 0.1,
+			off_home_row: 0.1,
+			thumbs_on_home: true,
+			corpus,
+		}
+	}
+}
+
+impl <'c> LayoutScorer for WeightedScorer<'c>
+{
+	fn score(&self, layout: &Layout)
+	-> f64
+	{
+		let corpus = self.corpus;
+		let len = corpus.len().max(1) as f32;
+		let (inward, outward) = layout.roll_counts(corpus);
+
+		let usage = layout.finger_usage(corpus);
+		let fingers = usage.iter().enumerate().filter(|&(i, _)| i != 4 && i != 5).map(|(_, &n)| n as f32);
+		let presses: f32 = fingers.clone().sum();
+		let load = if presses == 0.0 {
+			0.0
+		} else {
+			fingers.map(|n| (n / presses) * (n / presses)).sum()
+		};
+
+		let score = self.same_finger * layout.same_finger_bigrams(corpus) as f32 / len
+			+ self.rolls * (inward + outward) as f32 / len
+			+ self.row_jumps * layout.row_jumps(corpus) as f32 / len
+			+ layout.alternation_score(corpus, self.alternation) / len
+			+ self.finger_load * load
+			+ self.off_home_row * (1.0 - layout.home_row_rate(corpus, self.thumbs_on_home)) as f32;
+		score as f64
+	}
+}
+
+impl <const N: usize> Layout<N>
+{
+	// The cost of every pair of positions, with the default weights.
+	pub fn bigram_cost_table(&self)
+	-> [[f32; N]; N]
+	{
+		self.bigram_cost_table_with(&BigramWeights::default())
+	}
+
+	// `table[i][j]` is the cost of pressing position `i` and then position
+	// `j`. Same-finger bigrams on one key are repeats, which cost nothing, as
+	// do pairs of thumb keys, and a row jump is a same-finger bigram between
+	// the top and bottom rows, so it costs both weights.
+	pub fn bigram_cost_table_with(&self, weights: &BigramWeights)
+	-> [[f32; N]; N]
+	{
+		cost_table(weights)
+	}
+
+	// The total cost of `bigram_freq` typed on this layout, with the default
+	// weights. Bigrams with a character that is not on the layout are free.
+	pub fn score_from_freqs(&self, bigram_freq: &BigramFreq)
+	-> f32
+	{
+		self.score_from_freqs_with(bigram_freq, &self.bigram_cost_table())
+	}
+
+	// Same as `score_from_freqs`, but with a table from
+	// `bigram_cost_table_with`, which can be reused across layouts.
+	pub fn score_from_freqs_with(&self, bigram_freq: &BigramFreq, table: &[[f32; N]; N])
+	-> f32
+	{
+		let positions = ascii_positions(self);
+		let mut score = 0.0;
+		for (a, row) in bigram_freq.iter().enumerate() {
+			let i = match positions[a] {
+				Some(i) => i,
+				None => continue,
+			};
+			for (b, &n) in row.iter().enumerate() {
+				if let (Some(j), true) = (positions[b], n > 0) {
+					score += table[i][j] * n as f32;
+				}
+			}
+		}
+		score
+	}
+
+	// How much `score_from_freqs` would change if positions `i` and `j` were
+	// swapped, on both layers, without swapping them.
+	pub fn swap_delta(&self, i: usize, j: usize, bigram_freq: &BigramFreq)
+	-> f32
+	{
+		swap_delta_with(&ascii_positions(self), i, j, bigram_freq, &self.bigram_cost_table())
+	}
+}
+
+// `Layout::swap_delta` for the layout with each ASCII character on
+// `positions`, and a table from `bigram_cost_table_with`. Only the bigrams
+// that involve a character on one of the two keys are looked at, so a caller
+// that keeps `positions` up to date as it swaps keys can weigh each swap
+// without scoring the layout.
+pub fn swap_delta_with<const N: usize>(positions: &[Option<usize>; 128], i: usize, j: usize, bigram_freq: &BigramFreq,
+	table: &[[f32; N]; N])
+-> f32
+{
+	let moves = |pos: Option<usize>| pos == Some(i) || pos == Some(j);
+	let swapped = |pos: usize| if pos == i { j } else if pos == j { i } else { pos };
+
+	let mut delta = 0.0;
+	for a in (0..128).filter(|&a| moves(positions[a])) {
+		let pa = positions[a].unwrap();
+		for (b, &pos) in positions.iter().enumerate() {
+			let pb = match pos {
+				Some(pb) => pb,
+				None => continue,
+			};
+			let change = |p: usize, q: usize| table[swapped(p)][swapped(q)] - table[p][q];
+			delta += bigram_freq[a][b] as f32 * change(pa, pb);
+			// Pairs of two moving characters were counted from `a` already.
+			if !moves(pos) {
+				delta += bigram_freq[b][a] as f32 * change(pb, pa);
+			}
+		}
+	}
+	delta
+}
+
+// The ASCII bigram counts of `text`. Boxed, since the table is 128 KiB.
+pub fn bigram_freqs<T: Ngrams + ?Sized>(text: &T)
+-> Box<BigramFreq>
+{
+	let mut freqs = Box::new([[0; 128]; 128]);
+	text.each_bigram(&mut |a, b, n| {
+		if a.is_ascii() && b.is_ascii() {
+			freqs[a as usize][b as usize] += n;
+		}
+	});
+	freqs
+}
+
+// The costs only depend on the positions, not on what is on them.
+fn cost_table<const N: usize>(weights: &BigramWeights)
+-> [[f32; N]; N]
+{
+	let mut table = [[0.0; N]; N];
+	for (i, row) in table.iter_mut().enumerate() {
+		for (j, cost) in row.iter_mut().enumerate() {
+			*cost = pair_cost(i, j, weights);
+		}
+	}
+	table
+}
+
+fn pair_cost(i: usize, j: usize, weights: &BigramWeights)
+-> f32
+{
+	if hand_for_pos(i) != hand_for_pos(j) {
+		return weights.alternation;
+	}
+	if i == j || finger_for_pos(i) != finger_for_pos(j) || finger_for_pos(i) == Some(Finger::Thumb) {
+		return 0.0;
+	}
+
+	match (row_for_pos(i), row_for_pos(j)) {
+		(Some(Row::Top), Some(Row::Bottom)) | (Some(Row::Bottom), Some(Row::Top)) =>
+			weights.same_finger + weights.row_jump,
+		_ => weights.same_finger,
+	}
+}
+
+// The position of each ASCII character on the layout, as in its position
+// map: a character on several layers is typed on the earliest of them.
+// Transliterations only stand in for non-ASCII characters, so they can be
+// left out.
+fn ascii_positions<const N: usize>(layout: &Layout<N>)
+-> [Option<usize>; 128]
+{
+	let mut positions = [None; 128];
+	let layers: Vec<_> = layout.layers().collect();
+	for layer in layers.into_iter().rev() {
+		for (pos, &c) in layer.keys().iter().enumerate() {
+			if c.is_ascii() {
+				positions[c as usize] = Some(pos);
+			}
+		}
+	}
+	positions
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use layout::{DVORAK_LAYOUT, INIT_LAYOUT, QWERTY_LAYOUT};
+
+	static TEXT: &str = "The quick brown fox jumps over the lazy dog, and then sleeps.";
+
+	// Swaps of every pair of letter keys, some of them several at a time.
+	fn swap_sets()
+	-> Vec<Vec<(usize, usize)>>
+	{
+		let mut sets = Vec::new();
+		for i in 0..30 {
+			for j in (i + 1)..30 {
+				sets.push(vec![(i, j)]);
+			}
+			sets.push(vec![(i, (i + 7) % 30), ((i + 3) % 30, (i + 11) % 30), (i, (i + 13) % 30)]);
+		}
+		sets
+	}
+
+	// Checks `rescore` against a `score` of the swapped layout, for every
+	// set of swaps, made one after the other.
+	fn check_rescore<S: LayoutScorer>(scorer: &S, exact: bool)
+	{
+		let mut layout = QWERTY_LAYOUT.clone();
+		let mut map = layout.get_position_map();
+		let mut current = scorer.score(&layout);
+		for swaps in swap_sets() {
+			for &(i, j) in &swaps {
+				layout.swap_positions(i, j);
+			}
+			let rescored = scorer.rescore(&layout, &mut map, current, &swaps);
+			let scored = scorer.score(&layout);
+			if exact {
+				assert_eq!(rescored, scored, "after {:?}", swaps);
+			} else {
+				assert!((rescored - scored).abs() < 1e-4, "after {:?}: {} != {}", swaps, rescored, scored);
+			}
+			current = scored;
+		}
+	}
+
+	#[test]
+	fn swapped_position_maps_match_rebuilt_ones()
+	{
+		let mut layout = DVORAK_LAYOUT.clone();
+		let mut map = layout.get_position_map();
+		for swaps in swap_sets() {
+			for &(i, j) in &swaps {
+				layout.swap_positions(i, j);
+				map.swap_positions(i, j);
+			}
+			let rebuilt = layout.get_position_map();
+			for c in "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ,.;'/<>:\"?éàç".chars() {
+				let (a, b) = (map.get_key_position(c), rebuilt.get_key_position(c));
+				assert_eq!(format!("{:?}", a), format!("{:?}", b), "{} after {:?}", c, swaps);
+			}
+		}
+	}
+
+	#[test]
+	fn default_rescore_is_exact()
+	{
+		let penalties = penalty::init();
+		let map = INIT_LAYOUT.get_position_map();
+		let quartads = penalty::prepare_quartad_list(TEXT, &map);
+		check_rescore(&DefaultScorer::new(&quartads, TEXT.len(), &penalties), true);
+	}
+
+	#[test]
+	fn bigram_rescore_matches_score()
+	{
+		let corpus = Corpus::from_text(TEXT);
+		check_rescore(&BigramScorer::new(&corpus), false);
+	}
+
+	#[test]
+	fn swap_delta_is_the_change_in_score()
+	{
+		let freqs = bigram_freqs(TEXT);
+		let before = QWERTY_LAYOUT.score_from_freqs(&freqs);
+		for &(i, j) in &[(0, 1), (2, 13), (11, 29), (5, 20)] {
+			let mut swapped = QWERTY_LAYOUT.clone();
+			swapped.swap_positions(i, j);
+			let change = swapped.score_from_freqs(&freqs) - before;
+			assert!((QWERTY_LAYOUT.swap_delta(i, j, &freqs) - change).abs() < 1e-3);
+		}
+	}
+
+	#[test]
+	fn bigram_scorer_matches_score_from_freqs()
+	{
+		let corpus = Corpus::from_text(TEXT);
+		let scorer = BigramScorer::new(&corpus);
+		let freqs = bigram_freqs(&corpus);
+		for layout in &[&QWERTY_LAYOUT, &DVORAK_LAYOUT] {
+			let expected = layout.score_from_freqs(&freqs) / corpus.len() as f32;
+			assert_eq!(scorer.score(layout), expected as f64);
+		}
+	}
+}
@@ -1,13 +1,32 @@
-/// Applies the math in annealing.rs to keyboard layouts.
+//! Applies the math in annealing.rs to keyboard layouts.
 extern crate rand;
+extern crate rand_chacha;
+extern crate rayon;
 
-use self::rand::random;
+use self::rand::rngs::StdRng;
+use self::rand::{random, Rng, SeedableRng};
+use self::rand_chacha::ChaCha12Rng;
+use self::rayon::prelude::*;
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::collections::LinkedList;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::Instant;
 
 use annealing;
+use checkpoint::{Checkpoint, CheckpointError, CheckpointPolicy};
+use corpus::Corpus;
 use layout;
 use penalty;
+use progress::{NoProgress, ProgressSink, RunSummary, StageSummary, StdoutProgress};
+use scoring::{DefaultScorer, LayoutScorer};
+
+// The most swaps one move makes, unless the caller says otherwise.
+pub const DEFAULT_NUM_SWAPS: usize = 3;
 
 struct BestLayoutsEntry {
     layout: layout::Layout,
@@ -23,79 +42,618 @@ impl BestLayoutsEntry {
     }
 }
 
-pub fn simulate<'a>(
-    quartads: &penalty::QuartadList<'a>,
-    len: usize,
+// The `capacity` lowest-penalty layouts offered so far, best first. A layout
+// is only kept once, however many times it is reached.
+pub struct BestLayouts {
+    capacity: usize,
+    entries: LinkedList<BestLayoutsEntry>,
+    seen: HashSet<layout::Layout>,
+}
+
+impl BestLayouts {
+    pub fn new(capacity: usize) -> BestLayouts {
+        BestLayouts {
+            capacity,
+            entries: LinkedList::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    // Keeps `layout` if it is not kept already and beats the worst layout
+    // kept, or there is room. Returns whether it was kept.
+    pub fn offer(&mut self, layout: &layout::Layout, penalty: f64) -> bool {
+        if self.capacity == 0 || self.seen.contains(layout) {
+            return false;
+        }
+        if self.entries.len() >= self.capacity {
+            match self.entries.back() {
+                Some(worst) if penalty < worst.penalty => (),
+                _ => return false,
+            }
+        }
+
+        self.seen.insert(layout.clone());
+        let entry = BestLayoutsEntry {
+            layout: layout.clone(),
+            penalty,
+        };
+        self.entries = list_insert_ordered(std::mem::take(&mut self.entries), entry);
+        while self.entries.len() > self.capacity {
+            if let Some(worst) = self.entries.pop_back() {
+                self.seen.remove(&worst.layout);
+            }
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The layouts kept and their penalties, best first.
+    pub fn iter(&self) -> impl Iterator<Item = (&layout::Layout, f64)> {
+        self.entries.iter().map(|e| (&e.layout, e.penalty))
+    }
+
+    pub fn best(&self) -> Option<(&layout::Layout, f64)> {
+        self.iter().next()
+    }
+
+    // The layouts kept and their penalties, best first.
+    pub fn to_vec(&self) -> Vec<(layout::Layout, f64)> {
+        self.iter().map(|(l, p)| (l.clone(), p)).collect()
+    }
+}
+
+// Which `SwapScope` `simulate` uses over the course of a run: stages in order,
+// each with its share of the iterations. Shares are relative, so "any:80,
+// same-hand:20" and "any:4,same-hand:1" are the same schedule.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapSchedule(Vec<(layout::SwapScope, f64)>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduleError(pub String);
+
+impl SwapSchedule {
+    // `None` if there are no stages or a share is negative or not a number,
+    // or the shares add up to zero.
+    pub fn new(stages: Vec<(layout::SwapScope, f64)>) -> Option<SwapSchedule> {
+        let total: f64 = stages.iter().map(|s| s.1).sum();
+        if stages.iter().any(|s| s.1.is_nan() || s.1 < 0.0) || total <= 0.0 {
+            return None;
+        }
+        Some(SwapSchedule(stages))
+    }
+
+    // The scope for iteration `i` of `n`, counting from 1.
+    pub fn scope_at(&self, i: usize, n: usize) -> layout::SwapScope {
+        let total: f64 = self.0.iter().map(|s| s.1).sum();
+        let at = (i.saturating_sub(1)) as f64 / n.max(1) as f64 * total;
+        let mut end = 0.0;
+        for &(scope, share) in &self.0 {
+            end += share;
+            if at < end {
+                return scope;
+            }
+        }
+        self.0.last().unwrap().0
+    }
+}
+
+impl Default for SwapSchedule {
+    fn default() -> SwapSchedule {
+        SwapSchedule(vec![(layout::SwapScope::Any, 1.0)])
+    }
+}
+
+// Comma-separated `scope:share` stages, such as "any:80,same-hand:20". A
+// stage without a share gets 1, so "same-row" on its own is a whole run.
+impl FromStr for SwapSchedule {
+    type Err = ScheduleError;
+
+    fn from_str(s: &str) -> Result<SwapSchedule, ScheduleError> {
+        let err = || ScheduleError(s.to_string());
+        let mut stages = Vec::new();
+        for stage in s.split(',') {
+            let mut parts = stage.trim().splitn(2, ':');
+            let scope = parts.next().unwrap().parse().map_err(|_| err())?;
+            let share = match parts.next() {
+                Some(share) => share.trim().parse().map_err(|_| err())?,
+                None => 1.0,
+            };
+            stages.push((scope, share));
+        }
+        SwapSchedule::new(stages).ok_or_else(err)
+    }
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` is not a swap schedule", self.0)
+    }
+}
+
+impl Error for ScheduleError {}
+
+#[allow(clippy::too_many_arguments)]
+pub fn simulate<S: LayoutScorer>(
+    scorer: &S,
     init_layout: &layout::Layout,
-    penalties: &Vec<penalty::KeyPenalty<'a>>,
-    debug: bool,
+    mask: &layout::LayoutShuffleMask,
     top_layouts: usize,
     num_swaps: usize,
-) {
-    let penalty = penalty::calculate_penalty(&quartads, len, init_layout, penalties, true);
+    schedule: &SwapSchedule,
+    params: &annealing::AnnealingParams,
+    swap_weights: Option<&Corpus>,
+    sink: &mut dyn ProgressSink,
+    rng: &mut impl Rng,
+) -> layout::Layout {
+    let penalty = scorer.score(init_layout);
+    sink.on_start(init_layout, penalty);
 
-    if debug {
-        println!("Initial layout:");
-        print_result(init_layout, &penalty);
+    let mut progress = Progress::start(init_layout, penalty, top_layouts, params);
+    let done: Result<(), Infallible> = run_chain(
+        &mut progress,
+        scorer,
+        mask,
+        num_swaps,
+        schedule,
+        params,
+        swap_weights,
+        sink,
+        rng,
+        |_, _, _| Ok(()),
+    );
+    if let Err(never) = done {
+        match never {}
     }
 
-    // Keep track of the best layouts we've encountered.
-    let mut best_layouts: LinkedList<BestLayoutsEntry> = LinkedList::new();
+    progress.finish(sink);
+    progress.into_best(init_layout)
+}
+
+// `simulate`, saving its progress as `policy` says. With `resume`, the run
+// carries on from that checkpoint instead of starting from `init_layout`, and
+// `rng` is put back where it was when the checkpoint was saved, so that the
+// run makes the same moves the run with the same arguments would have made
+// had it not stopped. A checkpoint made with another corpus is refused.
+#[allow(clippy::too_many_arguments)]
+pub fn simulate_checkpointed<S: LayoutScorer>(
+    scorer: &S,
+    init_layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    top_layouts: usize,
+    num_swaps: usize,
+    schedule: &SwapSchedule,
+    params: &annealing::AnnealingParams,
+    swap_weights: Option<&Corpus>,
+    sink: &mut dyn ProgressSink,
+    rng: &mut ChaCha12Rng,
+    resume: Option<Checkpoint>,
+    policy: &CheckpointPolicy,
+) -> Result<layout::Layout, CheckpointError> {
+    let mut progress = match resume {
+        Some(checkpoint) => {
+            if checkpoint.corpus_hash != policy.corpus_hash {
+                return Err(CheckpointError::CorpusMismatch);
+            }
+            *rng = checkpoint.rng.clone();
+            Progress::resume(checkpoint, top_layouts, params)
+        }
+        None => {
+            let penalty = scorer.score(init_layout);
+            sink.on_start(init_layout, penalty);
+            Progress::start(init_layout, penalty, top_layouts, params)
+        }
+    };
 
-    let mut accepted_layout = init_layout.clone();
-    let mut accepted_penalty = penalty.1;
-    for i in annealing::get_simulation_range() {
-        // Copy and shuffle this iteration of the layout.
-        let mut curr_layout = accepted_layout.clone();
-        curr_layout.shuffle(random::<usize>() % num_swaps + 1);
+    let mut improvements = 0;
+    let mut saved_at = Instant::now();
+    run_chain(
+        &mut progress,
+        scorer,
+        mask,
+        num_swaps,
+        schedule,
+        params,
+        swap_weights,
+        sink,
+        rng,
+        |progress, rng, improved| {
+            if improved {
+                improvements += 1;
+            }
+            let due = (policy.every > 0 && improvements >= policy.every)
+                || policy.interval.is_some_and(|t| saved_at.elapsed() >= t)
+                || progress.iteration == params.iterations;
+            if due {
+                progress
+                    .checkpoint(policy.corpus_hash, rng)
+                    .save(&policy.path)?;
+                improvements = 0;
+                saved_at = Instant::now();
+            }
+            Ok(())
+        },
+    )?;
+    // A run that stopped early has not saved its last iteration yet.
+    if progress.stopped.is_some() {
+        progress
+            .checkpoint(policy.corpus_hash, rng)
+            .save(&policy.path)?;
+    }
 
-        // Calculate penalty.
-        let curr_layout_copy = curr_layout.clone();
-        let penalty = penalty::calculate_penalty(&quartads, len, &curr_layout, penalties, false);
-        let scaled_penalty = penalty.1;
+    progress.finish(sink);
+    Ok(progress.into_best(init_layout))
+}
 
-        // Probabilistically accept worse transitions; always accept better
-        // transitions.
-        if annealing::accept_transition(scaled_penalty - accepted_penalty, i) {
-            if debug {
-                println!("Iteration {} accepted with penalty {}", i, scaled_penalty);
+// Where an annealing run has got to: everything that carries over from one
+// iteration to the next, apart from the random number generator.
+struct Progress {
+    // The number of iterations done.
+    iteration: usize,
+    // The accepted layout, its position map and its penalty. Candidates are
+    // made by shuffling the layout and its map in place, and rejected ones
+    // are undone, so that only accepted layouts are copied.
+    layout: layout::Layout,
+    map: layout::LayoutPosMap,
+    penalty: f64,
+    best: BestLayouts,
+    // The lowest penalty so far, the starting layout's included, and how many
+    // moves ago it was found, for `AnnealingParams::patience`.
+    best_penalty: f64,
+    since_best: usize,
+    stopped: Option<annealing::EarlyStop>,
+    tracker: annealing::AcceptanceTracker,
+    tabu: annealing::TabuList,
+    // Moves tried, accepted and accepted though worse in this stage.
+    stage: (usize, usize, usize),
+}
+
+impl Progress {
+    fn start(
+        init_layout: &layout::Layout,
+        penalty: f64,
+        top_layouts: usize,
+        params: &annealing::AnnealingParams,
+    ) -> Progress {
+        Progress {
+            iteration: 0,
+            layout: init_layout.clone(),
+            map: init_layout.get_position_map(),
+            penalty,
+            best: BestLayouts::new(top_layouts),
+            best_penalty: penalty,
+            since_best: 0,
+            stopped: None,
+            tracker: annealing::AcceptanceTracker::new(params),
+            tabu: annealing::TabuList::for_params(params),
+            stage: (0, 0, 0),
+        }
+    }
+
+    fn resume(
+        checkpoint: Checkpoint,
+        top_layouts: usize,
+        params: &annealing::AnnealingParams,
+    ) -> Progress {
+        let mut best = BestLayouts::new(top_layouts);
+        for (layout, penalty) in &checkpoint.best {
+            best.offer(layout, *penalty);
+        }
+        Progress {
+            iteration: checkpoint.iteration,
+            map: checkpoint.layout.get_position_map(),
+            layout: checkpoint.layout,
+            penalty: checkpoint.penalty,
+            best,
+            best_penalty: checkpoint.best_penalty,
+            since_best: checkpoint.since_best,
+            stopped: None,
+            tracker: annealing::AcceptanceTracker::resume(
+                params,
+                &checkpoint.acceptance,
+                checkpoint.temperature,
+            ),
+            tabu: annealing::TabuList::resume(params, &checkpoint.tabu),
+            stage: checkpoint.stage,
+        }
+    }
+
+    fn checkpoint(&self, corpus_hash: u64, rng: &ChaCha12Rng) -> Checkpoint {
+        Checkpoint {
+            corpus_hash,
+            iteration: self.iteration,
+            layout: self.layout.clone(),
+            penalty: self.penalty,
+            best: self.best.to_vec(),
+            best_penalty: self.best_penalty,
+            since_best: self.since_best,
+            temperature: self.tracker.temperature(),
+            acceptance: self.tracker.recent().collect(),
+            tabu: self.tabu.entries().collect(),
+            stage: self.stage,
+            rng: rng.clone(),
+        }
+    }
+
+    // The stage so far, as it stands after iteration `last`.
+    fn stage_summary(&self, last: usize) -> StageSummary {
+        StageSummary {
+            first: last + 1 - self.stage.0,
+            last,
+            temperature: self.tracker.temperature(),
+            tried: self.stage.0,
+            accepted: self.stage.1,
+            worse: self.stage.2,
+            penalty: self.penalty,
+        }
+    }
+
+    fn finish(&self, sink: &mut dyn ProgressSink) {
+        sink.on_finish(&RunSummary {
+            iterations: self.iteration,
+            stopped: self.stopped,
+            best: self.best.to_vec(),
+        });
+    }
+
+    // The best layout found, or `init_layout` if none was accepted.
+    fn into_best(self, init_layout: &layout::Layout) -> layout::Layout {
+        match self.best.best() {
+            Some((best, _)) => best.clone(),
+            None => init_layout.clone(),
+        }
+    }
+}
+
+// Runs the iterations of `params` that `progress` has not done yet.
+// `after` is called after every iteration with the progress, the generator
+// and whether the iteration found a new best layout, and an error from it
+// stops the run.
+#[allow(clippy::too_many_arguments)]
+fn run_chain<S: LayoutScorer, R: Rng, E>(
+    progress: &mut Progress,
+    scorer: &S,
+    mask: &layout::LayoutShuffleMask,
+    num_swaps: usize,
+    schedule: &SwapSchedule,
+    params: &annealing::AnnealingParams,
+    swap_weights: Option<&Corpus>,
+    sink: &mut dyn ProgressSink,
+    rng: &mut R,
+    mut after: impl FnMut(&Progress, &R, bool) -> Result<(), E>,
+) -> Result<(), E> {
+    // With `swap_weights`, common characters are swapped more often. The
+    // weights follow the accepted layout and are only worked out again once
+    // it changes.
+    let mut weights = None;
+    for i in (progress.iteration + 1)..params.simulation_range().end {
+        progress.stopped = params.early_stop(progress.since_best, progress.tracker.temperature());
+        if progress.stopped.is_some() {
+            // Report the stage cut short.
+            if progress.stage.0 > 0 {
+                sink.on_temperature(&progress.stage_summary(i - 1));
+                progress.stage = (0, 0, 0);
+            }
+            break;
+        }
+
+        let scope = schedule.scope_at(i, params.iterations);
+        let times = rng.gen_range(1..=num_swaps);
+        // A 3-cycle stands in for the whole shuffle, whatever the scope.
+        let cycle = if params.cycle_rate > 0.0 && rng.gen::<f64>() < params.cycle_rate {
+            layout::Layout::propose_cycle(mask, rng)
+        } else {
+            None
+        };
+        let swaps = match (cycle, swap_weights) {
+            (Some(mv), _) => {
+                progress.layout.apply(mv);
+                mv.swaps()
             }
+            (None, Some(corpus)) => {
+                let w = weights.get_or_insert_with(|| progress.layout.position_weights(corpus));
+                progress.layout.shuffle_weighted(times, mask, scope, w, rng)
+            }
+            (None, None) => progress.layout.shuffle_scoped(times, mask, scope, rng),
+        };
 
-            accepted_layout = curr_layout_copy.clone();
-            accepted_penalty = scaled_penalty;
+        // A move the tabu list forbids is undone without being scored.
+        let mut improved = false;
+        progress.stage.0 += 1;
+        if progress.tabu.forbids(&swaps, i) {
+            progress.layout.undo_swaps(&swaps);
+        } else {
+            let scaled_penalty = scorer.rescore(
+                &progress.layout,
+                &mut progress.map,
+                progress.penalty,
+                &swaps,
+            );
 
-            // Insert this layout into best layouts.
-            let new_entry = BestLayoutsEntry {
-                layout: curr_layout_copy,
-                penalty: penalty.1,
-            };
-            best_layouts = list_insert_ordered(best_layouts, new_entry);
+            // Probabilistically accept worse transitions; always accept better
+            // transitions.
+            let de = scaled_penalty - progress.penalty;
+            if progress.tracker.accept(de, i, rng) {
+                progress.stage.1 += 1;
+                if de > 0.0 {
+                    progress.stage.2 += 1;
+                }
+                sink.on_accept(i, scaled_penalty);
 
-            // Limit best layouts list length.
-            while best_layouts.len() > top_layouts {
-                best_layouts.pop_back();
+                progress.penalty = scaled_penalty;
+                weights = None;
+                progress.tabu.record(&swaps, i);
+
+                improved = scaled_penalty < progress.best_penalty;
+                progress.best.offer(&progress.layout, scaled_penalty);
+                if improved {
+                    sink.on_improvement(i, scaled_penalty, &progress.layout);
+                }
+            } else {
+                progress.layout.undo_swaps(&swaps);
+                progress.map.undo_swaps(&swaps);
             }
         }
+
+        if improved {
+            progress.best_penalty = progress.penalty;
+            progress.since_best = 0;
+        } else {
+            progress.since_best += 1;
+        }
+
+        if i % params.stage_len() == 0 || i == params.iterations {
+            sink.on_temperature(&progress.stage_summary(i));
+            progress.stage = (0, 0, 0);
+        }
+
+        progress.iteration = i;
+        after(progress, rng, improved)?;
     }
+    Ok(())
+}
+
+// The chain `simulate` runs, keeping only the best layout and printing
+// nothing, for running chains side by side. `on_best` is called with each
+// new best layout and its penalty as the chain finds them. With the same
+// arguments and `rng`, the result is the layout `simulate` returns with
+// `top_layouts` at 1.
+#[allow(clippy::too_many_arguments)]
+pub fn anneal_penalty<S: LayoutScorer>(
+    scorer: &S,
+    init_layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    num_swaps: usize,
+    schedule: &SwapSchedule,
+    params: &annealing::AnnealingParams,
+    swap_weights: Option<&Corpus>,
+    rng: &mut impl Rng,
+    on_best: impl FnMut(&layout::Layout, f64),
+) -> (layout::Layout, f64) {
+    anneal_penalty_until(
+        scorer,
+        init_layout,
+        mask,
+        num_swaps,
+        schedule,
+        params,
+        swap_weights,
+        &AtomicBool::new(false),
+        rng,
+        on_best,
+    )
+}
+
+// Same as `anneal_penalty`, but the chain ends early, with the best layout
+// found so far, once `stop` is set; for example from a Ctrl-C handler.
+#[allow(clippy::too_many_arguments)]
+pub fn anneal_penalty_until<S: LayoutScorer>(
+    scorer: &S,
+    init_layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    num_swaps: usize,
+    schedule: &SwapSchedule,
+    params: &annealing::AnnealingParams,
+    swap_weights: Option<&Corpus>,
+    stop: &AtomicBool,
+    rng: &mut impl Rng,
+    on_best: impl FnMut(&layout::Layout, f64),
+) -> (layout::Layout, f64) {
+    let penalty = scorer.score(init_layout);
+    let mut progress = Progress::start(init_layout, penalty, 1, params);
+    // Stopping early is not an error: the result is the best layout so far.
+    let _: Result<(), ()> = run_chain(
+        &mut progress,
+        scorer,
+        mask,
+        num_swaps,
+        schedule,
+        params,
+        swap_weights,
+        &mut BestSink(on_best),
+        rng,
+        |_, _, _| {
+            if stop.load(AtomicOrdering::Relaxed) {
+                Err(())
+            } else {
+                Ok(())
+            }
+        },
+    );
 
-    for entry in best_layouts.into_iter() {
-        let layout = entry.layout;
-        let penalty = penalty::calculate_penalty(&quartads, len, &layout, penalties, true);
-        println!("");
-        print_result(&layout, &penalty);
+    match progress.best.best() {
+        Some((best, penalty)) => (best.clone(), penalty),
+        None => (init_layout.clone(), penalty),
     }
 }
 
+// Hands each new best layout to a closure, and ignores everything else.
+struct BestSink<F>(F);
+
+impl<F: FnMut(&layout::Layout, f64)> ProgressSink for BestSink<F> {
+    fn on_improvement(&mut self, _iteration: usize, penalty: f64, layout: &layout::Layout) {
+        (self.0)(layout, penalty);
+    }
+}
+
+// Greedy hill climbing from `init_layout`: tries every swap of two positions
+// `mask` leaves free, makes the one that lowers the penalty most, and repeats
+// until no swap does. Each candidate is scored with `LayoutScorer::rescore`,
+// from the penalty of the layout so far. Returns the polished layout, its
+// penalty and the number of swaps made.
+pub fn polish<S: LayoutScorer>(
+    scorer: &S,
+    init_layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+) -> (layout::Layout, f64, usize) {
+    let swappable = mask.swappable_positions();
+    let mut layout = init_layout.clone();
+    let mut map = layout.get_position_map();
+    let mut current = scorer.score(&layout);
+    let mut swaps = 0;
+    loop {
+        let mut best: Option<(usize, usize, f64)> = None;
+        for (n, &i) in swappable.iter().enumerate() {
+            for &j in &swappable[n + 1..] {
+                layout.swap_positions(i, j);
+                let penalty = scorer.rescore(&layout, &mut map, current, &[(i, j)]);
+                layout.swap_positions(i, j);
+                map.swap_positions(i, j);
+                if penalty < best.map_or(current, |b| b.2) {
+                    best = Some((i, j, penalty));
+                }
+            }
+        }
+        match best {
+            Some((i, j, penalty)) => {
+                layout.swap_positions(i, j);
+                map.swap_positions(i, j);
+                current = penalty;
+                swaps += 1;
+            }
+            None => return (layout, current, swaps),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn refine<'a>(
     quartads: &penalty::QuartadList<'a>,
     len: usize,
     init_layout: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
     penalties: &Vec<penalty::KeyPenalty<'a>>,
     debug: bool,
     top_layouts: usize,
     num_swaps: usize,
-) {
-    let penalty = penalty::calculate_penalty(&quartads, len, init_layout, penalties, true);
+) -> layout::Layout {
+    let penalty = penalty::calculate_penalty(quartads, len, init_layout, penalties, true);
 
     println!("Initial layout:");
     print_result(init_layout, &penalty);
@@ -105,56 +663,172 @@ pub fn refine<'a>(
 
     loop {
         // Test every layout within `num_swaps` swaps of the initial layout.
-        let mut best_layouts: LinkedList<BestLayoutsEntry> = LinkedList::new();
-        let permutations = layout::LayoutPermutations::new(init_layout, num_swaps);
+        let mut best_layouts = BestLayouts::new(top_layouts);
+        let permutations = layout::LayoutPermutations::new(init_layout, mask, num_swaps);
+        let mut tried = 0;
         for (i, layout) in permutations.enumerate() {
-            let penalty = penalty::calculate_penalty(&quartads, len, &layout, penalties, false);
+            tried += 1;
+            let penalty = penalty::calculate_penalty(quartads, len, &layout, penalties, false);
 
             if debug {
                 println!("Iteration {}: {}", i, penalty.1);
             }
 
-            // Insert this layout into best layouts.
-            let new_entry = BestLayoutsEntry {
-                layout: layout,
-                penalty: penalty.1,
-            };
-            best_layouts = list_insert_ordered(best_layouts, new_entry);
-
-            // Limit best layouts list length.
-            while best_layouts.len() > top_layouts {
-                best_layouts.pop_back();
-            }
+            best_layouts.offer(&layout, penalty.1);
         }
 
         // Print the top layouts.
-        for entry in best_layouts.iter() {
-            let ref layout = entry.layout;
-            let penalty = penalty::calculate_penalty(&quartads, len, &layout, penalties, true);
-            println!("");
-            print_result(&layout, &penalty);
-        }
+        StdoutProgress::new(quartads, len, penalties, false, false).on_finish(&RunSummary {
+            iterations: tried,
+            stopped: None,
+            best: best_layouts.to_vec(),
+        });
 
         // Keep going until swapping doesn't get us any more improvements.
-        let best = best_layouts.pop_front().unwrap();
-        if curr_penalty <= best.penalty {
+        let (best, best_penalty) = best_layouts.best().unwrap();
+        if curr_penalty <= best_penalty {
             break;
         } else {
-            curr_layout = best.layout;
-            curr_penalty = best.penalty;
+            curr_layout = best.clone();
+            curr_penalty = best_penalty;
         }
     }
 
-    println!("");
+    println!();
     println!("Ultimate winner:");
-    println!("{}", curr_layout);
+    println!("{}", curr_layout.to_full_string());
+
+    curr_layout
+}
+
+// Runs `runs` independent annealing chains from `init` across rayon's thread
+// pool, scoring layouts against `text` with `DefaultScorer`, as `run` does,
+// and returns the best layout found along with its score. Empty keys stay
+// where they are.
+pub fn optimize_parallel(init: &layout::Layout, text: &str, runs: usize) -> (layout::Layout, f64) {
+    let mut mask = layout::LAYOUT_MASK.clone();
+    for (pos, &c) in init.lower().keys().iter().enumerate() {
+        if c == '\0' {
+            mask.pin(pos);
+        }
+    }
+    let params = annealing::AnnealingParams::default();
+    let penalties = penalty::init();
+    let init_pos_map = layout::INIT_LAYOUT.get_position_map();
+    let quartads = penalty::prepare_quartad_list(text, &init_pos_map);
+    let scorer = DefaultScorer::new(&quartads, text.len(), &penalties);
+    optimize_parallel_with(init, &mask, runs, DEFAULT_NUM_SWAPS, &params, &scorer)
+}
+
+// Same as `optimize_parallel`, but only swaps positions `mask` allows, makes
+// up to `num_swaps` swaps a move, cools as `params` says and scores with
+// `scorer`. Each chain is the one `anneal_penalty` runs, with its own RNG
+// seeded from one random base seed plus the chain's index, and shares nothing
+// mutable with the others.
+pub fn optimize_parallel_with<S: LayoutScorer + Sync>(
+    init: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    runs: usize,
+    num_swaps: usize,
+    params: &annealing::AnnealingParams,
+    scorer: &S,
+) -> (layout::Layout, f64) {
+    let seed: u64 = random();
+    let schedule = SwapSchedule::default();
+
+    (0..runs.max(1) as u64)
+        .into_par_iter()
+        .map(|run| {
+            let mut rng = StdRng::seed_from_u64(seed.wrapping_add(run));
+            anneal_penalty(
+                scorer,
+                init,
+                mask,
+                num_swaps,
+                &schedule,
+                params,
+                None,
+                &mut rng,
+                |_, _| (),
+            )
+        })
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+        .unwrap()
+}
+
+// A single annealing chain of `iterations` moves from `init`, scored against
+// `text` with `DefaultScorer` and seeded with `seed`. Returns the best layout
+// the chain passed through, along with the score of the accepted layout after
+// each iteration. The same seed and text always give the same trace.
+pub fn optimize_traced(
+    init: &layout::Layout,
+    text: &str,
+    iterations: usize,
+    seed: u64,
+) -> (layout::Layout, Vec<f64>) {
+    let mut mask = layout::LAYOUT_MASK.clone();
+    for (pos, &c) in init.lower().keys().iter().enumerate() {
+        if c == '\0' {
+            mask.pin(pos);
+        }
+    }
+    let params = annealing::AnnealingParams {
+        iterations,
+        ..annealing::AnnealingParams::default()
+    };
+    let penalties = penalty::init();
+    let init_pos_map = layout::INIT_LAYOUT.get_position_map();
+    let quartads = penalty::prepare_quartad_list(text, &init_pos_map);
+    let scorer = DefaultScorer::new(&quartads, text.len(), &penalties);
+    optimize_traced_with(init, &mask, DEFAULT_NUM_SWAPS, &params, seed, &scorer)
+}
+
+// Same as `optimize_traced`, but only swaps positions `mask` allows, makes up
+// to `num_swaps` swaps a move, cools as `params` says and scores with
+// `scorer`. The chain is the one `anneal_penalty` runs; a chain that stops
+// early ends the trace there.
+pub fn optimize_traced_with<S: LayoutScorer>(
+    init: &layout::Layout,
+    mask: &layout::LayoutShuffleMask,
+    num_swaps: usize,
+    params: &annealing::AnnealingParams,
+    seed: u64,
+    scorer: &S,
+) -> (layout::Layout, Vec<f64>) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let penalty = scorer.score(init);
+    let mut progress = Progress::start(init, penalty, 1, params);
+    let mut trace = Vec::with_capacity(params.iterations);
+    let done: Result<(), Infallible> = run_chain(
+        &mut progress,
+        scorer,
+        mask,
+        num_swaps,
+        &SwapSchedule::default(),
+        params,
+        None,
+        &mut NoProgress,
+        &mut rng,
+        |progress, _, _| {
+            trace.push(progress.penalty);
+            Ok(())
+        },
+    );
+    if let Err(never) = done {
+        match never {}
+    }
+
+    match progress.best.best() {
+        Some((best, _)) => (best.clone(), trace),
+        None => (init.clone(), trace),
+    }
 }
 
 pub fn print_result<'a>(
     layout: &'a layout::Layout,
     penalty: &'a (f64, f64, Vec<penalty::KeyPenaltyResult<'a>>),
 ) {
-    println!("{}", layout);
+    println!("{:#}", layout);
 
     let (ref total, ref scaled, ref penalties) = *penalty;
     println!("total: {}; scaled: {}", total, scaled);
@@ -162,15 +836,16 @@ pub fn print_result<'a>(
         print!("{}  / ", penalty);
         let mut high_keys: Vec<(&str, f64)> =
             penalty.high_keys.iter().map(|x| (*x.0, *x.1)).collect();
+        // Ties go in key order, so that seeded runs print the same thing.
         high_keys.sort_by(|a, b| match b.1.abs().partial_cmp(&a.1.abs()) {
-            Some(c) => c,
+            Some(c) => c.then_with(|| a.0.cmp(b.0)),
             None => Ordering::Equal,
         });
         for key in high_keys.iter().take(5) {
             let (k, v) = *key;
             print!(" {}: {};", k, v);
         }
-        println!("");
+        println!();
     }
 }
 
@@ -180,8 +855,10 @@ fn list_insert_ordered(
     entry: BestLayoutsEntry,
 ) -> LinkedList<BestLayoutsEntry> {
     {
-        // Find where to add our new entry to, since the list is sorted.
+        // Find where to add our new entry to, since the list is sorted. The
+        // search starts before the front, so that the entry can go first.
         let mut cursor = list.cursor_front_mut();
+        cursor.move_prev();
         loop {
             {
                 let opt_next = cursor.peek_next();
@@ -203,3 +880,663 @@ fn list_insert_ordered(
     }
     list
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use layout::{COLEMAK_LAYOUT, QWERTY_LAYOUT};
+    use progress::NoProgress;
+
+    static TEXT: &str = "The quick brown fox jumps over the lazy dog, and then sleeps.";
+
+    // Scores a layout by how many keys it has away from `COLEMAK_LAYOUT`, so
+    // that COLEMAK is the only layout polish can end on.
+    struct DistanceFromColemak;
+
+    impl LayoutScorer for DistanceFromColemak {
+        fn score(&self, layout: &layout::Layout) -> f64 {
+            layout.positional_difference(&COLEMAK_LAYOUT) as f64
+        }
+    }
+
+    fn empty_keys_pinned(layout: &layout::Layout) -> layout::LayoutShuffleMask {
+        let mut mask = layout::LAYOUT_MASK.clone();
+        for (pos, &c) in layout.lower().keys().iter().enumerate() {
+            if c == '\0' {
+                mask.pin(pos);
+            }
+        }
+        mask
+    }
+
+    fn chain(scorer: &DefaultScorer, seed: u64) -> (layout::Layout, f64) {
+        let params = annealing::AnnealingParams {
+            iterations: 2000,
+            ..annealing::AnnealingParams::default()
+        };
+        let mask = empty_keys_pinned(&QWERTY_LAYOUT);
+        let mut rng = StdRng::seed_from_u64(seed);
+        anneal_penalty(
+            scorer,
+            &QWERTY_LAYOUT,
+            &mask,
+            3,
+            &SwapSchedule::default(),
+            &params,
+            None,
+            &mut rng,
+            |_, _| (),
+        )
+    }
+
+    #[test]
+    fn chains_on_two_threads_match_chains_run_in_turn() {
+        let penalties = penalty::init();
+        let map = layout::INIT_LAYOUT.get_position_map();
+        let quartads = penalty::prepare_quartad_list(TEXT, &map);
+        let scorer = DefaultScorer::new(&quartads, TEXT.len(), &penalties);
+        let seeds = [1, 2, 3, 4, 5];
+
+        let in_turn: Vec<_> = seeds.iter().map(|&seed| chain(&scorer, seed)).collect();
+        let threaded = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..2)
+                .map(|t| {
+                    let scorer = &scorer;
+                    scope.spawn(move || {
+                        (t..seeds.len())
+                            .step_by(2)
+                            .map(|k| (k, chain(scorer, seeds[k])))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            let mut results: Vec<_> = workers
+                .into_iter()
+                .flat_map(|w| w.join().unwrap())
+                .collect();
+            results.sort_by_key(|&(k, _)| k);
+            results.into_iter().map(|(_, r)| r).collect::<Vec<_>>()
+        });
+        assert_eq!(threaded, in_turn);
+    }
+
+    #[test]
+    fn anneal_penalty_finds_what_simulate_finds() {
+        let penalties = penalty::init();
+        let map = layout::INIT_LAYOUT.get_position_map();
+        let quartads = penalty::prepare_quartad_list(TEXT, &map);
+        let scorer = DefaultScorer::new(&quartads, TEXT.len(), &penalties);
+        let params = annealing::AnnealingParams {
+            iterations: 2000,
+            ..annealing::AnnealingParams::default()
+        };
+        let mask = empty_keys_pinned(&QWERTY_LAYOUT);
+        let mut rng = StdRng::seed_from_u64(9);
+        let simulated = simulate(
+            &scorer,
+            &QWERTY_LAYOUT,
+            &mask,
+            1,
+            3,
+            &SwapSchedule::default(),
+            &params,
+            None,
+            &mut NoProgress,
+            &mut rng,
+        );
+        assert_eq!(chain(&scorer, 9).0, simulated);
+    }
+
+    #[test]
+    fn traced_chains_are_the_chains_anneal_penalty_runs() {
+        let penalties = penalty::init();
+        let map = layout::INIT_LAYOUT.get_position_map();
+        let quartads = penalty::prepare_quartad_list(TEXT, &map);
+        let scorer = DefaultScorer::new(&quartads, TEXT.len(), &penalties);
+        let params = annealing::AnnealingParams {
+            iterations: 2000,
+            ..annealing::AnnealingParams::default()
+        };
+        let mask = empty_keys_pinned(&QWERTY_LAYOUT);
+
+        let (best, trace) = optimize_traced_with(
+            &QWERTY_LAYOUT,
+            &mask,
+            DEFAULT_NUM_SWAPS,
+            &params,
+            9,
+            &scorer,
+        );
+        assert_eq!(best, chain(&scorer, 9).0);
+        assert_eq!(trace.len(), 2000);
+        let lowest = trace.iter().cloned().fold(f64::INFINITY, f64::min);
+        assert_eq!(scorer.score(&best), lowest);
+        assert_eq!(
+            optimize_traced_with(
+                &QWERTY_LAYOUT,
+                &mask,
+                DEFAULT_NUM_SWAPS,
+                &params,
+                9,
+                &scorer
+            )
+            .1,
+            trace
+        );
+    }
+
+    #[test]
+    fn pinned_characters_stay_where_they_started() {
+        let penalties = penalty::init();
+        let map = layout::INIT_LAYOUT.get_position_map();
+        let quartads = penalty::prepare_quartad_list(TEXT, &map);
+        let scorer = DefaultScorer::new(&quartads, TEXT.len(), &penalties);
+        let params = annealing::AnnealingParams {
+            iterations: 2000,
+            ..annealing::AnnealingParams::default()
+        };
+        let mut mask = empty_keys_pinned(&QWERTY_LAYOUT);
+        let positions = mask.pin_chars(&QWERTY_LAYOUT, "etaoin").unwrap();
+        assert_eq!(positions, vec![2, 4, 11, 8, 7, 27]);
+
+        for seed in 0..3 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let best = simulate(
+                &scorer,
+                &QWERTY_LAYOUT,
+                &mask,
+                1,
+                3,
+                &SwapSchedule::default(),
+                &params,
+                None,
+                &mut NoProgress,
+                &mut rng,
+            );
+            assert_ne!(best, QWERTY_LAYOUT);
+            for (&pos, c) in positions.iter().zip("etaoin".chars()) {
+                assert_eq!(best.char_at(pos, false), Ok(c), "seed {}", seed);
+            }
+        }
+
+        let mut unchanged = mask.clone();
+        assert_eq!(
+            unchanged.pin_chars(&QWERTY_LAYOUT, "zé"),
+            Err(layout::UnknownChar('é'))
+        );
+        assert_eq!(unchanged.swappable_positions(), mask.swappable_positions());
+    }
+
+    #[test]
+    fn a_resumed_run_ends_where_a_straight_run_does() {
+        let penalties = penalty::init();
+        let map = layout::INIT_LAYOUT.get_position_map();
+        let quartads = penalty::prepare_quartad_list(TEXT, &map);
+        let scorer = DefaultScorer::new(&quartads, TEXT.len(), &penalties);
+        let params = annealing::AnnealingParams {
+            iterations: 200,
+            ..annealing::AnnealingParams::default()
+        };
+        let mask = empty_keys_pinned(&QWERTY_LAYOUT);
+        let schedule = SwapSchedule::default();
+        let path = std::env::temp_dir().join(format!("keygen-resume-{}.json", std::process::id()));
+        let policy = CheckpointPolicy {
+            path: path.clone(),
+            every: 0,
+            interval: None,
+            corpus_hash: ::checkpoint::corpus_hash(TEXT),
+        };
+
+        let mut rng = ChaCha12Rng::seed_from_u64(5);
+        let straight = simulate(
+            &scorer,
+            &QWERTY_LAYOUT,
+            &mask,
+            3,
+            3,
+            &schedule,
+            &params,
+            None,
+            &mut NoProgress,
+            &mut rng,
+        );
+
+        // Stop after 100 iterations, as a killed run would, and save where
+        // it got to.
+        let mut rng = ChaCha12Rng::seed_from_u64(5);
+        let penalty = scorer.score(&QWERTY_LAYOUT);
+        let mut progress = Progress::start(&QWERTY_LAYOUT, penalty, 3, &params);
+        let stopped = run_chain(
+            &mut progress,
+            &scorer,
+            &mask,
+            3,
+            &schedule,
+            &params,
+            None,
+            &mut NoProgress,
+            &mut rng,
+            |progress, _, _| {
+                if progress.iteration == 100 {
+                    Err(())
+                } else {
+                    Ok(())
+                }
+            },
+        );
+        assert_eq!(stopped, Err(()));
+        progress
+            .checkpoint(policy.corpus_hash, &rng)
+            .save(&path)
+            .unwrap();
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.iteration, 100);
+        let mut rng = ChaCha12Rng::seed_from_u64(99);
+        let resumed = simulate_checkpointed(
+            &scorer,
+            &QWERTY_LAYOUT,
+            &mask,
+            3,
+            3,
+            &schedule,
+            &params,
+            None,
+            &mut NoProgress,
+            &mut rng,
+            Some(checkpoint),
+            &policy,
+        )
+        .unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(resumed, straight);
+    }
+
+    #[test]
+    fn polish_recovers_perturbed_colemak() {
+        let mask = empty_keys_pinned(&COLEMAK_LAYOUT);
+        for perturbation in &[vec![(13, 17)], vec![(13, 17), (2, 28)]] {
+            let mut perturbed = COLEMAK_LAYOUT.clone();
+            for &(i, j) in perturbation {
+                perturbed.swap_positions(i, j);
+            }
+            let (polished, penalty, swaps) = polish(&DistanceFromColemak, &perturbed, &mask);
+            assert_eq!(polished, COLEMAK_LAYOUT);
+            assert_eq!(penalty, 0.0);
+            assert_eq!(swaps, perturbation.len());
+        }
+    }
+
+    #[test]
+    fn no_swap_improves_a_polished_layout() {
+        let penalties = penalty::init();
+        let map = layout::INIT_LAYOUT.get_position_map();
+        let quartads = penalty::prepare_quartad_list(TEXT, &map);
+        let scorer = DefaultScorer::new(&quartads, TEXT.len(), &penalties);
+        let mask = empty_keys_pinned(&QWERTY_LAYOUT);
+
+        let (polished, penalty, _) = polish(&scorer, &QWERTY_LAYOUT, &mask);
+        assert!(penalty <= scorer.score(&QWERTY_LAYOUT));
+        assert_eq!(penalty, scorer.score(&polished));
+        let swappable = mask.swappable_positions();
+        for (n, &i) in swappable.iter().enumerate() {
+            for &j in &swappable[n + 1..] {
+                let mut swapped = polished.clone();
+                swapped.swap_positions(i, j);
+                assert!(
+                    scorer.score(&swapped) >= penalty,
+                    "swapping {} and {}",
+                    i,
+                    j
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn best_layouts_keeps_the_lowest_penalties_in_order() {
+        use layout::{DVORAK_LAYOUT, QGMLWY_LAYOUT, WORKMAN_LAYOUT};
+
+        let mut best = BestLayouts::new(3);
+        assert!(best.offer(&QWERTY_LAYOUT, 5.0));
+        assert!(best.offer(&COLEMAK_LAYOUT, 3.0));
+        assert!(best.offer(&DVORAK_LAYOUT, 4.0));
+        assert!(!best.offer(&WORKMAN_LAYOUT, 6.0));
+        assert!(best.offer(&QGMLWY_LAYOUT, 1.0));
+        assert_eq!(
+            best.to_vec(),
+            vec![
+                (QGMLWY_LAYOUT.clone(), 1.0),
+                (COLEMAK_LAYOUT.clone(), 3.0),
+                (DVORAK_LAYOUT.clone(), 4.0),
+            ]
+        );
+
+        // QWERTY was pushed out, so it can come back, and what it pushes out
+        // can come back in turn.
+        assert!(!best.offer(&QWERTY_LAYOUT, 4.5));
+        assert!(best.offer(&QWERTY_LAYOUT, 3.5));
+        assert!(best.offer(&DVORAK_LAYOUT, 0.5));
+        assert_eq!(
+            best.to_vec(),
+            vec![
+                (DVORAK_LAYOUT.clone(), 0.5),
+                (QGMLWY_LAYOUT.clone(), 1.0),
+                (COLEMAK_LAYOUT.clone(), 3.0),
+            ]
+        );
+        assert_eq!(best.best(), Some((&DVORAK_LAYOUT, 0.5)));
+    }
+
+    #[test]
+    fn best_layouts_collapses_duplicates() {
+        let mut best = BestLayouts::new(3);
+        assert!(best.offer(&QWERTY_LAYOUT, 2.0));
+        for i in 0..100 {
+            assert!(!best.offer(&QWERTY_LAYOUT.clone(), i as f64 / 100.0));
+        }
+        assert!(best.offer(&COLEMAK_LAYOUT, 1.0));
+        assert!(!best.offer(&COLEMAK_LAYOUT, 0.0));
+        assert_eq!(
+            best.to_vec(),
+            vec![(COLEMAK_LAYOUT.clone(), 1.0), (QWERTY_LAYOUT.clone(), 2.0)]
+        );
+        assert!(!BestLayouts::new(0).offer(&QWERTY_LAYOUT, 0.0));
+    }
+
+    struct Constant;
+
+    impl LayoutScorer for Constant {
+        fn score(&self, _layout: &layout::Layout) -> f64 {
+            1.0
+        }
+    }
+
+    // Scores every layout it is asked about lower than the last.
+    struct Improving(std::cell::Cell<f64>);
+
+    impl LayoutScorer for Improving {
+        fn score(&self, _layout: &layout::Layout) -> f64 {
+            self.0.set(self.0.get() - 1.0);
+            self.0.get()
+        }
+    }
+
+    fn run_with_patience<S: LayoutScorer>(scorer: &S, patience: usize) -> Progress {
+        let params = annealing::AnnealingParams {
+            iterations: 1000,
+            patience,
+            ..annealing::AnnealingParams::default()
+        };
+        let mask = empty_keys_pinned(&QWERTY_LAYOUT);
+        let penalty = scorer.score(&QWERTY_LAYOUT);
+        let mut progress = Progress::start(&QWERTY_LAYOUT, penalty, 1, &params);
+        let mut rng = ChaCha12Rng::seed_from_u64(49);
+        let done: Result<(), Infallible> = run_chain(
+            &mut progress,
+            scorer,
+            &mask,
+            3,
+            &SwapSchedule::default(),
+            &params,
+            None,
+            &mut NoProgress,
+            &mut rng,
+            |_, _, _| Ok(()),
+        );
+        done.unwrap();
+        progress
+    }
+
+    #[test]
+    fn a_constant_penalty_stops_after_exactly_patience_moves() {
+        for &patience in &[1, 50, 999] {
+            let progress = run_with_patience(&Constant, patience);
+            assert_eq!(progress.iteration, patience);
+            assert_eq!(
+                progress.stopped,
+                Some(annealing::EarlyStop::Patience(patience))
+            );
+        }
+        assert_eq!(run_with_patience(&Constant, 1000).stopped, None);
+    }
+
+    #[test]
+    fn a_steadily_improving_penalty_never_stops_early() {
+        let progress = run_with_patience(&Improving(std::cell::Cell::new(0.0)), 1);
+        assert_eq!(progress.iteration, 1000);
+        assert_eq!(progress.stopped, None);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Start(f64),
+        Accept(usize, f64),
+        Improvement(usize, f64, Box<layout::Layout>),
+        Stage(StageSummary),
+        Finish(RunSummary),
+    }
+
+    #[derive(Default)]
+    struct Recording(Vec<Event>);
+
+    impl ProgressSink for Recording {
+        fn on_start(&mut self, _layout: &layout::Layout, penalty: f64) {
+            self.0.push(Event::Start(penalty));
+        }
+
+        fn on_accept(&mut self, iteration: usize, penalty: f64) {
+            self.0.push(Event::Accept(iteration, penalty));
+        }
+
+        fn on_improvement(&mut self, iteration: usize, penalty: f64, layout: &layout::Layout) {
+            self.0.push(Event::Improvement(
+                iteration,
+                penalty,
+                Box::new(layout.clone()),
+            ));
+        }
+
+        fn on_temperature(&mut self, stage: &StageSummary) {
+            self.0.push(Event::Stage(*stage));
+        }
+
+        fn on_finish(&mut self, summary: &RunSummary) {
+            self.0.push(Event::Finish(summary.clone()));
+        }
+    }
+
+    fn record(scorer: &DefaultScorer, seed: u64) -> Vec<Event> {
+        let params = annealing::AnnealingParams {
+            iterations: 500,
+            ..annealing::AnnealingParams::default()
+        };
+        let mask = empty_keys_pinned(&QWERTY_LAYOUT);
+        let mut sink = Recording::default();
+        let mut rng = StdRng::seed_from_u64(seed);
+        simulate(
+            scorer,
+            &QWERTY_LAYOUT,
+            &mask,
+            3,
+            3,
+            &SwapSchedule::default(),
+            &params,
+            None,
+            &mut sink,
+            &mut rng,
+        );
+        sink.0
+    }
+
+    #[test]
+    fn a_seeded_run_reports_its_events_in_order() {
+        let penalties = penalty::init();
+        let map = layout::INIT_LAYOUT.get_position_map();
+        let quartads = penalty::prepare_quartad_list(TEXT, &map);
+        let scorer = DefaultScorer::new(&quartads, TEXT.len(), &penalties);
+        let events = record(&scorer, 48);
+        assert_eq!(events, record(&scorer, 48));
+
+        assert_eq!(
+            events.first(),
+            Some(&Event::Start(scorer.score(&QWERTY_LAYOUT)))
+        );
+        let summary = match events.last() {
+            Some(Event::Finish(summary)) => summary,
+            e => panic!("the last event is {:?}", e),
+        };
+        assert_eq!(summary.iterations, 500);
+        assert_eq!(summary.stopped, None);
+
+        // Every improvement follows the accept of the same move and beats the
+        // one before, and the last one is the best layout of the run.
+        let mut accepts = 0;
+        let mut last_accept = None;
+        let mut improvements = Vec::new();
+        let mut stages: Vec<StageSummary> = Vec::new();
+        for (n, event) in events.iter().enumerate().skip(1) {
+            match *event {
+                Event::Accept(i, penalty) => {
+                    assert!(last_accept.is_none_or(|(j, _)| j < i));
+                    last_accept = Some((i, penalty));
+                    accepts += 1;
+                }
+                Event::Improvement(i, penalty, ref layout) => {
+                    assert_eq!(events[n - 1], Event::Accept(i, penalty));
+                    assert!(improvements
+                        .last()
+                        .is_none_or(|&(_, best, _)| penalty < best));
+                    improvements.push((i, penalty, layout));
+                }
+                Event::Stage(stage) => {
+                    assert_eq!(stage.first, stages.last().map_or(1, |s| s.last + 1));
+                    assert_eq!(stage.tried, stage.last + 1 - stage.first);
+                    stages.push(stage);
+                }
+                Event::Start(_) => panic!("a second start"),
+                Event::Finish(_) => assert_eq!(n, events.len() - 1),
+            }
+        }
+        assert_eq!(stages.len(), 100);
+        assert_eq!(stages.last().map(|s| s.last), Some(500));
+        assert_eq!(stages.iter().map(|s| s.accepted).sum::<usize>(), accepts);
+        let &(_, best, layout) = improvements.last().unwrap();
+        assert_eq!(summary.best[0], (layout.as_ref().clone(), best));
+    }
+
+    // Scores a layout and the one with its first two keys swapped the same,
+    // and everything else as too bad to accept, so that the only move a run
+    // from either can make is to flip between the two.
+    struct TwoStates(layout::Layout, layout::Layout);
+
+    impl LayoutScorer for TwoStates {
+        fn score(&self, layout: &layout::Layout) -> f64 {
+            if *layout == self.0 || *layout == self.1 {
+                0.0
+            } else {
+                1000.0
+            }
+        }
+    }
+
+    fn flips(tabu: usize) -> usize {
+        let mut swappable = [false; 34];
+        for s in &mut swappable[..3] {
+            *s = true;
+        }
+        let mask = layout::LayoutShuffleMask::from_bools(&swappable).unwrap();
+        let mut flipped = QWERTY_LAYOUT.clone();
+        flipped.swap_positions(0, 1);
+        let scorer = TwoStates(QWERTY_LAYOUT.clone(), flipped);
+        let params = annealing::AnnealingParams {
+            iterations: 1000,
+            tabu,
+            tabu_tenure: 1000,
+            ..annealing::AnnealingParams::default()
+        };
+        let mut sink = Recording::default();
+        let mut rng = StdRng::seed_from_u64(44);
+        simulate(
+            &scorer,
+            &QWERTY_LAYOUT,
+            &mask,
+            1,
+            1,
+            &SwapSchedule::default(),
+            &params,
+            None,
+            &mut sink,
+            &mut rng,
+        );
+        sink.0
+            .iter()
+            .filter(|e| matches!(e, Event::Accept(..)))
+            .count()
+    }
+
+    #[test]
+    fn a_tabu_list_stops_a_run_flipping_between_two_layouts() {
+        // About a third of the moves are the flip.
+        let plain = flips(0);
+        assert!(plain > 250, "{} flips", plain);
+        assert_eq!(flips(10), 1);
+    }
+
+    #[test]
+    fn a_zero_temperature_run_never_accepts_a_worse_layout() {
+        let penalties = penalty::init();
+        let map = layout::INIT_LAYOUT.get_position_map();
+        let quartads = penalty::prepare_quartad_list(TEXT, &map);
+        let scorer = DefaultScorer::new(&quartads, TEXT.len(), &penalties);
+        let mask = empty_keys_pinned(&QWERTY_LAYOUT);
+        for &schedule in &[
+            annealing::CoolingSchedule::Linear,
+            annealing::CoolingSchedule::Exponential,
+            annealing::CoolingSchedule::Geometric,
+        ] {
+            let params = annealing::AnnealingParams {
+                start_temp: 0.0,
+                end_temp: 0.0,
+                iterations: 1000,
+                schedule,
+                ..annealing::AnnealingParams::default()
+            };
+            let mut sink = Recording::default();
+            let mut rng = StdRng::seed_from_u64(38);
+            simulate(
+                &scorer,
+                &QWERTY_LAYOUT,
+                &mask,
+                1,
+                3,
+                &SwapSchedule::default(),
+                &params,
+                None,
+                &mut sink,
+                &mut rng,
+            );
+
+            let mut accepted = scorer.score(&QWERTY_LAYOUT);
+            let mut accepts = 0;
+            for event in &sink.0 {
+                match *event {
+                    Event::Accept(_, penalty) => {
+                        assert!(
+                            penalty < accepted,
+                            "{:?}: {} after {}",
+                            schedule,
+                            penalty,
+                            accepted
+                        );
+                        accepted = penalty;
+                        accepts += 1;
+                    }
+                    Event::Stage(stage) => assert_eq!(stage.worse, 0),
+                    _ => (),
+                }
+            }
+            assert!(accepts > 0);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Exporting a Kanata `defsrc`/`deflayer` pair.
+
+extern crate keygen;
+
+use keygen::export::kanata;
+use keygen::layout::LayoutShuffleMask;
+use keygen::layout::COLEMAK_LAYOUT;
+use keygen::layout::QWERTY_LAYOUT;
+
+// The keys of the `(name` ... `)` block of `config`.
+fn block<'a>(config: &'a str, name: &str)
+-> Vec<&'a str>
+{
+	let start = config.find(&format!("({}", name)).unwrap();
+	let body = &config[start..];
+	let end = body.find(')').unwrap();
+	body[..end].split_whitespace().skip(name.split_whitespace().count()).collect()
+}
+
+#[test]
+fn colemak_has_exactly_one_mapping_per_key()
+{
+	let config = kanata::to_kanata(&COLEMAK_LAYOUT, &QWERTY_LAYOUT);
+	let sources = block(&config, "defsrc");
+	let targets = block(&config, "deflayer keygen");
+	assert_eq!(sources.len(), 34);
+	assert_eq!(targets.len(), 34);
+
+	let mapping: Vec<(&str, &str)> = sources.into_iter().zip(targets).collect();
+	assert_eq!(mapping[2], ("e", "f"));
+	assert_eq!(mapping[10], ("-", "_"));
+	assert_eq!(mapping[32], ("102d", "XX"));
+	assert_eq!(mapping[33], ("spc", "spc"));
+	assert!(kanata::unmapped_keys(&COLEMAK_LAYOUT, &QWERTY_LAYOUT).is_empty());
+}
+
+#[test]
+fn pinned_keys_are_transparent()
+{
+	let mut mask: LayoutShuffleMask = LayoutShuffleMask::all();
+	mask.pin(0);
+	mask.pin(33);
+	let config = kanata::to_kanata_masked(&COLEMAK_LAYOUT, &QWERTY_LAYOUT, &mask);
+	let targets = block(&config, "deflayer keygen");
+	assert_eq!(targets.len(), 34);
+	let transparent: Vec<usize> = (0..34).filter(|&i| targets[i] == "_").collect();
+	assert_eq!(transparent, vec![0, 33]);
+}
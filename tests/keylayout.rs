@@ -0,0 +1,14 @@
+//! Exporting macOS .keylayout documents, checked against a snapshot.
+
+extern crate keygen;
+
+use keygen::export::keylayout;
+use keygen::layout::COLEMAK_LAYOUT;
+
+static COLEMAK_KEYLAYOUT: &str = include_str!("data/colemak.keylayout");
+
+#[test]
+fn colemak_matches_snapshot()
+{
+	assert_eq!(keylayout::to_keylayout(&COLEMAK_LAYOUT, "Colemak"), COLEMAK_KEYLAYOUT);
+}
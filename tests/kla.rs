@@ -0,0 +1,33 @@
+//! Importing a keyboard-layout-analyzer export.
+
+extern crate keygen;
+
+use keygen::import::kla;
+use keygen::layout::Layout;
+use keygen::layout::COLEMAK_LAYOUT;
+
+static COLEMAK_JSON: &str = include_str!("data/colemak.kla.json");
+
+// The analyzer's Colemak, which has `[` where `COLEMAK_LAYOUT` has `-`.
+fn analyzer_colemak()
+-> Layout
+{
+	let mut layout = COLEMAK_LAYOUT.clone();
+	layout.set_char_at(10, false, '[').unwrap();
+	layout.set_char_at(10, true, '{').unwrap();
+	layout
+}
+
+#[test]
+fn colemak_imports_key_for_key()
+{
+	assert_eq!(Layout::from_kla_json(COLEMAK_JSON).unwrap(), analyzer_colemak());
+}
+
+#[test]
+fn keys_off_the_alpha_block_are_skipped()
+{
+	let import = kla::parse(COLEMAK_JSON).unwrap();
+	let skipped: String = import.skipped.into_iter().collect();
+	assert_eq!(skipped, "`1234567890-=]\\");
+}
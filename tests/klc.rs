@@ -0,0 +1,22 @@
+//! Exporting MSKLC source files, checked against snapshots.
+
+extern crate keygen;
+
+use keygen::export::klc;
+use keygen::layout::DVORAK_LAYOUT;
+use keygen::layout::QWERTY_LAYOUT;
+
+static QWERTY_KLC: &str = include_str!("data/qwerty.klc");
+static DVORAK_KLC: &str = include_str!("data/dvorak.klc");
+
+#[test]
+fn qwerty_matches_snapshot()
+{
+	assert_eq!(klc::to_klc(&QWERTY_LAYOUT, "QWERTY"), QWERTY_KLC);
+}
+
+#[test]
+fn dvorak_matches_snapshot()
+{
+	assert_eq!(klc::to_klc(&DVORAK_LAYOUT, "DVORAK"), DVORAK_KLC);
+}
@@ -0,0 +1,15 @@
+//! Exporting a QMK keymap.c fragment, checked against a golden file.
+
+extern crate keygen;
+
+use keygen::export::qmk;
+use keygen::layout::QWERTY_LAYOUT;
+
+static QWERTY_KEYMAP: &str = include_str!("data/qwerty.keymap.c");
+
+#[test]
+fn qwerty_matches_golden_file()
+{
+	assert_eq!(qmk::to_qmk_keymap(&QWERTY_LAYOUT, "_BASE"), QWERTY_KEYMAP);
+	assert!(qmk::unmapped_keys(&QWERTY_LAYOUT).is_empty());
+}
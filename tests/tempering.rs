@@ -0,0 +1,61 @@
+//! Parallel tempering from start to finish on a small corpus.
+
+extern crate keygen;
+extern crate rand;
+extern crate rand_chacha;
+extern crate rayon;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+
+use keygen::layout;
+use keygen::layout::Layout;
+use keygen::optimizer::tempering;
+use keygen::penalty;
+use keygen::scoring::DefaultScorer;
+use keygen::scoring::LayoutScorer;
+
+static TEXT: &str = "Parallel tempering runs several chains at once, each at its own \
+	temperature, and swaps layouts between neighbouring levels now and then, \
+	so that the coldest chain can climb out of the valley it froze in.";
+
+// Tempers QWERTY with 8 replicas, seeded with `seed`, on `threads` threads.
+fn temper(seed: u64, threads: usize)
+-> (Layout, f64, f64)
+{
+	let penalties = penalty::init();
+	let map = layout::INIT_LAYOUT.get_position_map();
+	let quartads = penalty::prepare_quartad_list(TEXT, &map);
+	let scorer = DefaultScorer::new(&quartads, TEXT.len(), &penalties);
+	let mut mask = layout::LAYOUT_MASK.clone();
+	for (pos, &c) in layout::QWERTY_LAYOUT.lower().keys().iter().enumerate() {
+		if c == '\0' {
+			mask.pin(pos);
+		}
+	}
+	let params = tempering::TemperingParams {
+		replicas: 8,
+		sweeps: 20,
+		moves_per_sweep: 50,
+		..tempering::TemperingParams::default()
+	};
+
+	let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+	let mut rng = ChaCha12Rng::seed_from_u64(seed);
+	let (best, penalty) = pool.install(|| {
+		tempering::temper(&scorer, &layout::QWERTY_LAYOUT, &mask, &params, &mut rng, |_, _| ())
+	});
+	(best, penalty, scorer.score(&layout::QWERTY_LAYOUT))
+}
+
+#[test]
+fn eight_replicas_find_the_same_layout_for_a_seed()
+{
+	let (best, penalty, initial) = temper(42, 1);
+	assert!(penalty <= initial);
+	for &threads in &[1, 2, 8] {
+		let (again, again_penalty, _) = temper(42, threads);
+		assert_eq!(again, best, "on {} threads", threads);
+		assert_eq!(again_penalty, penalty);
+	}
+}
@@ -0,0 +1,22 @@
+//! Exporting XKB symbols, checked against golden files.
+
+extern crate keygen;
+
+use keygen::export::xkb;
+use keygen::layout::COLEMAK_LAYOUT;
+use keygen::layout::QWERTY_LAYOUT;
+
+static QWERTY_SYMBOLS: &str = include_str!("data/qwerty.xkb");
+static COLEMAK_SYMBOLS: &str = include_str!("data/colemak.xkb");
+
+#[test]
+fn qwerty_matches_golden_file()
+{
+	assert_eq!(xkb::to_xkb_symbols(&QWERTY_LAYOUT, "qwerty"), QWERTY_SYMBOLS);
+}
+
+#[test]
+fn colemak_matches_snapshot()
+{
+	assert_eq!(xkb::to_xkb_symbols(&COLEMAK_LAYOUT, "colemak"), COLEMAK_SYMBOLS);
+}